@@ -146,6 +146,54 @@ impl<E: HashElement, A: Algorithm<E>> AppendMerkleTree<E, A> {
         }
     }
 
+    /// Restore a tree from the nodes returned by `layers` without hashing. The layer sizes
+    /// are checked, but the nodes are not, so the caller should check the root.
+    pub fn from_layers(layers: Vec<Vec<E>>, leaf_height: usize) -> Result<Self> {
+        for (height, pair) in layers.windows(2).enumerate() {
+            if pair[1].len() != pair[0].len().div_ceil(2) {
+                bail!(
+                    "invalid layer size: height={} size={} lower_size={}",
+                    height + 1,
+                    pair[1].len(),
+                    pair[0].len()
+                );
+            }
+        }
+        if layers.last().is_some_and(|layer| layer.len() > 1) {
+            bail!("root layer missing: height={}", layers.len());
+        }
+        let mut merkle = Self {
+            node_manager: NodeManager::new_dummy(),
+            delta_nodes_map: BTreeMap::new(),
+            root_to_tx_seq_map: HashMap::new(),
+            min_depth: None,
+            leaf_height,
+            _a: Default::default(),
+        };
+        merkle.node_manager.start_transaction();
+        if layers.is_empty() {
+            merkle.node_manager.add_layer();
+        }
+        for (height, layer) in layers.iter().enumerate() {
+            merkle.node_manager.add_layer();
+            merkle.node_manager.append_nodes(height, layer);
+        }
+        merkle.node_manager.commit();
+        Ok(merkle)
+    }
+
+    /// Return all the nodes from the leaves to the root, e.g. to persist the tree for
+    /// `from_layers`.
+    pub fn layers(&self) -> Vec<Vec<E>> {
+        (0..self.height())
+            .map(|height| {
+                self.node_manager
+                    .get_nodes(height, 0, self.layer_len(height))
+                    .collect()
+            })
+            .collect()
+    }
+
     pub fn append(&mut self, new_leaf: E) {
         let start_time = Instant::now();
         if new_leaf == E::null() {
@@ -891,6 +939,28 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_from_layers() {
+        let mut data = vec![H256::zero()];
+        for _ in 0..300 {
+            data.push(H256::random());
+        }
+        let merkle = AppendMerkleTree::<H256, Sha3Algorithm>::new(data.clone(), 0, None);
+        let restored =
+            AppendMerkleTree::<H256, Sha3Algorithm>::from_layers(merkle.layers(), 0).unwrap();
+        assert_eq!(restored.root(), merkle.root());
+        for i in [0, 1, 150, 300] {
+            assert_eq!(restored.gen_proof(i).unwrap(), merkle.gen_proof(i).unwrap());
+        }
+
+        let mut layers = merkle.layers();
+        layers[1].pop();
+        assert!(AppendMerkleTree::<H256, Sha3Algorithm>::from_layers(layers, 0).is_err());
+        let mut layers = merkle.layers();
+        layers.pop();
+        assert!(AppendMerkleTree::<H256, Sha3Algorithm>::from_layers(layers, 0).is_err());
+    }
+
     fn verify(data: &[H256], merkle: &mut AppendMerkleTree<H256, Sha3Algorithm>) {
         for (i, item) in data.iter().enumerate() {
             let proof = merkle.gen_proof(i + 1).unwrap();
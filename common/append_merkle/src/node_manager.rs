@@ -7,7 +7,8 @@ use std::sync::Arc;
 use tracing::error;
 
 pub struct NodeManager<E: HashElement> {
-    cache: LruCache<(usize, usize), E>,
+    /// `None` if the cache capacity is 0, and all nodes are read from `db` directly.
+    cache: Option<LruCache<(usize, usize), E>>,
     layer_size: Vec<usize>,
     db: Arc<dyn NodeDatabase<E>>,
     db_tx: Option<Box<dyn NodeTransaction<E>>>,
@@ -22,16 +23,22 @@ impl<E: HashElement> NodeManager<E> {
             layer += 1;
         }
         Ok(Self {
-            cache: LruCache::new(NonZeroUsize::new(capacity).expect("capacity should be non-zero")),
+            cache: NonZeroUsize::new(capacity).map(LruCache::new),
             layer_size,
             db,
             db_tx: None,
         })
     }
 
+    /// The approximate memory usage of a cached node: the node with its key, the list links of
+    /// the LRU entry and the hash map slot pointing to it.
+    pub fn cache_entry_size() -> usize {
+        std::mem::size_of::<((usize, usize), E)>() + 4 * std::mem::size_of::<usize>()
+    }
+
     pub fn new_dummy() -> Self {
         Self {
-            cache: LruCache::unbounded(),
+            cache: Some(LruCache::unbounded()),
             layer_size: vec![],
             db: Arc::new(EmptyNodeDatabase {}),
             db_tx: None,
@@ -47,7 +54,9 @@ impl<E: HashElement> NodeManager<E> {
         let mut pos = self.layer_size[layer];
         let mut saved_nodes = Vec::with_capacity(nodes.len());
         for node in nodes {
-            self.cache.put((layer, pos), node.clone());
+            if let Some(cache) = self.cache.as_mut() {
+                cache.put((layer, pos), node.clone());
+            }
             saved_nodes.push((layer, pos, node));
             pos += 1;
         }
@@ -56,7 +65,11 @@ impl<E: HashElement> NodeManager<E> {
     }
 
     pub fn get_node(&self, layer: usize, pos: usize) -> Option<E> {
-        match self.cache.peek(&(layer, pos)) {
+        match self
+            .cache
+            .as_ref()
+            .and_then(|cache| cache.peek(&(layer, pos)))
+        {
             Some(node) => Some(node.clone()),
             None => self.db.get_node(layer, pos).unwrap_or_else(|e| {
                 error!("Failed to get node: {}", e);
@@ -76,9 +89,15 @@ impl<E: HashElement> NodeManager<E> {
 
     pub fn add_node(&mut self, layer: usize, pos: usize, node: E) {
         // No need to insert if the value is unchanged.
-        if self.cache.get(&(layer, pos)) != Some(&node) {
+        let unchanged = self
+            .cache
+            .as_mut()
+            .map_or(false, |cache| cache.get(&(layer, pos)) == Some(&node));
+        if !unchanged {
             self.db_tx().save_node(layer, pos, &node);
-            self.cache.put((layer, pos), node);
+            if let Some(cache) = self.cache.as_mut() {
+                cache.put((layer, pos), node);
+            }
         }
     }
 
@@ -99,7 +118,9 @@ impl<E: HashElement> NodeManager<E> {
     pub fn truncate_nodes(&mut self, layer: usize, pos_end: usize) {
         let mut removed_nodes = Vec::new();
         for pos in pos_end..self.layer_size[layer] {
-            self.cache.pop(&(layer, pos));
+            if let Some(cache) = self.cache.as_mut() {
+                cache.pop(&(layer, pos));
+            }
             removed_nodes.push((layer, pos));
         }
         self.db_tx().remove_node_list(&removed_nodes);
@@ -6,7 +6,7 @@ use tokio::time::{sleep, Duration, Instant};
 
 use contract_interface::{EpochRangeWithContextDigest, ZgsFlow};
 use storage::{
-    error::Result,
+    error::{Error as StorageError, Result},
     log_store::{SealAnswer, SealTask},
 };
 use storage_async::Store;
@@ -77,6 +77,11 @@ impl Sealer {
                         Ok(false) => {db_checker_throttle.as_mut().reset(Instant::now() + Duration::from_secs(DB_QUERY_PERIOD_ON_NO_TASK));}
                         Err(err) => {
                             warn!("Seal iteration failed {:?}", err);
+                            if let Some(StorageError::SealStateDesync { .. }) = err.downcast_ref() {
+                                if let Err(err) = self.store.resync_seal_set().await {
+                                    warn!("Resync seal set failed {:?}", err);
+                                }
+                            }
                             db_checker_throttle.as_mut().reset(Instant::now() + Duration::from_secs(DB_QUERY_PERIOD_ON_ERROR));
                         }
                     }
@@ -155,7 +160,17 @@ impl Sealer {
     }
 
     async fn submit_answer(&self, answers: Vec<SealAnswer>) -> Result<()> {
-        self.store.submit_seal_result(answers).await
+        let submitted = answers.len();
+        let stale = self.store.submit_seal_result(answers).await?;
+        if !stale.is_empty() {
+            info!(
+                target: "seal",
+                "Stale seal answers dropped: stale={} submitted={}",
+                stale.len(),
+                submitted
+            );
+        }
+        Ok(())
     }
 
     async fn seal_iteration(&mut self) -> Result<bool> {
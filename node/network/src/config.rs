@@ -11,7 +11,7 @@ use libp2p::gossipsub::{
 use libp2p::Multiaddr;
 use serde_derive::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use shared_types::NetworkIdentity;
+use shared_types::{NetworkIdentity, ShardConfig};
 use std::path::PathBuf;
 use std::time::Duration;
 
@@ -136,6 +136,10 @@ pub struct Config {
 
     /// Whether to allow find chunks from peers.
     pub find_chunks_enabled: bool,
+
+    /// The shard of the data stored locally, advertised in ENR.
+    #[serde(skip)]
+    pub shard_config: ShardConfig,
 }
 
 impl Default for Config {
@@ -218,6 +222,7 @@ impl Default for Config {
             peer_manager: Default::default(),
             disable_enr_network_id: false,
             find_chunks_enabled: false,
+            shard_config: Default::default(),
         }
     }
 }
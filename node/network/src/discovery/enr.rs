@@ -3,7 +3,7 @@
 pub use discv5::enr::{CombinedKey, EnrBuilder};
 use ssz::Encode;
 
-use super::enr_ext::{CombinedKeyExt, ENR_CONTENT_KEY_NETWORK_ID};
+use super::enr_ext::{CombinedKeyExt, ENR_CONTENT_KEY_NETWORK_ID, ENR_CONTENT_KEY_SHARD_CONFIG};
 use super::{EnrExt, ENR_FILENAME};
 use crate::types::Enr;
 use crate::NetworkConfig;
@@ -35,6 +35,7 @@ pub fn use_or_load_enr(
                         if local_enr.node_id() == disk_enr.node_id() {
                             if compare_enr(local_enr, &disk_enr)
                                 && is_disk_enr_network_id_unchanged(&disk_enr, config)
+                                && is_disk_enr_shard_config_unchanged(&disk_enr, config)
                             {
                                 debug!(file = ?enr_f, "ENR loaded from disk");
                                 // the stored ENR has the same configuration, use it
@@ -104,6 +105,12 @@ pub fn create_enr_builder_from_config<T: EnrKey>(
             &config.network_id.as_ssz_bytes(),
         );
     }
+
+    // add the locally stored shard so that peers can be discovered by shard
+    builder.add_value(
+        ENR_CONTENT_KEY_SHARD_CONFIG,
+        &config.shard_config.as_ssz_bytes(),
+    );
     builder
 }
 
@@ -135,6 +142,10 @@ fn is_disk_enr_network_id_unchanged(disk_enr: &Enr, config: &NetworkConfig) -> b
     }
 }
 
+fn is_disk_enr_shard_config_unchanged(disk_enr: &Enr, config: &NetworkConfig) -> bool {
+    matches!(disk_enr.shard_config(), Some(Ok(shard)) if shard == config.shard_config)
+}
+
 /// Loads enr from the given directory
 pub fn load_enr_from_disk(dir: &Path) -> Result<Enr, String> {
     let enr_f = dir.join(ENR_FILENAME);
@@ -2,11 +2,12 @@
 use crate::{Enr, Multiaddr, PeerId};
 use discv5::enr::{CombinedKey, CombinedPublicKey};
 use libp2p::core::{identity::Keypair, identity::PublicKey, multiaddr::Protocol};
-use shared_types::NetworkIdentity;
+use shared_types::{NetworkIdentity, ShardConfig};
 use ssz::Decode;
 use tiny_keccak::{Hasher, Keccak};
 
 pub(crate) const ENR_CONTENT_KEY_NETWORK_ID: &'static str = "network_identity";
+pub(crate) const ENR_CONTENT_KEY_SHARD_CONFIG: &str = "shard_config";
 
 /// Extend ENR for libp2p types.
 pub trait EnrExt {
@@ -31,6 +32,9 @@ pub trait EnrExt {
 
     /// Returns network identity in content.
     fn network_identity(&self) -> Option<Result<NetworkIdentity, ssz::DecodeError>>;
+
+    /// Returns shard config in content.
+    fn shard_config(&self) -> Option<Result<ShardConfig, ssz::DecodeError>>;
 }
 
 /// Extend ENR CombinedPublicKey for libp2p types.
@@ -202,6 +206,12 @@ impl EnrExt for Enr {
         let value = self.get(ENR_CONTENT_KEY_NETWORK_ID)?;
         Some(NetworkIdentity::from_ssz_bytes(value))
     }
+
+    /// Returns shard config in content.
+    fn shard_config(&self) -> Option<Result<ShardConfig, ssz::DecodeError>> {
+        let value = self.get(ENR_CONTENT_KEY_SHARD_CONFIG)?;
+        Some(ShardConfig::from_ssz_bytes(value))
+    }
 }
 
 impl CombinedKeyPublicExt for CombinedPublicKey {
@@ -8,7 +8,7 @@ pub mod enr_ext;
 
 use crate::metrics;
 use crate::{error, Enr, NetworkConfig, NetworkGlobals};
-use discv5::{enr::NodeId, Discv5, Discv5Event};
+use discv5::{enr::NodeId, DiscoveryStats, Discv5, Discv5Event};
 pub use enr::{
     build_enr, create_enr_builder_from_config, load_enr_from_disk, use_or_load_enr, CombinedKey,
 };
@@ -25,6 +25,8 @@ pub use libp2p::{
     },
 };
 use lru::LruCache;
+use shared_types::{NetworkIdentity, ShardConfig};
+use ssz::Encode;
 use std::{
     collections::HashMap,
     net::{IpAddr, SocketAddr},
@@ -32,7 +34,7 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    time::Instant,
+    time::{Duration, Instant},
 };
 use tokio::sync::mpsc;
 
@@ -43,6 +45,8 @@ pub const ENR_FILENAME: &str = "enr.dat";
 /// We could reduce this constant to speed up queries however at the cost of security. It will
 /// make it easier to peers to eclipse this node. Kademlia suggests a value of 16.
 pub const FIND_NODE_QUERY_CLOSEST_PEERS: usize = 16;
+/// The maximum time to wait for a targeted query to locate a specific node.
+pub const FIND_NODE_ENR_TIMEOUT: Duration = Duration::from_secs(30);
 /// The threshold for updating `min_ttl` on a connected peer.
 
 /// The events emitted by polling discovery.
@@ -316,6 +320,65 @@ impl Discovery {
         self.discv5.table_entries_enr()
     }
 
+    /// Returns statistics about the peers currently held in the DHT.
+    pub fn discovery_stats(&self) -> DiscoveryStats {
+        self.discv5.discovery_stats()
+    }
+
+    /// Searches the DHT for the latest ENR of the given node.
+    ///
+    /// Unlike `enr_of_peer`, this always issues a `FindNode` query, so that a peer whose address
+    /// has changed can be re-located. Returns `Ok(None)` if the node was not found before the
+    /// query timed out.
+    pub async fn find_node_enr(&self, node_id: NodeId) -> error::Result<Option<Enr>> {
+        let query = self.discv5.find_node(node_id);
+        find_node_enr_with_timeout(node_id, FIND_NODE_ENR_TIMEOUT, query).await
+    }
+
+    /// Searches the DHT for up to `n` peers on our network that store all the data of the `want`
+    /// shard.
+    ///
+    /// discv5 has no wire-level predicate, so the shard predicate is evaluated locally on every
+    /// ENR learnt during the `FindNode` walk. Only matching peers count towards `n`, which lets the
+    /// walk stop early instead of filtering a generic discovery result afterwards.
+    ///
+    /// If the walk returns less than `n` peers, e.g. because the queried peers only returned ENRs
+    /// without a shard config, the ENRs of the routing table and the local cache are filtered
+    /// with the same predicate to fill up the result.
+    pub async fn find_shard_peers(&self, want: ShardConfig, n: usize) -> error::Result<Vec<Enr>> {
+        let local_network_id = self.network_globals.network_id();
+        let network_id = local_network_id.clone();
+        let predicate = move |enr: &Enr| -> bool { is_shard_peer(enr, &network_id, &want) };
+
+        let mut enrs = self
+            .discv5
+            .find_node_predicate(NodeId::random(), Box::new(predicate), n)
+            .await
+            .map_err(|e| format!("Discovery query failed: {}", e))?;
+        enrs.truncate(n);
+
+        if enrs.len() < n {
+            let known = self
+                .discv5
+                .table_entries_enr()
+                .into_iter()
+                .chain(self.cached_enrs.iter().map(|(_, enr)| enr.clone()));
+            for enr in known {
+                if enrs.len() >= n {
+                    break;
+                }
+
+                if is_shard_peer(&enr, &local_network_id, &want)
+                    && !enrs.iter().any(|e| e.node_id() == enr.node_id())
+                {
+                    enrs.push(enr);
+                }
+            }
+        }
+
+        Ok(enrs)
+    }
+
     /// Returns the ENR of a known peer if it exists.
     pub fn enr_of_peer(&mut self, peer_id: &PeerId) -> Option<Enr> {
         // first search the local cache
@@ -378,6 +441,22 @@ impl Discovery {
         Ok(())
     }
 
+    /// Updates the shard config advertised in the local ENR.
+    pub fn update_enr_shard_config(&mut self, shard_config: ShardConfig) -> Result<(), String> {
+        self.discv5
+            .enr_insert(
+                enr_ext::ENR_CONTENT_KEY_SHARD_CONFIG,
+                &shard_config.as_ssz_bytes(),
+            )
+            .map_err(|e| format!("{:?}", e))?;
+
+        // replace the global version
+        *self.network_globals.local_enr.write() = self.discv5.local_enr();
+        // persist modified enr to disk
+        enr::save_enr_to_disk(Path::new(&self.enr_dir), &self.local_enr());
+        Ok(())
+    }
+
     // Bans a peer and it's associated seen IP addresses.
     pub fn ban_peer(&mut self, peer_id: &PeerId, ip_addresses: Vec<IpAddr>) {
         // first try and convert the peer_id to a node_id.
@@ -490,6 +569,41 @@ impl Discovery {
     }
 }
 
+/// Waits for a targeted `FindNode` query and returns the ENR of `node_id` if it was found within
+/// `timeout`.
+async fn find_node_enr_with_timeout<F, E>(
+    node_id: NodeId,
+    timeout: Duration,
+    query: F,
+) -> error::Result<Option<Enr>>
+where
+    F: Future<Output = Result<Vec<Enr>, E>>,
+    E: std::fmt::Display,
+{
+    match tokio::time::timeout(timeout, query).await {
+        Ok(Ok(enrs)) => Ok(enrs.into_iter().find(|enr| enr.node_id() == node_id)),
+        Ok(Err(e)) => Err(format!("Discovery query failed: {}", e).into()),
+        Err(_) => {
+            debug!(%node_id, "Targeted discovery query timed out");
+            Ok(None)
+        }
+    }
+}
+
+/// Returns whether the peer of `enr` is on the `network_id` network and stores every segment of
+/// the `want` shard.
+fn is_shard_peer(enr: &Enr, network_id: &NetworkIdentity, want: &ShardConfig) -> bool {
+    matches!(enr.network_identity(), Some(Ok(id)) if &id == network_id)
+        && matches!(enr.shard_config(), Some(Ok(shard)) if shard_covers(&shard, want))
+}
+
+/// Returns whether a peer with the `peer` shard config stores every segment of the `want` shard.
+fn shard_covers(peer: &ShardConfig, want: &ShardConfig) -> bool {
+    peer.num_shard != 0
+        && want.num_shard % peer.num_shard == 0
+        && want.shard_id % peer.num_shard == peer.shard_id
+}
+
 /* NetworkBehaviour Implementation */
 
 impl NetworkBehaviour for Discovery {
@@ -627,6 +741,113 @@ impl NetworkBehaviour for Discovery {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enr::EnrBuilder;
+    use enr_ext::{ENR_CONTENT_KEY_NETWORK_ID, ENR_CONTENT_KEY_SHARD_CONFIG};
+
+    fn make_enr(network_id: Option<&NetworkIdentity>, shard: Option<ShardConfig>) -> Enr {
+        let mut builder = EnrBuilder::new("v4");
+        if let Some(network_id) = network_id {
+            builder.add_value(ENR_CONTENT_KEY_NETWORK_ID, &network_id.as_ssz_bytes());
+        }
+        if let Some(shard) = shard {
+            builder.add_value(ENR_CONTENT_KEY_SHARD_CONFIG, &shard.as_ssz_bytes());
+        }
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let enr_key: CombinedKey = CombinedKey::from_libp2p(&keypair).unwrap();
+        builder.build(&enr_key).unwrap()
+    }
+
+    fn shard(num_shard: usize, shard_id: usize) -> ShardConfig {
+        ShardConfig {
+            num_shard,
+            shard_id,
+        }
+    }
+
+    #[test]
+    fn test_shard_covers() {
+        assert!(shard_covers(&shard(1, 0), &shard(4, 3)));
+        assert!(shard_covers(&shard(2, 1), &shard(4, 3)));
+        assert!(shard_covers(&shard(4, 3), &shard(4, 3)));
+        assert!(!shard_covers(&shard(2, 0), &shard(4, 3)));
+        assert!(!shard_covers(&shard(8, 3), &shard(4, 3)));
+        assert!(!shard_covers(&shard(0, 0), &shard(4, 3)));
+    }
+
+    #[test]
+    fn test_is_shard_peer() {
+        let network_id = NetworkIdentity::default();
+        let want = shard(4, 1);
+
+        assert!(is_shard_peer(
+            &make_enr(Some(&network_id), Some(shard(2, 1))),
+            &network_id,
+            &want
+        ));
+        assert!(!is_shard_peer(
+            &make_enr(Some(&network_id), Some(shard(2, 0))),
+            &network_id,
+            &want
+        ));
+        // peers that do not advertise a shard config
+        assert!(!is_shard_peer(
+            &make_enr(Some(&network_id), None),
+            &network_id,
+            &want
+        ));
+        // peers of another network
+        let other_network_id = NetworkIdentity {
+            chain_id: network_id.chain_id + 1,
+            ..network_id.clone()
+        };
+        assert!(!is_shard_peer(
+            &make_enr(Some(&other_network_id), Some(shard(1, 0))),
+            &network_id,
+            &want
+        ));
+        assert!(!is_shard_peer(
+            &make_enr(None, Some(shard(1, 0))),
+            &network_id,
+            &want
+        ));
+    }
+
+    #[test]
+    fn test_build_enr_with_shard_config() {
+        let config = NetworkConfig {
+            shard_config: shard(4, 2),
+            ..Default::default()
+        };
+        let keypair = libp2p::identity::Keypair::generate_secp256k1();
+        let enr_key: CombinedKey = CombinedKey::from_libp2p(&keypair).unwrap();
+        let enr = build_enr(&enr_key, &config).unwrap();
+
+        assert_eq!(enr.shard_config(), Some(Ok(shard(4, 2))));
+        assert!(is_shard_peer(&enr, &config.network_id, &shard(8, 6)));
+    }
+
+    #[tokio::test]
+    async fn test_find_node_enr_timeout() {
+        let enr = make_enr(None, None);
+        let node_id = enr.node_id();
+
+        let query = future::pending::<Result<Vec<Enr>, String>>();
+        let found = find_node_enr_with_timeout(node_id, Duration::from_millis(10), query).await;
+        assert_eq!(found.unwrap(), None);
+
+        let query = future::ready(Ok::<_, String>(vec![make_enr(None, None), enr.clone()]));
+        let found = find_node_enr_with_timeout(node_id, Duration::from_millis(10), query).await;
+        assert_eq!(found.unwrap(), Some(enr));
+
+        let query = future::ready(Err::<Vec<Enr>, _>("query failed".to_string()));
+        let found = find_node_enr_with_timeout(node_id, Duration::from_millis(10), query).await;
+        assert!(found.is_err());
+    }
+}
+
 // #[cfg(test)]
 // mod tests {
 //     use super::*;
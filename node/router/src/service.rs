@@ -420,6 +420,15 @@ impl RouterService {
                     .send_to_chunk_pool(ChunkPoolMessage::ChangeShardConfig(shard_config));
 
                 let shard_config = shared_types::ShardConfig::from(shard_config);
+                if let Err(e) = self
+                    .libp2p
+                    .swarm
+                    .behaviour_mut()
+                    .discovery_mut()
+                    .update_enr_shard_config(shard_config)
+                {
+                    warn!(error = %e, "Failed to update ENR");
+                }
                 self.libp2p_event_handler
                     .publish(PubsubMessage::AnnounceShardConfig(shard_config.into()));
             }
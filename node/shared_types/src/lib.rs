@@ -213,6 +213,95 @@ impl ChunkArray {
             start_index: start,
         })
     }
+
+    /// Check that the data are whole chunks and the chunk indices from `start_index` do not
+    /// overflow, so `chunk_at` and `sub_array` stay within the data. `start_index` is a chunk
+    /// index, so it is always chunk-aligned.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.data.len() % CHUNK_SIZE != 0 {
+            bail!(
+                "invalid chunk array: data length {} is not a multiple of {}",
+                self.data.len(),
+                CHUNK_SIZE
+            );
+        }
+        let num_chunks = (self.data.len() / CHUNK_SIZE) as u64;
+        if self.start_index.checked_add(num_chunks).is_none() {
+            bail!(
+                "invalid chunk array: chunk index overflows, start_index={} chunks={}",
+                self.start_index,
+                num_chunks
+            );
+        }
+        Ok(())
+    }
+
+    /// Encode the array as `version | varint(start_index) | varint(chunk_count) | data`.
+    /// Unlike SSZ, there is no per-chunk framing, so this is used for bulk data transfer.
+    pub fn encode_compact(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(1 + 2 * MAX_VARINT_LEN + self.data.len());
+        buf.push(CHUNK_ARRAY_COMPACT_VERSION);
+        write_varint(&mut buf, self.start_index);
+        write_varint(&mut buf, (self.data.len() / CHUNK_SIZE) as u64);
+        buf.extend_from_slice(&self.data);
+        buf
+    }
+
+    pub fn decode_compact(bytes: &[u8]) -> anyhow::Result<ChunkArray> {
+        let (&version, mut rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("empty compact chunk array"))?;
+        if version != CHUNK_ARRAY_COMPACT_VERSION {
+            bail!("unsupported compact chunk array version {}", version);
+        }
+        let start_index = read_varint(&mut rest)?;
+        let num_chunks = read_varint(&mut rest)?;
+        let data_len = (num_chunks as usize)
+            .checked_mul(CHUNK_SIZE)
+            .ok_or_else(|| anyhow!("compact chunk array too large: chunks={}", num_chunks))?;
+        if rest.len() != data_len {
+            bail!(
+                "compact chunk array data length mismatch: expected={} get={}",
+                data_len,
+                rest.len()
+            );
+        }
+        Ok(ChunkArray {
+            data: rest.to_vec(),
+            start_index,
+        })
+    }
+}
+
+const CHUNK_ARRAY_COMPACT_VERSION: u8 = 1;
+/// A LEB128-encoded `u64` takes at most 10 bytes.
+const MAX_VARINT_LEN: usize = 10;
+
+fn write_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(bytes: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut value = 0u64;
+    for i in 0..MAX_VARINT_LEN {
+        let (&byte, rest) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("truncated varint"))?;
+        *bytes = rest;
+        let low_bits = (byte & 0x7f) as u64;
+        if i == MAX_VARINT_LEN - 1 && low_bits > 1 {
+            bail!("varint overflow");
+        }
+        value |= low_bits << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    bail!("varint overflow")
 }
 
 impl std::fmt::Display for ChunkArray {
@@ -455,4 +544,66 @@ mod tests {
             TxSeqOrRoot::Root(v) if v == hash,
         ));
     }
+
+    #[test]
+    fn test_chunk_array_compact_round_trip() {
+        for (start_index, num_chunks) in [(0, 0), (0, 1), (127, 3), (128, 1024), (u64::MAX, 2)] {
+            let data = (0..num_chunks * CHUNK_SIZE)
+                .map(|i| (i % 251) as u8)
+                .collect();
+            let chunks = ChunkArray { data, start_index };
+            let encoded = chunks.encode_compact();
+            assert!(encoded.len() <= chunks.as_ssz_bytes().len() + 2 * MAX_VARINT_LEN);
+            assert_eq!(ChunkArray::decode_compact(&encoded).unwrap(), chunks);
+        }
+    }
+
+    #[test]
+    fn test_chunk_array_compact_invalid() {
+        let chunks = ChunkArray {
+            data: vec![7; 2 * CHUNK_SIZE],
+            start_index: 300,
+        };
+        let encoded = chunks.encode_compact();
+
+        assert!(ChunkArray::decode_compact(&[]).is_err());
+        // Unknown version.
+        let mut bad_version = encoded.clone();
+        bad_version[0] = CHUNK_ARRAY_COMPACT_VERSION + 1;
+        assert!(ChunkArray::decode_compact(&bad_version).is_err());
+        // Truncated data.
+        assert!(ChunkArray::decode_compact(&encoded[..encoded.len() - 1]).is_err());
+        // Trailing bytes.
+        let mut trailing = encoded.clone();
+        trailing.push(0);
+        assert!(ChunkArray::decode_compact(&trailing).is_err());
+        // Truncated varint.
+        assert!(ChunkArray::decode_compact(&[CHUNK_ARRAY_COMPACT_VERSION, 0x80]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_array_validate() {
+        let chunks = ChunkArray {
+            data: vec![1; 3 * CHUNK_SIZE],
+            start_index: 10,
+        };
+        assert!(chunks.validate().is_ok());
+        assert!(ChunkArray {
+            data: vec![],
+            start_index: u64::MAX,
+        }
+        .validate()
+        .is_ok());
+
+        let partial = ChunkArray {
+            data: vec![1; 3 * CHUNK_SIZE - 1],
+            start_index: 10,
+        };
+        assert!(partial.validate().is_err());
+        let overflow = ChunkArray {
+            data: vec![1; 2 * CHUNK_SIZE],
+            start_index: u64::MAX,
+        };
+        assert!(overflow.validate().is_err());
+    }
 }
@@ -118,6 +118,8 @@ impl ClientBuilder {
         self.store = Some(store.clone());
 
         if let Some(ctx) = self.runtime_context.as_ref() {
+            store.start_seal_set_compaction(&ctx.executor);
+            store.start_metrics_export(&ctx.executor);
             self.async_store = Some(Arc::new(storage_async::Store::new(
                 store,
                 ctx.executor.clone(),
@@ -350,6 +352,7 @@ impl ClientBuilder {
 
         Ok(Client {
             network_globals: self.network.as_ref().map(|network| network.globals.clone()),
+            store: self.store,
         })
     }
 }
@@ -5,6 +5,7 @@ mod environment;
 
 use network::{Enr, Multiaddr, NetworkGlobals};
 use std::sync::Arc;
+use storage::log_store::Store;
 
 pub use builder::ClientBuilder;
 pub use environment::{EnvironmentBuilder, RuntimeContext};
@@ -14,6 +15,7 @@ pub use environment::{EnvironmentBuilder, RuntimeContext};
 /// Holds references to running services, cleanly shutting them down when dropped.
 pub struct Client {
     network_globals: Option<Arc<NetworkGlobals>>,
+    store: Option<Arc<dyn Store>>,
 }
 
 impl Client {
@@ -31,4 +33,16 @@ impl Client {
     pub fn enr(&self) -> Option<Enr> {
         self.network_globals.as_ref().map(|n| n.local_enr())
     }
+
+    /// Writes the data still buffered in memory by the store to disk.
+    ///
+    /// This should be called on shutdown after all the services have stopped.
+    pub fn flush_store(&self) {
+        if let Some(store) = &self.store {
+            info!("Flushing the store on shutdown");
+            if let Err(e) = store.flush() {
+                error!("Flush store on shutdown failed: {:?}", e);
+            }
+        }
+    }
 }
@@ -12,7 +12,9 @@ use std::net::IpAddr;
 use std::sync::Arc;
 use std::time::Duration;
 use storage::config::ShardConfig;
+use storage::log_store::flow_store::MerkleNodeCacheCapacity;
 use storage::log_store::log_manager::LogConfig;
+use storage::log_store::metrics_export::MetricsExport;
 use storage::StorageConfig;
 
 impl ZgsConfig {
@@ -108,13 +110,67 @@ impl ZgsConfig {
         network_config.peer_manager = self.network_peer_manager.clone();
         network_config.disable_enr_network_id = self.discv5_disable_enr_network_id;
         network_config.find_chunks_enabled = self.network_find_chunks_enabled;
+        network_config.shard_config = self.shard_config()?.into();
 
         Ok(network_config)
     }
 
     pub fn storage_config(&self) -> Result<StorageConfig, String> {
         let mut log_config = LogConfig::default();
-        log_config.flow.merkle_node_cache_capacity = self.merkle_node_cache_capacity;
+        log_config.flow.merkle_node_cache_capacity = match self.merkle_node_cache_bytes {
+            Some(bytes) => MerkleNodeCacheCapacity::Bytes(bytes),
+            None => MerkleNodeCacheCapacity::Nodes(self.merkle_node_cache_capacity),
+        };
+        log_config.flow.seal_backlog_high_water_mark = self.seal_backlog_high_water_mark;
+        log_config.flow.sealed_data_cache_capacity = self.sealed_data_cache_capacity;
+        log_config.flow.max_batches_per_append = self.max_batches_per_append;
+        log_config.flow.coalesce_small_appends = self.coalesce_small_appends;
+        log_config.flow.append_buffer_flush_interval =
+            Duration::from_secs(self.append_buffer_flush_interval_secs);
+        log_config.flow.append_buffer_capacity = self.append_buffer_capacity;
+        log_config.flow.append_write_window_bytes = self.append_write_window_bytes;
+        log_config.flow.append_write_window_interval =
+            Duration::from_millis(self.append_write_window_interval_ms);
+        log_config.flow.seal_quarantine_window =
+            self.seal_quarantine_window_secs.map(Duration::from_secs);
+        log_config.flow.max_seal_answers_per_lock = self.max_seal_answers_per_lock;
+        log_config.flow.seal_result_write_chunk_size = self.seal_result_write_chunk_size;
+        log_config.flow.release_seal_lock_before_write = self.release_seal_lock_before_write;
+        log_config.flow.max_seal_load_chunks_per_pull = self.max_seal_load_chunks_per_pull;
+        log_config.flow.max_outstanding_seal_leases = self.max_outstanding_seal_leases;
+        log_config.flow.operation_log_path = self.operation_log_path.clone().map(Into::into);
+        log_config.flow.seal_verify_sample_rate = self.seal_verify_sample_rate;
+        log_config.flow.seal_verify_threads = self.seal_verify_threads;
+        log_config.flow.batch_access_tracking_capacity = self.batch_access_tracking_capacity;
+        log_config.flow.prioritize_recently_read_seals = self.prioritize_recently_read_seals;
+        log_config.flow.proof_cache_capacity = self.proof_cache_capacity;
+        log_config.flow.require_seal_aligned_appends = self.require_seal_aligned_appends;
+        log_config.flow.seal_set_compaction_interval = self
+            .seal_set_compaction_interval_secs
+            .map(Duration::from_secs);
+        log_config.flow.max_concurrent_reads = self.max_concurrent_reads;
+        log_config.flow.proof_sweep_prefetch = self.proof_sweep_prefetch;
+        log_config.flow.archive_mode = self.archive_mode;
+        log_config.flow.archive_min_retained_index = self.archive_min_retained_index;
+        log_config.flow.reject_reads_past_flow_end = self.reject_reads_past_flow_end;
+        log_config.flow.monotonic_pad_data_sync_height = self.monotonic_pad_data_sync_height;
+        log_config.flow.delete_concurrency = self.delete_concurrency;
+        if let Some(path) = &self.log_store_metrics_prometheus_file {
+            log_config.flow.metrics_export = MetricsExport::Prometheus(path.into());
+        }
+        log_config.flow.metrics_export_interval =
+            Duration::from_secs(self.log_store_metrics_export_interval_secs);
+        log_config.flow.warmup_batches = self.log_store_warmup_batches;
+        log_config.flow.self_check = self.log_store_self_check;
+        log_config.flow.persist_batch_merkle = self.persist_batch_merkle;
+        log_config.flow.subtree_list_conflict = self
+            .subtree_list_conflict_mode
+            .parse()
+            .map_err(|e| format!("Unable to parse subtree_list_conflict_mode: {:?}", e))?;
+        log_config.flow.truncation_race = self
+            .truncation_race_mode
+            .parse()
+            .map_err(|e| format!("Unable to parse truncation_race_mode: {:?}", e))?;
         Ok(StorageConfig {
             db_dir: self.db_dir.clone().into(),
             log_config,
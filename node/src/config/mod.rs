@@ -65,6 +65,43 @@ build_config! {
     (prune_batch_size, (usize), 16 * 1024)
     (prune_batch_wait_time_ms, (u64), 1000)
     (merkle_node_cache_capacity, (usize), 32 * 1024 * 1024)
+    (merkle_node_cache_bytes, (Option<usize>), None)
+    (subtree_list_conflict_mode, (String), "overwrite".to_string())
+    (log_store_warmup_batches, (usize), 0)
+    (log_store_self_check, (bool), false)
+    (persist_batch_merkle, (bool), false)
+    (seal_backlog_high_water_mark, (Option<usize>), None)
+    (sealed_data_cache_capacity, (usize), 0)
+    (max_batches_per_append, (Option<usize>), None)
+    (truncation_race_mode, (String), "skip".to_string())
+    (coalesce_small_appends, (bool), false)
+    (append_buffer_flush_interval_secs, (u64), 5)
+    (append_buffer_capacity, (usize), 64)
+    (append_write_window_bytes, (Option<usize>), None)
+    (append_write_window_interval_ms, (u64), 1000)
+    (seal_quarantine_window_secs, (Option<u64>), None)
+    (max_seal_answers_per_lock, (Option<usize>), None)
+    (seal_result_write_chunk_size, (Option<usize>), None)
+    (release_seal_lock_before_write, (bool), false)
+    (max_seal_load_chunks_per_pull, (usize), 1)
+    (max_outstanding_seal_leases, (Option<usize>), None)
+    (operation_log_path, (Option<String>), None)
+    (seal_verify_sample_rate, (f64), 0.0)
+    (seal_verify_threads, (usize), 0)
+    (batch_access_tracking_capacity, (usize), 0)
+    (prioritize_recently_read_seals, (bool), false)
+    (proof_cache_capacity, (usize), 0)
+    (require_seal_aligned_appends, (bool), false)
+    (seal_set_compaction_interval_secs, (Option<u64>), None)
+    (max_concurrent_reads, (Option<usize>), None)
+    (proof_sweep_prefetch, (usize), 2)
+    (archive_mode, (bool), false)
+    (archive_min_retained_index, (u64), 0)
+    (reject_reads_past_flow_end, (bool), false)
+    (monotonic_pad_data_sync_height, (bool), false)
+    (delete_concurrency, (usize), 1)
+    (log_store_metrics_prometheus_file, (Option<String>), None)
+    (log_store_metrics_export_interval_secs, (u64), 10)
 
     // misc
     (log_config_file, (String), "log_config".to_string())
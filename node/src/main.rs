@@ -9,6 +9,7 @@ mod log;
 use crate::config::ZgsConfig;
 use client::{Client, ClientBuilder, RuntimeContext};
 use std::error::Error;
+use std::sync::{Arc, Mutex};
 
 async fn start_node(context: RuntimeContext, config: ZgsConfig) -> Result<Client, String> {
     let network_config = config.network_config().await?;
@@ -73,21 +74,24 @@ fn main() -> Result<(), Box<dyn Error>> {
     );
 
     // start services
+    let client = Arc::new(Mutex::new(None));
+    let started_client = client.clone();
     executor.clone().spawn(
         async move {
             info!("Starting services...");
-            if let Err(e) = start_node(context.clone(), config).await {
-                error!(reason = %e, "Failed to start zgs node");
-                // Ignore the error since it always occurs during normal operation when
-                // shutting down.
-                let _ =
-                    executor
-                        .shutdown_sender()
-                        .try_send(task_executor::ShutdownReason::Failure(
-                            "Failed to start zgs node",
-                        ));
-            } else {
-                info!("Services started");
+            match start_node(context.clone(), config).await {
+                Ok(client) => {
+                    *started_client.lock().unwrap() = Some(client);
+                    info!("Services started");
+                }
+                Err(e) => {
+                    error!(reason = %e, "Failed to start zgs node");
+                    // Ignore the error since it always occurs during normal operation when
+                    // shutting down.
+                    let _ = executor.shutdown_sender().try_send(
+                        task_executor::ShutdownReason::Failure("Failed to start zgs node"),
+                    );
+                }
             }
         },
         "zgs_node",
@@ -102,6 +106,11 @@ fn main() -> Result<(), Box<dyn Error>> {
     // Shutdown the environment once all tasks have completed.
     environment.shutdown_on_idle();
 
+    // Flush the store once the services that write to it have stopped.
+    if let Some(client) = client.lock().unwrap().take() {
+        client.flush_store();
+    }
+
     match shutdown_reason {
         task_executor::ShutdownReason::Success(_) => Ok(()),
         task_executor::ShutdownReason::Failure(msg) => Err(msg.to_string().into()),
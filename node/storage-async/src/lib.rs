@@ -53,10 +53,12 @@ impl Store {
     delegate!(fn put_chunks(tx_seq: u64, chunks: ChunkArray) -> Result<()>);
     delegate!(fn put_chunks_with_tx_hash(tx_seq: u64, tx_hash: H256, chunks: ChunkArray, maybe_file_proof: Option<FlowProof>) -> Result<bool>);
     delegate!(fn get_chunk_by_flow_index(index: u64, length: u64) -> Result<Option<ChunkArray>>);
+    delegate!(fn get_entries_at_version(start: u64, end: u64, min_flow_length: u64) -> Result<Option<ChunkArray>>);
     delegate!(fn finalize_tx(tx_seq: u64) -> Result<()>);
     delegate!(fn prune_tx(tx_seq: u64) -> Result<()>);
     delegate!(fn finalize_tx_with_hash(tx_seq: u64, tx_hash: H256) -> Result<bool>);
     delegate!(fn get_proof_at_root(root: Option<DataRoot>, index: u64, length: u64) -> Result<FlowRangeProof>);
+    delegate!(fn gen_tx_root_proof(tx_seq: u64) -> Result<(DataRoot, FlowProof)>);
     delegate!(fn get_context() -> Result<(DataRoot, u64)>);
 
     pub async fn get_tx_seq_by_data_root(&self, data_root: &DataRoot) -> Result<Option<u64>> {
@@ -103,11 +105,27 @@ impl Store {
             .await
     }
 
-    pub async fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> anyhow::Result<()> {
+    pub async fn submit_seal_result(
+        &self,
+        answers: Vec<SealAnswer>,
+    ) -> anyhow::Result<Vec<SealAnswer>> {
         self.spawn(move |store| store.submit_seal_result(answers))
             .await
     }
 
+    pub async fn resync_seal_set(&self) -> anyhow::Result<()> {
+        self.spawn(move |store| store.resync_seal_set()).await
+    }
+
+    pub async fn rebuild_seal_set(
+        &self,
+        start_batch: u64,
+        end_batch: u64,
+    ) -> anyhow::Result<usize> {
+        self.spawn(move |store| store.rebuild_seal_set(start_batch, end_batch))
+            .await
+    }
+
     pub async fn load_sealed_data(&self, chunk_index: u64) -> Result<Option<MineLoadChunk>> {
         self.spawn(move |store| store.load_sealed_data(chunk_index))
             .await
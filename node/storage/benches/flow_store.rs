@@ -0,0 +1,340 @@
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use shared_types::ChunkArray;
+use storage::{
+    config::ShardConfig,
+    log_store::{
+        flow_store::{batch_iter, batch_iter_lazy, batch_iter_sharded, FlowConfig},
+        log_manager::{LogConfig, PORA_CHUNK_SIZE},
+        FlowRead, FlowSeal, FlowWrite, SealAnswer,
+    },
+    LogManager, H256,
+};
+use zgs_spec::{BYTES_PER_SECTOR, SECTORS_PER_SEAL};
+
+const NUM_READ_BATCHES: usize = 256;
+
+fn create_store() -> LogManager {
+    LogManager::memorydb(LogConfig::default()).unwrap()
+}
+
+fn chunk_array(start_index: usize, num_sectors: usize) -> ChunkArray {
+    ChunkArray {
+        data: (0..num_sectors * BYTES_PER_SECTOR)
+            .map(|i| (i % 251) as u8)
+            .collect(),
+        start_index: start_index as u64,
+    }
+}
+
+fn append_entries(c: &mut Criterion) {
+    let mut group = c.benchmark_group("append_entries");
+    group.sample_size(20);
+    for num_sectors in [64, PORA_CHUNK_SIZE, 16 * PORA_CHUNK_SIZE] {
+        let chunks = chunk_array(PORA_CHUNK_SIZE, num_sectors);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(num_sectors),
+            &chunks,
+            |b, chunks| {
+                b.iter_batched(
+                    create_store,
+                    |store| store.flow_store().append_entries(chunks.clone()).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+/// Many small appends, committed one by one or collected by the write window.
+fn small_appends(c: &mut Criterion) {
+    let mut group = c.benchmark_group("small_appends");
+    group.sample_size(10);
+    let appends: Vec<ChunkArray> = (0..16 * PORA_CHUNK_SIZE / 64)
+        .map(|i| chunk_array(PORA_CHUNK_SIZE + i * 64, 64))
+        .collect();
+    for window_bytes in [None, Some(4 << 20)] {
+        let name = window_bytes.map_or("no_window".to_string(), |bytes| bytes.to_string());
+        group.bench_with_input(BenchmarkId::from_parameter(name), &appends, |b, appends| {
+            b.iter_batched(
+                || {
+                    LogManager::memorydb(LogConfig {
+                        flow: FlowConfig {
+                            append_write_window_bytes: window_bytes,
+                            ..Default::default()
+                        },
+                    })
+                    .unwrap()
+                },
+                |store| {
+                    for data in appends {
+                        store.flow_store().append_entries(data.clone()).unwrap();
+                    }
+                    store.flow_store().flush_append_buffer().unwrap();
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Pruning many batches with the delete transactions committed serially or concurrently.
+fn prune(c: &mut Criterion) {
+    let mut group = c.benchmark_group("prune");
+    group.sample_size(10);
+    let num_batches = 64;
+    let batch_list: Vec<u64> = (1..=num_batches as u64).collect();
+    for concurrency in [1, 4] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(concurrency),
+            &batch_list,
+            |b, batch_list| {
+                b.iter_batched(
+                    || {
+                        let store = LogManager::memorydb(LogConfig {
+                            flow: FlowConfig {
+                                delete_batch_chunk_size: 4,
+                                delete_concurrency: concurrency,
+                                ..Default::default()
+                            },
+                        })
+                        .unwrap();
+                        store
+                            .flow_store()
+                            .append_entries(chunk_array(
+                                PORA_CHUNK_SIZE,
+                                num_batches * PORA_CHUNK_SIZE,
+                            ))
+                            .unwrap();
+                        store
+                    },
+                    |store| store.flow_store().delete_batch_list(batch_list).unwrap(),
+                    BatchSize::LargeInput,
+                )
+            },
+        );
+    }
+    group.finish();
+}
+
+fn get_entries(c: &mut Criterion) {
+    let store = create_store();
+    store
+        .flow_store()
+        .append_entries(chunk_array(
+            PORA_CHUNK_SIZE,
+            NUM_READ_BATCHES * PORA_CHUNK_SIZE,
+        ))
+        .unwrap();
+    let flow_store = store.flow_store();
+
+    let mut group = c.benchmark_group("get_entries");
+    // Read a different batch each time.
+    let mut batch_index = 0;
+    group.bench_function("cold", |b| {
+        b.iter(|| {
+            batch_index = batch_index % NUM_READ_BATCHES + 1;
+            let start = (batch_index * PORA_CHUNK_SIZE) as u64;
+            flow_store
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap()
+        })
+    });
+    // Read the same batch repeatedly.
+    group.bench_function("warm", |b| {
+        let start = PORA_CHUNK_SIZE as u64;
+        b.iter(|| {
+            flow_store
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap()
+        })
+    });
+    group.finish();
+}
+
+fn gen_proof_in_batch(c: &mut Criterion) {
+    let store = create_store();
+    store
+        .flow_store()
+        .append_entries(chunk_array(PORA_CHUNK_SIZE, PORA_CHUNK_SIZE))
+        .unwrap();
+    let flow_store = store.flow_store();
+    c.bench_function("gen_proof_in_batch", |b| {
+        b.iter(|| {
+            flow_store
+                .gen_proof_in_batch(1, PORA_CHUNK_SIZE / 2)
+                .unwrap()
+        })
+    });
+}
+
+fn gen_proof_in_batch_skewed(c: &mut Criterion) {
+    let mut group = c.benchmark_group("gen_proof_in_batch_skewed");
+    for proof_cache_capacity in [0, 1024] {
+        let config = LogConfig {
+            flow: FlowConfig {
+                proof_cache_capacity,
+                ..Default::default()
+            },
+        };
+        let store = LogManager::memorydb(config).unwrap();
+        store
+            .flow_store()
+            .append_entries(chunk_array(
+                PORA_CHUNK_SIZE,
+                NUM_READ_BATCHES * PORA_CHUNK_SIZE,
+            ))
+            .unwrap();
+        let flow_store = store.flow_store();
+        // 9 of 10 requests are for the 8 hot sectors of the first batch.
+        let mut request = 0;
+        group.bench_function(BenchmarkId::from_parameter(proof_cache_capacity), |b| {
+            b.iter(|| {
+                request += 1;
+                let (batch_index, sector_index) = if request % 10 == 0 {
+                    (
+                        request / 10 % NUM_READ_BATCHES + 1,
+                        request % PORA_CHUNK_SIZE,
+                    )
+                } else {
+                    (1, request % 8)
+                };
+                flow_store
+                    .gen_proof_in_batch(batch_index, sector_index)
+                    .unwrap()
+            })
+        });
+    }
+    group.finish();
+}
+
+fn proof_sweep(c: &mut Criterion) {
+    let mut group = c.benchmark_group("proof_sweep");
+    group.sample_size(10);
+    let batches: Vec<u64> = (1..=NUM_READ_BATCHES as u64).collect();
+    let sector_selector = |batch_index: u64| batch_index as usize % PORA_CHUNK_SIZE;
+    for proof_sweep_prefetch in [0, 2] {
+        let config = LogConfig {
+            flow: FlowConfig {
+                proof_sweep_prefetch,
+                ..Default::default()
+            },
+        };
+        let store = LogManager::memorydb(config).unwrap();
+        store
+            .flow_store()
+            .append_entries(chunk_array(
+                PORA_CHUNK_SIZE,
+                NUM_READ_BATCHES * PORA_CHUNK_SIZE,
+            ))
+            .unwrap();
+        let flow_store = store.flow_store();
+        group.bench_function(BenchmarkId::from_parameter(proof_sweep_prefetch), |b| {
+            b.iter(|| flow_store.proof_sweep(&batches, sector_selector).unwrap())
+        });
+    }
+    // The same sweep with one `gen_proof_in_batch` call per batch.
+    let store = create_store();
+    store
+        .flow_store()
+        .append_entries(chunk_array(
+            PORA_CHUNK_SIZE,
+            NUM_READ_BATCHES * PORA_CHUNK_SIZE,
+        ))
+        .unwrap();
+    let flow_store = store.flow_store();
+    group.bench_function("per_batch", |b| {
+        b.iter(|| {
+            for &batch_index in &batches {
+                flow_store
+                    .gen_proof_in_batch(batch_index as usize, sector_selector(batch_index))
+                    .unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn submit_seal_result_verified(c: &mut Criterion) {
+    let mut group = c.benchmark_group("submit_seal_result_verified");
+    group.sample_size(10);
+    for seal_verify_threads in [0, 4] {
+        let setup = || {
+            let config = LogConfig {
+                flow: FlowConfig {
+                    seal_verify_sample_rate: 1.0,
+                    seal_verify_threads,
+                    ..Default::default()
+                },
+            };
+            let store = LogManager::memorydb(config).unwrap();
+            let flow_store = store.flow_store();
+            // Mark the seal worker as available so the appended seals are queued.
+            flow_store.pull_seal_chunk(usize::MAX).unwrap();
+            flow_store
+                .append_entries(chunk_array(PORA_CHUNK_SIZE, PORA_CHUNK_SIZE))
+                .unwrap();
+            let answers: Vec<SealAnswer> = flow_store
+                .pull_seal_chunk(usize::MAX)
+                .unwrap()
+                .unwrap()
+                .into_iter()
+                .map(|task| {
+                    let mut sealed_data = task.non_sealed_data;
+                    zgs_seal::seal(
+                        &mut sealed_data,
+                        &H256::zero(),
+                        &H256::zero(),
+                        task.seal_index * SECTORS_PER_SEAL as u64,
+                    );
+                    SealAnswer {
+                        seal_index: task.seal_index,
+                        version: task.version,
+                        sealed_data,
+                        miner_id: H256::zero(),
+                        seal_context: H256::zero(),
+                        context_end_seal: task.seal_index + 1,
+                    }
+                })
+                .collect();
+            (store, answers)
+        };
+        group.bench_function(BenchmarkId::from_parameter(seal_verify_threads), |b| {
+            b.iter_batched(
+                &setup,
+                |(store, answers)| store.flow_store().submit_seal_result(answers).unwrap(),
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn batch_iterators(c: &mut Criterion) {
+    let end = (1024 * PORA_CHUNK_SIZE) as u64;
+    let shard_config = ShardConfig::new(1, 4).unwrap();
+    c.bench_function("batch_iter", |b| {
+        b.iter(|| batch_iter(1, end, PORA_CHUNK_SIZE))
+    });
+    c.bench_function("batch_iter_lazy", |b| {
+        b.iter(|| batch_iter_lazy(1, end, PORA_CHUNK_SIZE).count())
+    });
+    c.bench_function("batch_iter_sharded", |b| {
+        b.iter(|| batch_iter_sharded(1, end, PORA_CHUNK_SIZE, shard_config))
+    });
+}
+
+criterion_group!(
+    benches,
+    append_entries,
+    small_appends,
+    prune,
+    get_entries,
+    gen_proof_in_batch,
+    gen_proof_in_batch_skewed,
+    proof_sweep,
+    submit_seal_result_verified,
+    batch_iterators
+);
+criterion_main!(benches);
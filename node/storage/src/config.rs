@@ -1,3 +1,4 @@
+use crate::log_store::flow_store::batch_iter_lazy;
 use crate::log_store::log_manager::LogConfig;
 use serde::{Deserialize, Serialize};
 use ssz_derive::{Decode, Encode};
@@ -123,6 +124,15 @@ impl ShardConfig {
         segment_index as usize % self.num_shard == self.shard_id
     }
 
+    /// Return the parts of the sector range `[start, end)` in the batches owned by this shard,
+    /// split at the batch boundaries, e.g. to partition the data before sending them to the
+    /// sharded nodes.
+    pub fn owned_batch_ranges(&self, start: u64, end: u64, batch_size: usize) -> Vec<(u64, u64)> {
+        batch_iter_lazy(start, end, batch_size)
+            .filter(|(batch_start, _)| self.in_range(batch_start / batch_size as u64))
+            .collect()
+    }
+
     pub fn next_segment_index(&self, current: usize, start_index: usize) -> usize {
         // `shift` should be 0 if `current` was returned by the same config.
         let shift = (start_index + current + self.num_shard - self.shard_id) % self.num_shard;
@@ -277,6 +287,25 @@ mod tests {
         ]));
     }
 
+    #[test]
+    fn test_owned_batch_ranges() {
+        assert_eq!(
+            new_config(1, 2).owned_batch_ranges(10, 4100, 1024),
+            vec![(1024, 2048), (3072, 4096)]
+        );
+        assert_eq!(
+            new_config(0, 2).owned_batch_ranges(10, 4100, 1024),
+            vec![(10, 1024), (2048, 3072), (4096, 4100)]
+        );
+        assert_eq!(
+            new_config(0, 1).owned_batch_ranges(10, 2050, 1024),
+            vec![(10, 1024), (1024, 2048), (2048, 2050)]
+        );
+        assert!(new_config(1, 2)
+            .owned_batch_ranges(10, 1000, 1024)
+            .is_empty());
+    }
+
     #[test]
     fn test_shard_intersect() {
         // 1 shard
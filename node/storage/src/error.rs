@@ -1,4 +1,5 @@
 use anyhow;
+use ethereum_types::H256;
 use ssz::DecodeError;
 use std::error::Error as ErrorTrait;
 use std::fmt::{Debug, Display, Formatter};
@@ -12,6 +13,72 @@ pub enum Error {
     /// A partial chunk batch is written.
     InvalidBatchBoundary,
     ValueDecodingError(DecodeError),
+    /// The seal backlog has reached the configured high-water mark, so new entries are
+    /// rejected until the sealing worker catches up.
+    SealBacklogFull {
+        backlog: usize,
+        limit: usize,
+    },
+    /// The flow is shorter than the length the reader has observed, e.g. it has been reverted
+    /// by a chain reorg since then.
+    Stale {
+        flow_length: u64,
+        min_flow_length: u64,
+    },
+    /// A seal task in the in-memory seal set has no unsealed data in db. The seal set can be
+    /// rebuilt with `FlowSeal::resync_seal_set`.
+    SealStateDesync {
+        seal_index: u64,
+    },
+    /// A write is called on a store opened with `FlowStore::open_read_only`.
+    ReadOnly,
+    /// A seal answer sampled by `FlowConfig::seal_verify_sample_rate` does not match the
+    /// recomputed seal, so the whole submission is rejected.
+    SealVerificationFailed {
+        seal_index: u64,
+        miner_id: H256,
+    },
+    /// The root of a batch completed by `FlowStore::append_entries_with_roots` differs from
+    /// the supplied one.
+    BatchRootMismatch {
+        batch_index: u64,
+        expected: H256,
+        actual: H256,
+    },
+    /// A truncate below `FlowConfig::archive_min_retained_index` is rejected in archive mode.
+    TruncateBlocked {
+        start_index: u64,
+        min_retained_index: u64,
+    },
+    /// `FlowConfig::reject_reads_past_flow_end` is set and a read starts at or after the end
+    /// of the flow, so the data do not exist yet.
+    BeyondFlowEnd {
+        requested: u64,
+        flow_length: u64,
+    },
+    /// `FlowConfig::monotonic_pad_data_sync_height` is set and a pad data sync height below
+    /// the stored one is put without `force`.
+    PadDataSyncHeightRegression {
+        current: u64,
+        requested: u64,
+    },
+    /// The subtree list inserted for a batch conflicts with the stored one under
+    /// `FlowConfig::subtree_list_conflict`.
+    SubtreeListConflict {
+        batch_index: u64,
+    },
+    /// An append prepared before a truncate has data at or after its start index, and
+    /// `FlowConfig::truncation_race` rejects it.
+    TruncatedDuringAppend {
+        start_index: u64,
+        truncated_at: u64,
+    },
+    /// A stored MPT node is not a 32-byte hash, so the node should be rebuilt.
+    CorruptNode {
+        layer: usize,
+        pos: usize,
+        len: usize,
+    },
     Custom(String),
 }
 
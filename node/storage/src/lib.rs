@@ -48,7 +48,7 @@ impl ZgsKeyValueDB for Database {
 }
 
 impl ZgsKeyValueDB for InMemory {
-    fn num_keys(&self, _col: u32) -> std::io::Result<u64> {
-        todo!("not used")
+    fn num_keys(&self, col: u32) -> std::io::Result<u64> {
+        Ok(self.iter(col).count() as u64)
     }
 }
@@ -2,70 +2,656 @@ use crate::config::ShardConfig;
 use crate::error::Error;
 use crate::log_store::load_chunk::EntryBatch;
 use crate::log_store::log_manager::{
-    bytes_to_entries, COL_ENTRY_BATCH, COL_FLOW_MPT_NODES, COL_PAD_DATA_LIST,
-    COL_PAD_DATA_SYNC_HEIGH, PORA_CHUNK_SIZE,
+    bytes_to_entries, COL_BATCH_MERKLE, COL_ENTRY_BATCH, COL_FLOW_MPT_NODES, COL_MISC,
+    COL_PAD_DATA_LIST, COL_PAD_DATA_SYNC_HEIGH, COL_ROOT_INDEX, COL_SEAL_QUARANTINE,
+};
+use crate::log_store::metrics_export::MetricsExport;
+use crate::log_store::op_log::OperationLog;
+use crate::log_store::seal_task_manager::{
+    current_timestamp, decode_seal_set, encode_seal_set, SealTaskManager, SEAL_SET_RUN_SIZE,
+    SEAL_TASK_LEASE_TIMEOUT,
 };
-use crate::log_store::seal_task_manager::SealTaskManager;
 use crate::log_store::{
-    metrics, FlowRead, FlowSeal, FlowWrite, MineLoadChunk, SealAnswer, SealTask,
+    metrics, CommitmentCheck, FlowHealth, FlowInitState, FlowRead, FlowSeal, FlowWrite,
+    MineLoadChunk, SealAnswer, SealBacklog, SealInspect, SealSetSummary, SealTask, TruncationEvent,
 };
 use crate::{try_option, ZgsKeyValueDB};
 use any::Any;
 use anyhow::{anyhow, bail, Result};
-use append_merkle::{MerkleTreeRead, NodeDatabase, NodeTransaction};
+use append_merkle::{MerkleTreeRead, NodeDatabase, NodeManager, NodeTransaction, Sha3Algorithm};
+use futures::{stream, Stream};
 use itertools::Itertools;
-use kvdb::DBTransaction;
-use parking_lot::RwLock;
-use shared_types::{ChunkArray, DataRoot, FlowProof};
+use kvdb::{DBOp, DBTransaction};
+use lru::LruCache;
+use parking_lot::{Condvar, Mutex, RwLock};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rayon::prelude::{IntoParallelRefIterator, ParallelIterator};
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use shared_types::{ChunkArray, DataRoot, FlowProof, Merkle};
 use ssz::{Decode, Encode};
 use ssz_derive::{Decode as DeriveDecode, Encode as DeriveEncode};
 
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::fmt::Debug;
-use std::sync::Arc;
-use std::time::Instant;
-use std::{any, cmp};
-use tracing::{debug, error, trace};
-use zgs_spec::{BYTES_PER_SECTOR, SEALS_PER_LOAD, SECTORS_PER_LOAD, SECTORS_PER_SEAL};
+use std::io::{Read, Write};
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+use std::{any, cmp, mem};
+use tiny_keccak::{Hasher, Keccak};
+use tokio::sync::broadcast;
+use tracing::{debug, error, field, info, instrument, trace, warn, Span};
+use zgs_spec::{
+    BYTES_PER_SEAL, BYTES_PER_SECTOR, SEALS_PER_LOAD, SECTORS_PER_LOAD, SECTORS_PER_SEAL,
+};
 
 pub struct FlowStore {
     flow_db: Arc<FlowDBStore>,
     data_db: Arc<FlowDBStore>,
     seal_manager: SealTaskManager,
+    /// The recently loaded `MineLoadChunk`s keyed by batch index. `None` if disabled.
+    sealed_data_cache: Option<Mutex<LruCache<u64, MineLoadChunk>>>,
+    /// The incomplete batches updated by `append_entries` but not written to db yet, with the
+    /// time they are first buffered. Only used if `coalesce_small_appends` is set.
+    append_buffer: Mutex<AppendBuffer>,
+    /// The batch writes collected if `append_write_window_bytes` is set.
+    write_window: Mutex<WriteWindow>,
+    /// The end index of the last append and whether it succeeded.
+    last_append: Mutex<Option<(u64, bool)>>,
+    /// The flow length set by `set_flow_length` and kept up to date by the appends, pads and
+    /// truncates. `None` until it is set.
+    flow_length: Mutex<Option<u64>>,
+    /// Serializes the read-modify-writes of the pad data sync height.
+    pad_data_sync_height_lock: Mutex<()>,
+    /// Held by `submit_seal_result` while it writes the sealed batches after releasing the
+    /// seal lock, see `release_seal_lock_before_write`.
+    seal_write_lock: Mutex<()>,
+    /// Set by `open_read_only`. The methods that write to db return `Error::ReadOnly`.
+    read_only: bool,
+    /// Records the writes if `operation_log_path` is set.
+    operation_log: Option<OperationLog>,
+    /// The proofs generated by `gen_proof_in_batch`. `None` if `proof_cache_capacity` is `0`.
+    proof_cache: Option<Mutex<ProofCache>>,
+    /// The last time each recently read batch is loaded, least recent first when iterated in
+    /// reverse. `None` if `batch_access_tracking_capacity` is `0`.
+    batch_access: Option<Mutex<LruCache<u64, Instant>>>,
+    /// Recomputes the sampled seals in `submit_seal_result` if `seal_verify_threads` is set.
+    seal_verify_pool: Option<ThreadPool>,
+    /// Sends the events of `subscribe_truncations`.
+    truncation_events: broadcast::Sender<TruncationEvent>,
+    /// Updated by `truncate` under the seal lock, see `append_entries_since`.
+    truncation_history: Mutex<TruncationHistory>,
+    /// Selects the seal answers verified by `submit_seal_result`.
+    seal_verify_rng: Mutex<StdRng>,
+    /// Bounds the concurrent `get_entries` calls if `max_concurrent_reads` is set.
+    read_limiter: Option<ReadLimiter>,
     config: FlowConfig,
 }
 
+/// The number of batches before the end of the last append checked by `health`.
+const HEALTH_TAIL_BATCHES: u64 = 16;
+
+/// The number of truncation events kept for a slow subscriber.
+const TRUNCATION_EVENT_CAPACITY: usize = 64;
+
+/// The number of recent truncations kept to check the appends prepared before them.
+const TRUNCATION_HISTORY_CAPACITY: usize = 1024;
+
+type AppendBuffer = BTreeMap<u64, (EntryBatch, Instant)>;
+
+/// The start indices of the recent truncates, oldest first. `epoch` counts all the truncates,
+/// so the last entry of `recent` is the truncate of `epoch`.
+#[derive(Default)]
+struct TruncationHistory {
+    epoch: u64,
+    recent: VecDeque<u64>,
+}
+
+impl TruncationHistory {
+    fn record(&mut self, start_index: u64) {
+        self.epoch += 1;
+        if self.recent.len() == TRUNCATION_HISTORY_CAPACITY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(start_index);
+    }
+
+    /// Return the lowest start index truncated after `epoch`, or `None` if there is no such
+    /// truncate. An epoch older than the kept history is an error, because the dropped
+    /// truncates may be lower.
+    fn boundary_since(&self, epoch: u64) -> Result<Option<u64>> {
+        let missed = self.epoch.saturating_sub(epoch);
+        if missed > self.recent.len() as u64 {
+            bail!(
+                "truncation epoch out of history: epoch={} current={} kept={}",
+                epoch,
+                self.epoch,
+                self.recent.len()
+            );
+        }
+        Ok(self
+            .recent
+            .iter()
+            .skip(self.recent.len() - missed as usize)
+            .min()
+            .copied())
+    }
+}
+
+/// The pending db transaction of the write window, with its batches for the reads.
+struct WriteWindow {
+    tx: DBTransaction,
+    batches: BTreeMap<u64, EntryBatch>,
+    /// The approximate size of `tx`.
+    bytes: usize,
+    /// The time the first batch is added.
+    opened_at: Option<Instant>,
+}
+
+impl Default for WriteWindow {
+    fn default() -> Self {
+        Self {
+            tx: DBTransaction::new(),
+            batches: Default::default(),
+            bytes: 0,
+            opened_at: None,
+        }
+    }
+}
+
+/// The proofs keyed by `(batch_index, sector_index)`. `epoch` is increased by every
+/// invalidation, so a proof generated from a batch loaded before a write is not cached.
+struct ProofCache {
+    proofs: LruCache<(u64, usize), FlowProof>,
+    epoch: u64,
+}
+
+/// A counting semaphore for the reads. A permit is taken before any lock of the store and the
+/// writes never take one, so a read waiting for a permit cannot block an append.
+struct ReadLimiter {
+    limit: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+struct ReadPermit<'a> {
+    limiter: &'a ReadLimiter,
+}
+
+impl ReadLimiter {
+    fn new(limit: usize) -> Self {
+        Self {
+            limit: cmp::max(limit, 1),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until fewer than `limit` reads are in flight.
+    fn acquire(&self) -> ReadPermit {
+        let mut in_flight = self.in_flight.lock();
+        while *in_flight >= self.limit {
+            self.released.wait(&mut in_flight);
+        }
+        *in_flight += 1;
+        metrics::READS_IN_FLIGHT.update(*in_flight);
+        ReadPermit { limiter: self }
+    }
+}
+
+impl Drop for ReadPermit<'_> {
+    fn drop(&mut self) {
+        let mut in_flight = self.limiter.in_flight.lock();
+        *in_flight -= 1;
+        metrics::READS_IN_FLIGHT.update(*in_flight);
+        self.limiter.released.notify_one();
+    }
+}
+
+const EXPORT_RECORD_BATCH: u8 = 0;
+const EXPORT_RECORD_TRAILER: u8 = 1;
+const EXPORT_RECORD_COMPLETE_BATCH: u8 = 2;
+const SEAL_SET_KEY: &[u8] = b"seal_set";
+const TRUNCATE_PROGRESS_KEY: &[u8] = b"truncate_start_index";
+const GENESIS_KEY: &[u8] = b"genesis";
+/// The first sector of the flow is reserved and never has data, which is why `get_entries`
+/// skips it.
+const GENESIS_RESERVED_SECTORS: u64 = 1;
+/// A batch record holds at most one batch of data plus a small ssz overhead.
+const MAX_EXPORT_RECORD_SIZE: usize = 2 * SECTORS_PER_LOAD * BYTES_PER_SECTOR;
+
 impl FlowStore {
     pub fn new(flow_db: Arc<FlowDBStore>, data_db: Arc<FlowDBStore>, config: FlowConfig) -> Self {
         Self {
             flow_db,
             data_db,
             seal_manager: Default::default(),
+            sealed_data_cache: NonZeroUsize::new(config.sealed_data_cache_capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            append_buffer: Default::default(),
+            write_window: Default::default(),
+            last_append: Default::default(),
+            flow_length: Default::default(),
+            read_only: false,
+            proof_cache: NonZeroUsize::new(config.proof_cache_capacity).map(|capacity| {
+                Mutex::new(ProofCache {
+                    proofs: LruCache::new(capacity),
+                    epoch: 0,
+                })
+            }),
+            batch_access: NonZeroUsize::new(config.batch_access_tracking_capacity)
+                .map(|capacity| Mutex::new(LruCache::new(capacity))),
+            operation_log: config.operation_log_path.as_ref().and_then(|path| {
+                OperationLog::open(path)
+                    .map_err(|e| error!("Open operation log failed: path={:?} e={:?}", path, e))
+                    .ok()
+            }),
+            truncation_events: broadcast::channel(TRUNCATION_EVENT_CAPACITY).0,
+            truncation_history: Default::default(),
+            seal_verify_pool: match config.seal_verify_threads {
+                0 => None,
+                num_threads => ThreadPoolBuilder::new()
+                    .num_threads(num_threads)
+                    .thread_name(|i| format!("seal_verify_{}", i))
+                    .build()
+                    .map_err(|e| error!("Build seal verify pool failed: e={:?}", e))
+                    .ok(),
+            },
+            seal_verify_rng: Mutex::new(match config.seal_verify_seed {
+                Some(seed) => StdRng::seed_from_u64(seed),
+                None => StdRng::from_entropy(),
+            }),
+            read_limiter: config.max_concurrent_reads.map(ReadLimiter::new),
+            pad_data_sync_height_lock: Mutex::new(()),
+            seal_write_lock: Mutex::new(()),
             config,
         }
     }
 
+    /// Open a flow store that rejects all the writes with `Error::ReadOnly`, so diagnostics can
+    /// run against the db of a running node, e.g. opened with `open_secondary_db`.
+    ///
+    /// A rocksdb secondary instance reads a snapshot of the primary db as of its open or its
+    /// last `try_catch_up_with_primary`, so the data may be stale and the tail batches may be
+    /// behind the flow of the node.
+    pub fn open_read_only(
+        flow_db: Arc<FlowDBStore>,
+        data_db: Arc<FlowDBStore>,
+        config: FlowConfig,
+    ) -> Self {
+        let mut store = Self::new(flow_db, data_db, config);
+        store.read_only = true;
+        store
+    }
+
+    pub fn config(&self) -> &FlowConfig {
+        &self.config
+    }
+
+    /// The number of sectors of `size_bytes` bytes, rounded up like `bytes_to_entries`.
+    fn bytes_to_sectors(&self, size_bytes: usize) -> u64 {
+        size_bytes.div_ceil(self.config.sector_size) as u64
+    }
+
+    fn ensure_writable(&self) -> Result<()> {
+        if self.read_only {
+            bail!(Error::ReadOnly);
+        }
+        Ok(())
+    }
+
+    /// Write the batches buffered by `coalesce_small_appends` and the operation log to disk. This
+    /// is called on a clean shutdown.
+    ///
+    /// The dbs themselves are not flushed. `kvdb-rocksdb` 0.19 has neither a `flush` nor a
+    /// `flush_wal`, and it does not expose the underlying rocksdb handle to call them on, so
+    /// `ZgsKeyValueDB` has no flush either. This is safe because every write goes through the
+    /// WAL, which is replayed when the db is opened.
+    pub fn flush(&self) -> Result<()> {
+        self.flush_append_buffer()?;
+        if self.config.seal_set_compaction_interval.is_some() {
+            self.persist_seal_set()?;
+        }
+        if let Some(log) = &self.operation_log {
+            log.sync()?;
+        }
+        Ok(())
+    }
+
+    /// Write the seal set to db in the range-encoded form of `encode_seal_set`, replacing the
+    /// persisted one. Return the number of encoded runs.
+    pub fn persist_seal_set(&self) -> Result<usize> {
+        self.ensure_writable()?;
+        let encoded = encode_seal_set(&self.seal_manager.to_seal_set.read());
+        self.data_db.put_seal_set(&encoded)?;
+        Ok(encoded.len() / SEAL_SET_RUN_SIZE)
+    }
+
+    /// Replace the seal set with the one written by `persist_seal_set`, and return the number
+    /// of restored seals. The restored seals get the current version, since the versions of
+    /// the previous run are meaningless to the seal workers.
+    ///
+    /// The persisted set is only as recent as the last `persist_seal_set`, so it may lack the
+    /// latest seal tasks or contain sealed ones. `resync_seal_set` rebuilds an exact set from
+    /// the stored batches.
+    ///
+    /// A set with more seals than the stored batches can hold is rejected as corrupt. The
+    /// batch count of rocksdb is an estimate, so the bound allows for twice as many batches.
+    pub fn restore_seal_set(&self) -> Result<usize> {
+        let encoded = match self.data_db.get_seal_set()? {
+            Some(encoded) => encoded,
+            None => return Ok(0),
+        };
+        let num_batches = self
+            .data_db
+            .kvdb
+            .num_keys(self.data_db.columns.entry_batch)? as usize;
+        let max_seals = num_batches
+            .saturating_mul(2)
+            .saturating_add(1)
+            .saturating_mul(SEALS_PER_LOAD);
+        let version = self.seal_manager.to_seal_version();
+        let mut restored = decode_seal_set(&encoded, max_seals)?;
+        restored.values_mut().for_each(|v| *v = version);
+        let count = restored.len();
+        *self.seal_manager.to_seal_set.write() = restored;
+        if count > 0 {
+            self.seal_manager.notify_new_tasks();
+        }
+        Ok(count)
+    }
+
+    /// Write all the batches buffered by `coalesce_small_appends` and the write window of
+    /// `append_write_window_bytes` to db.
+    pub fn flush_append_buffer(&self) -> Result<()> {
+        let mut append_buffer = self.append_buffer.lock();
+        let indices = append_buffer.keys().copied().collect();
+        self.flush_buffered_batches(&mut append_buffer, indices)?;
+        self.flush_write_window(&mut self.write_window.lock())
+    }
+
+    fn flush_buffered_batches(
+        &self,
+        append_buffer: &mut AppendBuffer,
+        indices: Vec<u64>,
+    ) -> Result<()> {
+        if indices.is_empty() {
+            return Ok(());
+        }
+        let batch_list = indices
+            .into_iter()
+            .filter_map(|index| Some((index, append_buffer.get(&index)?.0.clone())))
+            .collect();
+        metrics::APPEND_BUFFER_FLUSH.mark(1);
+        // The buffered batches are incomplete, so no batch root is returned and none of them
+        // is restored from the quarantine.
+        self.write_entry_batch_list(batch_list, append_buffer, &BTreeMap::new(), &HashSet::new())?;
+        Ok(())
+    }
+
+    /// Recompute the seals of a sample of `answers` from the unsealed data and check them
+    /// against the submitted data. The answers that are stale or already sealed are not checked.
+    /// The seals are recomputed in `seal_verify_pool` if it is set, and the first wrong
+    /// answer in the submitted order is reported.
+    fn verify_seal_sample(&self, answers: &[SealAnswer]) -> Result<()> {
+        let sample_rate = self.config.seal_verify_sample_rate.min(1.0);
+        let sampled: Vec<&SealAnswer> = {
+            let mut rng = self.seal_verify_rng.lock();
+            answers
+                .iter()
+                .filter(|_| rng.gen_bool(sample_rate))
+                .collect()
+        };
+        let mut batch: Option<(u64, EntryBatch)> = None;
+        let mut to_verify = Vec::with_capacity(sampled.len());
+        for answer in sampled {
+            let load_index = answer.seal_index / SEALS_PER_LOAD as u64;
+            if batch
+                .as_ref()
+                .map_or(true, |(index, _)| *index != load_index)
+            {
+                batch = match self.get_entry_batch(load_index)? {
+                    Some(loaded) => Some((load_index, loaded)),
+                    None => continue,
+                };
+            }
+            let (_, loaded) = batch.as_ref().unwrap();
+            let local_seal_index = (answer.seal_index % SEALS_PER_LOAD as u64) as u16;
+            if let Some(data) = loaded.get_non_sealed_data(local_seal_index) {
+                to_verify.push((answer, data));
+            }
+        }
+        let is_wrong_seal = |(answer, data): &&(&SealAnswer, [u8; BYTES_PER_SEAL])| {
+            let mut data = *data;
+            zgs_seal::seal(
+                &mut data,
+                &answer.miner_id,
+                &answer.seal_context,
+                answer.seal_index * SECTORS_PER_SEAL as u64,
+            );
+            data != answer.sealed_data
+        };
+        let wrong_answer = match &self.seal_verify_pool {
+            Some(pool) => pool.install(|| to_verify.par_iter().find_first(is_wrong_seal)),
+            None => to_verify.iter().find(is_wrong_seal),
+        };
+        if let Some((answer, _)) = wrong_answer {
+            warn!(
+                "Seal verification failed: seal_index={} miner_id={:?}",
+                answer.seal_index, answer.miner_id
+            );
+            metrics::SEAL_VERIFICATION_FAILED.mark(1);
+            bail!(Error::SealVerificationFailed {
+                seal_index: answer.seal_index,
+                miner_id: answer.miner_id,
+            });
+        }
+        Ok(())
+    }
+
+    /// Load a batch, preferring the version buffered by `coalesce_small_appends`.
+    /// The read is recorded for `coldest_batches` if the batch exists.
+    fn get_entry_batch(&self, batch_index: u64) -> Result<Option<EntryBatch>> {
+        let batch = self.get_batch_with_pending(batch_index)?;
+        if let (Some(batch_access), Some(_)) = (&self.batch_access, &batch) {
+            batch_access.lock().put(batch_index, Instant::now());
+        }
+        Ok(batch)
+    }
+
+    /// Return the first seal task below `seal_index_max` in the most recently read batch that
+    /// has one, if `prioritize_recently_read_seals` is set and the reads are tracked.
+    fn recently_read_seal_start(
+        &self,
+        to_seal_set: &BTreeMap<usize, u64>,
+        seal_index_max: usize,
+    ) -> Option<usize> {
+        if !self.config.prioritize_recently_read_seals {
+            return None;
+        }
+        let batch_access = self.batch_access.as_ref()?.lock();
+        batch_access.iter().find_map(|(batch_index, _)| {
+            let start = *batch_index as usize * SEALS_PER_LOAD;
+            let (&seal_index, _) = to_seal_set.range(start..start + SEALS_PER_LOAD).next()?;
+            (seal_index < seal_index_max).then_some(seal_index)
+        })
+    }
+
+    /// Return at most `n` batch indices in db, least recently read first, for choosing the
+    /// batches to move to cold storage. The batches that are not tracked, i.e. not read since
+    /// the start or evicted from the tracker, come first in index order.
+    /// This scans the batch keys in db, and fails if `batch_access_tracking_capacity` is `0`.
+    pub fn coldest_batches(&self, n: usize) -> Result<Vec<u64>> {
+        let batch_access = match &self.batch_access {
+            Some(batch_access) => batch_access,
+            None => bail!("batch access tracking is disabled"),
+        };
+        let tracked: Vec<u64> = batch_access
+            .lock()
+            .iter()
+            .rev()
+            .map(|(batch_index, _)| *batch_index)
+            .collect();
+        let tracked_set: HashSet<u64> = tracked.iter().copied().collect();
+        let mut stored = HashSet::new();
+        let mut coldest = Vec::with_capacity(n);
+        for batch_index in self.data_db.iter_batch_indices() {
+            let batch_index = batch_index?;
+            if !tracked_set.contains(&batch_index) {
+                if coldest.len() < n {
+                    coldest.push(batch_index);
+                }
+            } else {
+                stored.insert(batch_index);
+            }
+        }
+        // The tracked batches may have been deleted since they were read.
+        coldest.extend(
+            tracked
+                .into_iter()
+                .filter(|batch_index| stored.contains(batch_index))
+                .take(n - coldest.len()),
+        );
+        Ok(coldest)
+    }
+
     pub fn insert_subtree_list_for_batch(
         &self,
         batch_index: usize,
         subtree_list: Vec<(usize, usize, DataRoot)>,
     ) -> Result<()> {
+        self.ensure_writable()?;
         let start_time = Instant::now();
+        self.flush_append_buffer()?;
         let mut batch = self
             .data_db
             .get_entry_batch(batch_index as u64)?
             .unwrap_or_else(|| EntryBatch::new(batch_index as u64));
+        let existing = batch.subtree_list();
         batch.set_subtree_list(subtree_list);
+        if !existing.is_empty() {
+            // Compared after the subtrees of the known data are dropped by `set_subtree_list`.
+            let inserted = batch.subtree_list();
+            let conflict = Error::SubtreeListConflict {
+                batch_index: batch_index as u64,
+            };
+            match self.config.subtree_list_conflict {
+                SubtreeListConflictMode::Overwrite => {}
+                SubtreeListConflictMode::Verify => {
+                    if inserted != existing {
+                        error!(
+                            "Subtree list conflict: batch_index={} existing={:?} inserted={:?}",
+                            batch_index, existing, inserted
+                        );
+                        bail!(conflict);
+                    }
+                }
+                SubtreeListConflictMode::Merge => match merge_subtree_lists(&existing, &inserted) {
+                    Some(merged) => batch.set_subtree_list(merged),
+                    None => {
+                        error!(
+                            "Subtree list overlap: batch_index={} existing={:?} inserted={:?}",
+                            batch_index, existing, inserted
+                        );
+                        bail!(conflict);
+                    }
+                },
+            }
+        }
         self.data_db
             .put_entry_raw(vec![(batch_index as u64, batch)])?;
         metrics::INSERT_SUBTREE_LIST.update_since(start_time);
         Ok(())
     }
 
+    /// The proof is cached if `proof_cache_capacity` is set.
     pub fn gen_proof_in_batch(&self, batch_index: usize, sector_index: usize) -> Result<FlowProof> {
+        let cache = match &self.proof_cache {
+            Some(cache) => cache,
+            None => return self.gen_proof_in_batch_uncached(batch_index, sector_index),
+        };
+        let key = (batch_index as u64, sector_index);
+        let epoch = {
+            let mut cache = cache.lock();
+            if let Some(proof) = cache.proofs.get(&key) {
+                metrics::PROOF_CACHE_HIT.mark(1);
+                return Ok(proof.clone());
+            }
+            cache.epoch
+        };
+        let proof = self.gen_proof_in_batch_uncached(batch_index, sector_index)?;
+        let mut cache = cache.lock();
+        // A batch written during the generation may be loaded before the write.
+        if cache.epoch == epoch {
+            cache.proofs.put(key, proof.clone());
+        }
+        Ok(proof)
+    }
+
+    fn gen_proof_in_batch_uncached(
+        &self,
+        batch_index: usize,
+        sector_index: usize,
+    ) -> Result<FlowProof> {
         let batch = self
-            .data_db
             .get_entry_batch(batch_index as u64)?
             .ok_or_else(|| anyhow!("batch missing, index={}", batch_index))?;
+        let merkle = self
+            .batch_merkle_tree(batch_index as u64, &batch)?
+            .ok_or_else(|| {
+                anyhow!(
+                    "batch data incomplete for building a merkle tree, index={}",
+                    batch_index
+                )
+            })?;
+        merkle.gen_proof(sector_index)
+    }
+
+    /// Build the merkle tree of a batch, or load it from db if `persist_batch_merkle` is set
+    /// and the batch is complete. A tree built for a complete batch is stored if its root is
+    /// the batch root in the flow MPT.
+    fn batch_merkle_tree(&self, batch_index: u64, batch: &EntryBatch) -> Result<Option<Merkle>> {
+        let is_first_chunk = batch_index == 0;
+        if !self.config.persist_batch_merkle || batch.num_stored_sectors() != SECTORS_PER_LOAD {
+            return batch.to_merkle_tree(is_first_chunk);
+        }
+        let expected_root = self.flow_db.get_node(0, batch_index as usize)?;
+        if let (Some(root), Some(layers)) =
+            (expected_root, self.data_db.get_batch_merkle(batch_index)?)
+        {
+            match Merkle::from_layers(layers, 0) {
+                Ok(merkle) if merkle.root() == root => {
+                    metrics::BATCH_MERKLE_HIT.mark(1);
+                    return Ok(Some(merkle));
+                }
+                Ok(_) => debug!("Stored batch merkle is stale: batch_index={}", batch_index),
+                Err(e) => warn!(
+                    "Stored batch merkle is invalid: batch_index={} e={:?}",
+                    batch_index, e
+                ),
+            }
+        }
+        let merkle = try_option!(batch.to_merkle_tree(is_first_chunk)?);
+        if !self.read_only && expected_root == Some(merkle.root()) {
+            if let Err(e) = self.data_db.put_batch_merkle(batch_index, &merkle.layers()) {
+                warn!(
+                    "Store batch merkle failed: batch_index={} e={:?}",
+                    batch_index, e
+                );
+            }
+        }
+        Ok(Some(merkle))
+    }
+
+    fn gen_proof_from_batch(
+        batch: &EntryBatch,
+        batch_index: usize,
+        sector_index: usize,
+    ) -> Result<FlowProof> {
         let merkle = batch.to_merkle_tree(batch_index == 0)?.ok_or_else(|| {
             anyhow!(
                 "batch data incomplete for building a merkle tree, index={}",
@@ -75,108 +661,212 @@ impl FlowStore {
         merkle.gen_proof(sector_index)
     }
 
-    pub fn delete_batch_list(&self, batch_list: &[u64]) -> Result<()> {
-        self.seal_manager.delete_batch_list(batch_list);
-        self.data_db.delete_batch_list(batch_list)
-    }
-}
-
-#[derive(Clone, Debug)]
-pub struct FlowConfig {
-    pub batch_size: usize,
-    pub merkle_node_cache_capacity: usize,
-    pub shard_config: Arc<RwLock<ShardConfig>>,
-}
-
-impl Default for FlowConfig {
-    fn default() -> Self {
-        Self {
-            batch_size: SECTORS_PER_LOAD,
-            // Each node takes (8+8+32=)48 Bytes, so the default value is 1.5 GB memory size.
-            merkle_node_cache_capacity: 32 * 1024 * 1024,
-            shard_config: Default::default(),
+    /// Generate the proof of the sector `sector_selector(batch_index)` in each of `batches`,
+    /// and return the proofs in the order of `batches`. Up to `proof_sweep_prefetch` batches
+    /// are read by another thread while the proofs of the earlier ones are built, so the IO
+    /// and the hashing of a sequential sweep, e.g. for DAS, overlap. The proof cache is not
+    /// used, since a sweep rarely repeats a proof.
+    pub fn proof_sweep(
+        &self,
+        batches: &[u64],
+        sector_selector: impl Fn(u64) -> usize,
+    ) -> Result<Vec<FlowProof>> {
+        let gen_proof = |batch_index: u64, batch: Result<Option<EntryBatch>>| {
+            let batch = batch?.ok_or_else(|| anyhow!("batch missing, index={}", batch_index))?;
+            Self::gen_proof_from_batch(&batch, batch_index as usize, sector_selector(batch_index))
+        };
+        let prefetch = self.config.proof_sweep_prefetch;
+        if prefetch == 0 || batches.len() < 2 {
+            return batches
+                .iter()
+                .map(|&batch_index| gen_proof(batch_index, self.get_entry_batch(batch_index)))
+                .collect();
         }
+        std::thread::scope(|scope| {
+            let (sender, receiver) = mpsc::sync_channel(prefetch);
+            scope.spawn(move || {
+                for &batch_index in batches {
+                    let batch = self.get_entry_batch(batch_index);
+                    let failed = batch.is_err();
+                    // The receiver is dropped if a proof fails, which stops the prefetch.
+                    if sender.send((batch_index, batch)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+            receiver
+                .into_iter()
+                .map(|(batch_index, batch)| gen_proof(batch_index, batch))
+                .collect()
+        })
     }
-}
 
-impl FlowRead for FlowStore {
-    /// Return `Ok(None)` if only partial data are available.
-    fn get_entries(&self, index_start: u64, index_end: u64) -> Result<Option<ChunkArray>> {
-        if index_end <= index_start {
+    /// Generate a proof against the current partial merkle root of a batch that may still
+    /// be incomplete. The returned root is not the final batch root.
+    pub fn gen_partial_proof_in_batch(
+        &self,
+        batch_index: usize,
+        sector_index: usize,
+    ) -> Result<PartialBatchProof> {
+        let batch = self
+            .get_entry_batch(batch_index as u64)?
+            .ok_or_else(|| anyhow!("batch missing, index={}", batch_index))?;
+        let is_first_chunk = batch_index == 0;
+        if !(is_first_chunk && sector_index == 0)
+            && batch.get_unsealed_data(sector_index, 1).is_none()
+        {
             bail!(
-                "invalid entry index: start={} end={}",
-                index_start,
-                index_end
+                "sector missing in batch, batch_index={} sector_index={}",
+                batch_index,
+                sector_index
             );
         }
-        let mut data = Vec::with_capacity((index_end - index_start) as usize * BYTES_PER_SECTOR);
-        for (start_entry_index, end_entry_index) in
-            batch_iter(index_start, index_end, self.config.batch_size)
-        {
-            let chunk_index = start_entry_index / self.config.batch_size as u64;
-            let mut offset = start_entry_index - chunk_index * self.config.batch_size as u64;
-            let mut length = end_entry_index - start_entry_index;
+        let merkle = batch.to_partial_merkle_tree(is_first_chunk)?;
+        Ok(PartialBatchProof {
+            proof: merkle.gen_proof(sector_index)?,
+            partial_root: merkle.root(),
+        })
+    }
 
-            // Tempfix: for first chunk, its offset is always 1
-            if chunk_index == 0 && offset == 0 {
-                offset = 1;
-                length -= 1;
-            }
+    pub fn delete_batch_list(&self, batch_list: &[u64]) -> Result<()> {
+        self.ensure_writable()?;
+        self.flush_append_buffer()?;
+        self.seal_manager.delete_batch_list(batch_list);
+        let result = self.data_db.delete_batch_list(
+            batch_list,
+            self.config.delete_batch_chunk_size,
+            self.config.delete_concurrency,
+        );
+        self.invalidate_batch_caches(batch_list.iter().copied());
+        result
+    }
 
-            let entry_batch = try_option!(self.data_db.get_entry_batch(chunk_index)?);
-            let mut entry_batch_data =
-                try_option!(entry_batch.get_unsealed_data(offset as usize, length as usize));
-            data.append(&mut entry_batch_data);
+    /// Clear the sectors in each `[start, end)` range. Batches that become empty are deleted.
+    /// Return the number of cleared sectors.
+    pub fn delete_sector_ranges(&self, ranges: &[(u64, u64)]) -> Result<usize> {
+        self.ensure_writable()?;
+        let mut to_seal_set = self.seal_manager.to_seal_set.write();
+        self.wait_for_seal_writes();
+        self.flush_append_buffer()?;
+        let batch_size = self.config.batch_size as u64;
+        let mut updated_batches = BTreeMap::<u64, Option<EntryBatch>>::new();
+        let mut cleared = 0;
+        for &(start, end) in ranges {
+            if end <= start {
+                bail!("invalid sector range: start={} end={}", start, end);
+            }
+            for (batch_start, batch_end) in batch_iter(start, end, self.config.batch_size) {
+                let batch_index = batch_start / batch_size;
+                let batch = match updated_batches.entry(batch_index) {
+                    Entry::Vacant(e) => e.insert(self.data_db.get_entry_batch(batch_index)?),
+                    Entry::Occupied(e) => e.into_mut(),
+                };
+                let batch = match batch {
+                    Some(batch) => batch,
+                    None => continue,
+                };
+                let batch_offset = batch_index * batch_size;
+                let (cleared_in_batch, cleared_seals) = batch.clear_range(
+                    (batch_start - batch_offset) as usize,
+                    (batch_end - batch_offset) as usize,
+                );
+                cleared += cleared_in_batch;
+                // Remove the cleared seals so `pull_seal_chunk` will not try to load them.
+                for seal_index in cleared_seals {
+                    to_seal_set
+                        .remove(&(batch_index as usize * SEALS_PER_LOAD + seal_index as usize));
+                }
+            }
         }
-        Ok(Some(ChunkArray {
-            data,
-            start_index: index_start,
-        }))
+        let updated_indices: Vec<u64> = updated_batches.keys().copied().collect();
+        let result = self.data_db.update_entry_batch_list(
+            updated_batches
+                .into_iter()
+                .filter_map(|(batch_index, batch)| Some((batch_index, batch?)))
+                .collect(),
+        );
+        self.invalidate_batch_caches(updated_indices);
+        result?;
+        Ok(cleared)
     }
 
-    fn get_available_entries(&self, index_start: u64, index_end: u64) -> Result<Vec<ChunkArray>> {
-        // Both `index_start` and `index_end` are at the batch boundaries, so we do not need
-        // to check if the data is within range when we process each batch.
-        if index_end <= index_start
-            || index_start % self.config.batch_size as u64 != 0
-            || index_end % self.config.batch_size as u64 != 0
-        {
-            bail!(
-                "invalid entry index: start={} end={}",
-                index_start,
-                index_end
-            );
+    /// Drop the leases of the submitted, reverted, or expired tasks. Return the number of the
+    /// tasks that can still be leased under `max_outstanding_seal_leases`.
+    fn drop_finished_leases(
+        &self,
+        to_seal_set: &BTreeMap<usize, u64>,
+        in_flight: &mut BTreeMap<usize, (u64, Instant)>,
+    ) -> usize {
+        let now = Instant::now();
+        in_flight.retain(|seal_index, (version, leased_at)| {
+            to_seal_set.get(seal_index) == Some(version)
+                && now.duration_since(*leased_at) < SEAL_TASK_LEASE_TIMEOUT
+        });
+        metrics::SEAL_LEASES_OUTSTANDING.update(in_flight.len());
+        self.config
+            .max_outstanding_seal_leases
+            .map_or(usize::MAX, |max| max.saturating_sub(in_flight.len()))
+    }
+
+    /// Lease the seal tasks below `seal_index_max` in the first batch that has tasks not
+    /// leased yet, at most as many as `max_outstanding_seal_leases` allows.
+    fn lease_seal_chunk(&self, seal_index_max: usize) -> Result<Vec<SealTask>> {
+        let to_seal_set = self.seal_manager.to_seal_set.read();
+        self.seal_manager.update_pull_time();
+        let mut in_flight = self.seal_manager.in_flight.lock();
+        let now = Instant::now();
+        let available = self.drop_finished_leases(&to_seal_set, &mut in_flight);
+        if available == 0 {
+            return Ok(vec![]);
         }
-        let mut entry_list = Vec::<ChunkArray>::new();
-        for (start_entry_index, _) in batch_iter(index_start, index_end, self.config.batch_size) {
-            let chunk_index = start_entry_index / self.config.batch_size as u64;
 
-            if let Some(mut data_list) = self
-                .data_db
-                .get_entry_batch(chunk_index)?
-                .map(|b| b.into_data_list(start_entry_index))
-            {
-                if data_list.is_empty() {
-                    continue;
-                }
-                // This will not happen for now because we only get entries for the last chunk.
-                if let Some(last) = entry_list.last_mut() {
-                    if last.start_index + bytes_to_entries(last.data.len() as u64)
-                        == data_list[0].start_index
-                    {
-                        // Merge the first element with the previous one.
-                        last.data.append(&mut data_list.remove(0).data);
-                    }
-                }
-                for data in data_list {
-                    entry_list.push(data);
-                }
-            }
+        let batch_index = match to_seal_set
+            .keys()
+            .take_while(|&&seal_index| seal_index < seal_index_max)
+            .find(|seal_index| !in_flight.contains_key(seal_index))
+        {
+            Some(&first_index) => first_index / SEALS_PER_LOAD,
+            None => return Ok(vec![]),
+        };
+        let selected: Vec<(usize, u64)> = to_seal_set
+            .range(
+                batch_index * SEALS_PER_LOAD
+                    ..cmp::min(seal_index_max, (batch_index + 1) * SEALS_PER_LOAD),
+            )
+            .filter(|(seal_index, _)| !in_flight.contains_key(seal_index))
+            .take(available)
+            .map(|(&seal_index, &version)| (seal_index, version))
+            .collect();
+
+        let batch_data = match self.get_entry_batch(batch_index as u64)? {
+            Some(batch_data) => batch_data,
+            None => bail!(Error::SealStateDesync {
+                seal_index: selected[0].0 as u64,
+            }),
+        };
+        let mut tasks = Vec::with_capacity(selected.len());
+        for (seal_index, version) in selected {
+            let non_sealed_data =
+                match batch_data.get_non_sealed_data((seal_index % SEALS_PER_LOAD) as u16) {
+                    Some(data) => data,
+                    None => bail!(Error::SealStateDesync {
+                        seal_index: seal_index as u64,
+                    }),
+                };
+            in_flight.insert(seal_index, (version, now));
+            tasks.push(SealTask {
+                seal_index: seal_index as u64,
+                version,
+                non_sealed_data,
+            });
         }
-        Ok(entry_list)
+        metrics::SEAL_LEASES_OUTSTANDING.update(in_flight.len());
+        Ok(tasks)
     }
 
-    fn load_sealed_data(&self, chunk_index: u64) -> Result<Option<MineLoadChunk>> {
+    /// The batches buffered by `coalesce_small_appends` are not checked, because sealing
+    /// flushes the buffer and appends do not change the sealed data.
+    fn load_sealed_data_from_db(&self, chunk_index: u64) -> Result<Option<MineLoadChunk>> {
         let batch = try_option!(self.data_db.get_entry_batch(chunk_index)?);
         let mut mine_chunk = MineLoadChunk::default();
         for (seal_index, (sealed, validity)) in mine_chunk
@@ -193,459 +883,5803 @@ impl FlowRead for FlowStore {
         Ok(Some(mine_chunk))
     }
 
-    fn get_num_entries(&self) -> Result<u64> {
-        // This is an over-estimation as it assumes each batch is full.
-        self.data_db
-            .kvdb
-            .num_keys(COL_ENTRY_BATCH)
-            .map(|num_batches| num_batches * PORA_CHUNK_SIZE as u64)
-            .map_err(Into::into)
+    /// Write the batches in one db transaction and return the roots of the completed ones.
+    /// The written batches are removed from `append_buffer`. See `append_entries_with_roots`
+    /// for `supplied_roots`. The quarantine entries of the `restored` batches are deleted in
+    /// the same transaction.
+    fn write_entry_batch_list(
+        &self,
+        batch_list: Vec<(u64, EntryBatch)>,
+        append_buffer: &mut AppendBuffer,
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        restored: &HashSet<u64>,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        if self.config.append_write_window_bytes.is_some() {
+            return self.add_to_write_window(batch_list, append_buffer, supplied_roots, restored);
+        }
+        let updated_indices: Vec<u64> = batch_list.iter().map(|(index, _)| *index).collect();
+        let result = self.data_db.put_entry_batch_list(
+            batch_list,
+            supplied_roots,
+            restored,
+            self.config.verify_supplied_batch_roots,
+        );
+        self.invalidate_batch_caches(updated_indices.iter().copied());
+        if result.is_ok() {
+            for index in &updated_indices {
+                append_buffer.remove(index);
+            }
+        }
+        result
     }
 
-    fn get_shard_config(&self) -> ShardConfig {
-        *self.config.shard_config.read()
-    }
+    /// Add the writes of `batch_list` to the write window, and write the window to db if it
+    /// has reached `append_write_window_bytes` or is older than `append_write_window_interval`.
+    fn add_to_write_window(
+        &self,
+        batch_list: Vec<(u64, EntryBatch)>,
+        append_buffer: &mut AppendBuffer,
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        restored: &HashSet<u64>,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        if batch_list.is_empty() {
+            return Ok(Vec::new());
+        }
+        // The batches are added to a separate transaction first, so a failed one leaves the
+        // window unchanged.
+        let mut tx = DBTransaction::new();
+        let completed_batches = self.data_db.add_entry_batch_list(
+            &mut tx,
+            &batch_list,
+            supplied_roots,
+            restored,
+            self.config.verify_supplied_batch_roots,
+        )?;
+        let updated_indices: Vec<u64> = batch_list.iter().map(|(index, _)| *index).collect();
+        let mut window = self.write_window.lock();
+        window.bytes += tx_size(&tx);
+        window.tx.ops.append(&mut tx.ops);
+        window.opened_at.get_or_insert_with(Instant::now);
+        for (index, batch) in batch_list {
+            append_buffer.remove(&index);
+            window.batches.insert(index, batch);
+        }
+        self.invalidate_batch_caches(updated_indices);
+        let full = window.bytes >= self.config.append_write_window_bytes.unwrap_or(0)
+            || window.opened_at.map_or(false, |opened_at| {
+                opened_at.elapsed() >= self.config.append_write_window_interval
+            });
+        if full {
+            self.flush_write_window(&mut window)?;
+        }
+        Ok(completed_batches)
+    }
+
+    /// Write the write window to db in one transaction. The appends in the window have
+    /// returned and their seals are queued, so a failed write keeps the window, and its
+    /// batches are written by the next flush.
+    fn flush_write_window(&self, window: &mut WriteWindow) -> Result<()> {
+        if window.batches.is_empty() {
+            return Ok(());
+        }
+        metrics::APPEND_WRITE_WINDOW_FLUSH.mark(1);
+        self.data_db
+            .write_tx("append_write_window", window.tx.clone())?;
+        window.tx = DBTransaction::new();
+        window.bytes = 0;
+        window.opened_at = None;
+        let updated_indices: Vec<u64> = mem::take(&mut window.batches).into_keys().collect();
+        self.invalidate_batch_caches(updated_indices);
+        Ok(())
+    }
+
+    /// Iterate over the indices of the batches in db, in the write window and in the append
+    /// buffer in increasing order, so the scans also see the appends not committed yet.
+    fn iter_batch_indices_with_pending(&self) -> impl Iterator<Item = Result<u64>> + '_ {
+        let mut pending: Vec<u64> = self.append_buffer.lock().keys().copied().collect();
+        pending.extend(self.write_window.lock().batches.keys());
+        pending.sort_unstable();
+        pending.dedup();
+        self.data_db
+            .iter_batch_indices()
+            .merge_by(pending.into_iter().map(Ok), |a, b| match (a, b) {
+                (Ok(a), Ok(b)) => a <= b,
+                _ => true,
+            })
+            .dedup_by(|a, b| matches!((a, b), (Ok(a), Ok(b)) if a == b))
+    }
+
+    /// Return the batch in the append buffer, the write window or db, without recording the
+    /// read.
+    fn get_batch_with_pending(&self, batch_index: u64) -> Result<Option<EntryBatch>> {
+        let buffered = self
+            .append_buffer
+            .lock()
+            .get(&batch_index)
+            .map(|(batch, _)| batch.clone());
+        match buffered {
+            Some(batch) => Ok(Some(batch)),
+            None => self.get_written_batch(batch_index),
+        }
+    }
+
+    /// Return the batch in the write window or, if it is not there, in db.
+    fn get_written_batch(&self, batch_index: u64) -> Result<Option<EntryBatch>> {
+        if let Some(batch) = self.write_window.lock().batches.get(&batch_index) {
+            return Ok(Some(batch.clone()));
+        }
+        self.data_db.get_entry_batch(batch_index)
+    }
+
+    /// Wait for the sealed batches written by `submit_seal_result` after it releases the seal
+    /// lock. Call it with the seal lock held before the batches are read for a write, so the
+    /// write is not lost or overwritten.
+    fn wait_for_seal_writes(&self) {
+        if self.config.release_seal_lock_before_write {
+            drop(self.seal_write_lock.lock());
+        }
+    }
+
+    /// Write the sealed batches in transactions of `seal_result_write_chunk_size` batches. On
+    /// a failure, return the error with the seal tasks removed for the batches not written.
+    fn write_sealed_batches(
+        &self,
+        mut updated: Vec<SealedBatch>,
+    ) -> std::result::Result<(), (anyhow::Error, Vec<(usize, u64)>)> {
+        let chunk_size = self
+            .config
+            .seal_result_write_chunk_size
+            .map_or(usize::MAX, |size| cmp::max(size, 1));
+        while !updated.is_empty() {
+            let rest = updated.split_off(cmp::min(chunk_size, updated.len()));
+            let mut batch_list = Vec::with_capacity(updated.len());
+            let mut removed = Vec::new();
+            for (batch_index, batch, removed_in_batch) in updated {
+                batch_list.push((batch_index, batch));
+                removed.extend(removed_in_batch);
+            }
+            let updated_indices: Vec<u64> = batch_list.iter().map(|(index, _)| *index).collect();
+            let result = self.data_db.put_entry_raw(batch_list);
+            self.invalidate_batch_caches(updated_indices);
+            if let Err(e) = result {
+                removed.extend(rest.into_iter().flat_map(|(_, _, removed)| removed));
+                return Err((e, removed));
+            }
+            updated = rest;
+        }
+        Ok(())
+    }
+
+    /// Queue again the seal tasks removed for the batches that failed to be written, with
+    /// their versions. A task queued meanwhile, e.g. by a truncate, is kept. With `check_db`,
+    /// which is needed if the seal lock was released before the write, a task is dropped if
+    /// its batch is gone or its seal has no unsealed data in db.
+    fn requeue_unwritten_seals(
+        &self,
+        to_seal_set: &mut BTreeMap<usize, u64>,
+        unwritten: Vec<(usize, u64)>,
+        check_db: bool,
+    ) {
+        let mut requeued = 0;
+        for (batch_index, tasks) in &unwritten
+            .into_iter()
+            .chunk_by(|(seal_index, _)| (seal_index / SEALS_PER_LOAD) as u64)
+        {
+            // The tasks are queued if the batch can not be read, since a missing task is
+            // never noticed, but a desync is reported by `pull_seal_chunk`.
+            let batch = check_db.then(|| self.data_db.get_entry_batch(batch_index));
+            for (seal_index, version) in tasks {
+                let unsealed = match &batch {
+                    Some(Ok(Some(batch))) => batch
+                        .get_non_sealed_data((seal_index % SEALS_PER_LOAD) as u16)
+                        .is_some(),
+                    Some(Ok(None)) => false,
+                    None | Some(Err(_)) => true,
+                };
+                if unsealed {
+                    if let Entry::Vacant(e) = to_seal_set.entry(seal_index) {
+                        e.insert(version);
+                        requeued += 1;
+                    }
+                }
+            }
+        }
+        if requeued > 0 {
+            warn!(
+                "Requeue seal tasks of unwritten batches: count={}",
+                requeued
+            );
+            self.seal_manager.notify_new_tasks();
+        }
+    }
+
+    /// Drop the cached sealed data and proofs of the batches. This must be called after the
+    /// batches are written to db.
+    fn invalidate_batch_caches(&self, batch_list: impl IntoIterator<Item = u64>) {
+        let batch_list: HashSet<u64> = batch_list.into_iter().collect();
+        if let Some(cache) = &self.sealed_data_cache {
+            let mut cache = cache.lock();
+            for batch_index in &batch_list {
+                cache.pop(batch_index);
+            }
+        }
+        if let Some(cache) = &self.proof_cache {
+            let mut cache = cache.lock();
+            cache.epoch += 1;
+            let removed: Vec<(u64, usize)> = cache
+                .proofs
+                .iter()
+                .map(|(key, _)| *key)
+                .filter(|(batch_index, _)| batch_list.contains(batch_index))
+                .collect();
+            for key in removed {
+                cache.proofs.pop(&key);
+            }
+        }
+    }
+
+    fn clear_batch_caches(&self) {
+        if let Some(cache) = &self.sealed_data_cache {
+            cache.lock().clear();
+        }
+        if let Some(cache) = &self.proof_cache {
+            let mut cache = cache.lock();
+            cache.epoch += 1;
+            cache.proofs.clear();
+        }
+    }
+
+    /// Return the index of the batch whose completed root is `root`. Batches with identical
+    /// data share a root, and the lowest index of them is returned in this case.
+    pub fn batch_index_of_root(&self, root: &DataRoot) -> Result<Option<u64>> {
+        self.data_db.get_batch_index_of_root(root)
+    }
+
+    /// Replace the root index with the roots of the stored complete batches, dropping the
+    /// entries left behind by truncates and deletes. Return the number of entries written.
+    /// The incomplete batches have no single root and are not indexed, like when they are
+    /// appended. The batches are indexed in transactions of `delete_batch_chunk_size` batches.
+    /// `LogManager` runs it when it is opened if `self_check` is set.
+    pub fn rebuild_root_index(&self) -> Result<usize> {
+        self.ensure_writable()?;
+        let end_batch = match self.flow_length() {
+            Some(flow_length) => self.config.expected_batch_count(flow_length),
+            None => match self.data_db.iter_batch_indices().last() {
+                Some(batch_index) => batch_index? + 1,
+                None => 0,
+            },
+        };
+        // The index is cleared first, so the batches indexed by the appends during the rebuild
+        // are kept. The buffered batches are incomplete and have no root, and the write window
+        // indexes its complete batches when it is committed, so neither is flushed.
+        {
+            let _to_seal_set = self.seal_manager.to_seal_set.write();
+            self.data_db.clear_root_index()?;
+        }
+        let chunk_size = cmp::max(self.config.delete_batch_chunk_size, 1) as u64;
+        let mut count = 0;
+        let mut chunk_start = 0;
+        while chunk_start < end_batch {
+            let chunk_end = cmp::min(chunk_start + chunk_size, end_batch);
+            // The lock is taken per chunk, so the appends and the seal submissions only wait
+            // for one chunk.
+            let _to_seal_set = self.seal_manager.to_seal_set.write();
+            count += self.data_db.index_batch_roots(chunk_start, chunk_end)?;
+            chunk_start = chunk_end;
+        }
+        Ok(count)
+    }
+
+    /// Return the flow root stored as the only node of the top MPT layer, without loading the
+    /// tree. `None` if the flow is empty. This is the root the flow merkle tree has committed
+    /// to db, so it can be compared with the on-chain root.
+    pub fn flow_root(&self) -> Result<Option<DataRoot>> {
+        let mut num_layers = 0;
+        let mut top_layer_size = 0;
+        while let Some(size) = self.flow_db.get_layer_size(num_layers)? {
+            top_layer_size = size;
+            num_layers += 1;
+        }
+        if num_layers == 0 || top_layer_size == 0 {
+            return Ok(None);
+        }
+        let top_layer = num_layers - 1;
+        if top_layer_size != 1 {
+            bail!(
+                "invalid top MPT layer: layer={} size={}",
+                top_layer,
+                top_layer_size
+            );
+        }
+        match self.flow_db.get_node(top_layer, 0)? {
+            Some(root) => Ok(Some(root)),
+            None => bail!("flow root missing: layer={}", top_layer),
+        }
+    }
+
+    /// Check the committed flow root against the root expected by the chain. On a mismatch,
+    /// recompute the roots of the stored complete batches in increasing order and compare them
+    /// with the MPT leaves to find where the local flow diverges. The buffered appends of
+    /// `coalesce_small_appends` only hold incomplete batches, so they are not checked.
+    pub fn verify_commitment(&self, expected_flow_root: &DataRoot) -> Result<CommitmentCheck> {
+        let local_root = self.flow_root()?;
+        if local_root.as_ref() == Some(expected_flow_root) {
+            return Ok(CommitmentCheck::Match);
+        }
+        let mut first_divergent_batch = None;
+        for batch_index in self.iter_batch_indices_with_pending() {
+            let batch_index = batch_index?;
+            let batch = match self.get_written_batch(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            let root = match batch.build_root(batch_index == 0)? {
+                Some(root) => root,
+                None => continue,
+            };
+            if self.flow_db.get_node(0, batch_index as usize)? != Some(root) {
+                first_divergent_batch = Some(batch_index);
+                break;
+            }
+        }
+        warn!(
+            "Flow commitment mismatch: expected={:?} local={:?} first_divergent_batch={:?}",
+            expected_flow_root, local_root, first_divergent_batch
+        );
+        Ok(CommitmentCheck::Mismatch {
+            local_root,
+            first_divergent_batch,
+        })
+    }
+
+    /// Check the pad data against each other and the flow. The pads are visited in the order of
+    /// tx seq, and a pad is reported if it starts before the end of an earlier pad, ends after
+    /// the flow length, or covers stored sectors with non-zero data. The batches that are not
+    /// stored, e.g. out of the shard range, are not checked for data.
+    pub fn verify_pad_consistency(&self) -> Result<Vec<PadInconsistency>> {
+        let flow_length = self.flow_length();
+        let mut inconsistencies = Vec::new();
+        let mut last_end = 0;
+        for tx_seq in self.flow_db.pad_data_tx_seqs()? {
+            let pad_list = match self.flow_db.get_pad_data(tx_seq)? {
+                Some(pad_list) => pad_list,
+                None => continue,
+            };
+            for pad in pad_list {
+                let start_index = pad.start_index;
+                let end_index = start_index + self.bytes_to_sectors(pad.data_size as usize);
+                let mut report = |kind| {
+                    inconsistencies.push(PadInconsistency {
+                        tx_seq,
+                        start_index,
+                        end_index,
+                        kind,
+                    })
+                };
+                if start_index < last_end {
+                    report(PadInconsistencyKind::Overlap);
+                }
+                if matches!(flow_length, Some(flow_length) if end_index > flow_length) {
+                    report(PadInconsistencyKind::BeyondFlowEnd);
+                }
+                if self.has_data_in_range(start_index, end_index)? {
+                    report(PadInconsistencyKind::RealData);
+                }
+                last_end = cmp::max(last_end, end_index);
+            }
+        }
+        if !inconsistencies.is_empty() {
+            warn!(
+                "Pad data inconsistent: count={} first={:?}",
+                inconsistencies.len(),
+                inconsistencies[0]
+            );
+        }
+        Ok(inconsistencies)
+    }
+
+    /// Whether any stored sector in `[start, end)` has non-zero data.
+    fn has_data_in_range(&self, start: u64, end: u64) -> Result<bool> {
+        for (start_entry_index, end_entry_index) in
+            batch_iter_lazy(start, end, self.config.batch_size)
+        {
+            let chunk_index = start_entry_index / self.config.batch_size as u64;
+            let offset = start_entry_index - chunk_index * self.config.batch_size as u64;
+            let length = end_entry_index - start_entry_index;
+            let data = match self.get_entry_batch(chunk_index)? {
+                Some(batch) => batch.get_unsealed_data(offset as usize, length as usize),
+                None => None,
+            };
+            if data.is_some_and(|data| data.iter().any(|b| *b != 0)) {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Repair the recorded MPT layer sizes from the stored nodes, and return the corrected
+    /// sizes. Nothing is written if they already agree. This must be called before the flow
+    /// merkle tree is loaded from db.
+    pub fn recompute_layer_sizes(&self) -> Result<Vec<usize>> {
+        self.ensure_writable()?;
+        self.flow_db.recompute_layer_sizes()
+    }
+
+    /// Return the roots of all the complete batches in `[start_batch, end_batch)` in increasing
+    /// order of the batch index. The incomplete and absent batches are skipped.
+    pub fn completed_roots(
+        &self,
+        start_batch: u64,
+        end_batch: u64,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        let mut roots = Vec::new();
+        for batch_index in start_batch..end_batch {
+            let batch = match self.get_entry_batch(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            if let Some(root) = batch.build_root(batch_index == 0)? {
+                roots.push((batch_index, root));
+            }
+        }
+        Ok(roots)
+    }
+
+    /// Read the sectors `start, start + stride, ...` below `end`, and return them as
+    /// `(sector_index, data)` pairs. Each batch is loaded once. Return `None` if any of the
+    /// sectors is missing. The first sector of the flow has no data and is skipped.
+    pub fn get_entries_strided(
+        &self,
+        start: u64,
+        end: u64,
+        stride: usize,
+    ) -> Result<Option<Vec<(u64, Vec<u8>)>>> {
+        if end <= start || stride == 0 {
+            bail!(
+                "invalid strided range: start={} end={} stride={}",
+                start,
+                end,
+                stride
+            );
+        }
+        let batch_size = self.config.batch_size as u64;
+        let mut entries = Vec::new();
+        let mut loaded: Option<(u64, EntryBatch)> = None;
+        for sector_index in (start..end).step_by(stride) {
+            if sector_index == 0 {
+                continue;
+            }
+            let batch_index = sector_index / batch_size;
+            if loaded.as_ref().map(|(index, _)| *index) != Some(batch_index) {
+                let batch = try_option!(self.get_entry_batch(batch_index)?);
+                loaded = Some((batch_index, batch));
+            }
+            let (_, batch) = loaded.as_ref().expect("loaded above");
+            let data = try_option!(
+                batch.get_unsealed_data((sector_index - batch_index * batch_size) as usize, 1)
+            );
+            entries.push((sector_index, data));
+        }
+        Ok(Some(entries))
+    }
+
+    /// Like `get_entries`, but the data are written to `buf`, which is cleared first, so a read
+    /// loop can reuse one buffer. Return the start index of the data, or `None` if any sector is
+    /// missing, in which case `buf` is left empty.
+    pub fn get_entries_into(
+        &self,
+        index_start: u64,
+        index_end: u64,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<u64>> {
+        buf.clear();
+        if index_end <= index_start {
+            bail!(
+                "invalid entry index: start={} end={}",
+                index_start,
+                index_end
+            );
+        }
+        if self.config.reject_reads_past_flow_end {
+            if let Some(flow_length) = self.flow_length() {
+                if index_start >= flow_length {
+                    bail!(Error::BeyondFlowEnd {
+                        requested: index_start,
+                        flow_length,
+                    });
+                }
+            }
+        }
+        let _permit = self.read_limiter.as_ref().map(ReadLimiter::acquire);
+        buf.reserve((index_end - index_start) as usize * self.config.sector_size);
+        for (start_entry_index, end_entry_index) in
+            batch_iter_lazy(index_start, index_end, self.config.batch_size)
+        {
+            let chunk_index = start_entry_index / self.config.batch_size as u64;
+            let mut offset = start_entry_index - chunk_index * self.config.batch_size as u64;
+            let mut length = end_entry_index - start_entry_index;
+
+            // Tempfix: for first chunk, its offset is always 1
+            if chunk_index == 0 && offset == 0 {
+                metrics::FIRST_CHUNK_OFFSET_FIX.mark(1);
+                offset = 1;
+                length -= 1;
+            }
+
+            let entry_batch_data = match self.get_entry_batch(chunk_index)? {
+                Some(entry_batch) => {
+                    entry_batch.get_unsealed_data(offset as usize, length as usize)
+                }
+                None => None,
+            };
+            match entry_batch_data {
+                Some(entry_batch_data) => buf.extend_from_slice(&entry_batch_data),
+                None => {
+                    buf.clear();
+                    return Ok(None);
+                }
+            }
+        }
+        Ok(Some(index_start))
+    }
+
+    /// Read the parts of `[start, end)` in the batches of our shard, skipping the batches of
+    /// the other shards and the missing ones instead of returning `None` for the whole range.
+    /// The contiguous parts are merged like `get_available_entries`.
+    pub fn get_entries_owned(&self, start: u64, end: u64) -> Result<Vec<ChunkArray>> {
+        if end <= start {
+            bail!("invalid entry index: start={} end={}", start, end);
+        }
+        let shard_config = *self.config.shard_config.read();
+        let mut entry_list = Vec::<ChunkArray>::new();
+        for (start_entry_index, end_entry_index) in
+            batch_iter_sharded_lazy(start, end, self.config.batch_size, shard_config)
+        {
+            let mut entries = match self.get_entries(start_entry_index, end_entry_index)? {
+                Some(entries) => entries,
+                None => continue,
+            };
+            if let Some(last) = entry_list.last_mut() {
+                if last.start_index + self.bytes_to_sectors(last.data.len()) == entries.start_index
+                {
+                    last.data.append(&mut entries.data);
+                    continue;
+                }
+            }
+            entry_list.push(entries);
+        }
+        Ok(entry_list)
+    }
+
+    /// Like `get_entries`, but the sectors of the sealed seals are returned as they are stored,
+    /// sealed, instead of being unsealed. The data are split into regions of the same form,
+    /// e.g. for a replicator that copies the raw storage. Return `None` if any sector is
+    /// missing. As in `get_entries`, the reserved first sector of the flow is skipped.
+    pub fn get_entries_any(&self, start: u64, end: u64) -> Result<Option<ChunkArrayTagged>> {
+        if end <= start {
+            bail!("invalid entry index: start={} end={}", start, end);
+        }
+        let batch_size = self.config.batch_size as u64;
+        let mut regions: Vec<TaggedRegion> = Vec::new();
+        for (start_entry_index, end_entry_index) in
+            batch_iter_lazy(start, end, self.config.batch_size)
+        {
+            let batch_index = start_entry_index / batch_size;
+            let batch_start = batch_index * batch_size;
+            let mut sector = (start_entry_index - batch_start) as usize;
+            let end_sector = (end_entry_index - batch_start) as usize;
+            if batch_index == 0 && sector == 0 {
+                sector = 1;
+            }
+            let entry_batch = try_option!(self.get_entry_batch(batch_index)?);
+            while sector < end_sector {
+                let seal_index = sector / SECTORS_PER_SEAL;
+                let seal_start = seal_index * SECTORS_PER_SEAL;
+                let seal_end = cmp::min(seal_start + SECTORS_PER_SEAL, end_sector);
+                let (sealed, mut data) = match entry_batch.get_sealed_data(seal_index as u16) {
+                    Some(sealed_data) => (
+                        true,
+                        sealed_data[(sector - seal_start) * BYTES_PER_SECTOR
+                            ..(seal_end - seal_start) * BYTES_PER_SECTOR]
+                            .to_vec(),
+                    ),
+                    None => (
+                        false,
+                        try_option!(entry_batch.get_unsealed_data(sector, seal_end - sector)),
+                    ),
+                };
+                let start_index = batch_start + sector as u64;
+                match regions.last_mut() {
+                    Some(last)
+                        if last.sealed == sealed
+                            && last.start_index + self.bytes_to_sectors(last.data.len())
+                                == start_index =>
+                    {
+                        last.data.append(&mut data)
+                    }
+                    _ => regions.push(TaggedRegion {
+                        start_index,
+                        sealed,
+                        data,
+                    }),
+                }
+                sector = seal_end;
+            }
+        }
+        Ok(Some(ChunkArrayTagged {
+            start_index: start,
+            regions,
+        }))
+    }
+
+    /// Return the sealed bytes of one seal in a batch, or `None` if the batch is absent or the
+    /// seal is not sealed. Unlike `load_sealed_data`, the other seals of the batch are not copied.
+    pub fn get_sealed_seal(&self, batch_index: u64, seal_index: u16) -> Result<Option<Vec<u8>>> {
+        if seal_index as usize >= SEALS_PER_LOAD {
+            bail!("invalid seal index: {}", seal_index);
+        }
+        let batch = try_option!(self.get_entry_batch(batch_index)?);
+        Ok(batch.get_sealed_data(seal_index).map(|data| data.to_vec()))
+    }
+
+    /// Return the indices in the batch of its sealed seals in ascending order, or `None` if the
+    /// batch is absent.
+    pub fn sealed_seal_indices(&self, batch_index: u64) -> Result<Option<Vec<u16>>> {
+        let batch = try_option!(self.get_entry_batch(batch_index)?);
+        Ok(Some(
+            (0..SEALS_PER_LOAD as u16)
+                .filter(|seal_index| batch.get_sealed_data(*seal_index).is_some())
+                .collect(),
+        ))
+    }
+
+    /// Read `count` distinct sectors of a complete batch with their proofs against the batch
+    /// root. The sectors are selected from `seed` deterministically, so a verifier with the
+    /// same seed can check that the expected sectors are returned.
+    /// Return `(sector_index_in_batch, data, proof)` in the selection order.
+    pub fn sample_batch(
+        &self,
+        batch_index: u64,
+        seed: [u8; 32],
+        count: usize,
+    ) -> Result<Vec<(usize, ChunkArray, FlowProof)>> {
+        let batch = self
+            .get_entry_batch(batch_index)?
+            .ok_or_else(|| anyhow!("batch missing, index={}", batch_index))?;
+        let is_first_chunk = batch_index == 0;
+        // The merkle tree is built once for all the samples.
+        let merkle = self
+            .batch_merkle_tree(batch_index, &batch)?
+            .ok_or_else(|| {
+                anyhow!(
+                    "batch data incomplete for building a merkle tree, index={}",
+                    batch_index
+                )
+            })?;
+        // The first sector of the flow is a padding leaf without data.
+        let first_sector = is_first_chunk as usize;
+        if count > self.config.batch_size - first_sector {
+            bail!(
+                "too many samples: count={} batch_size={}",
+                count,
+                self.config.batch_size
+            );
+        }
+
+        let mut selected = Vec::with_capacity(count);
+        let mut counter = 0u64;
+        while selected.len() < count {
+            let hash = Sha3Algorithm::leaf_raw(&[&seed[..], &counter.to_be_bytes()].concat());
+            counter += 1;
+            let value = u64::from_be_bytes(hash.0[..8].try_into().unwrap());
+            let sector_index =
+                first_sector + (value % (self.config.batch_size - first_sector) as u64) as usize;
+            if !selected.contains(&sector_index) {
+                selected.push(sector_index);
+            }
+        }
+
+        let mut samples = Vec::with_capacity(count);
+        for sector_index in selected {
+            let data = batch.get_unsealed_data(sector_index, 1).ok_or_else(|| {
+                anyhow!(
+                    "sector missing in batch, batch_index={} sector_index={}",
+                    batch_index,
+                    sector_index
+                )
+            })?;
+            let chunk = ChunkArray {
+                data,
+                start_index: batch_index * self.config.batch_size as u64 + sector_index as u64,
+            };
+            samples.push((sector_index, chunk, merkle.gen_proof(sector_index)?));
+        }
+        Ok(samples)
+    }
+
+    /// Return the data of a sector with its proof and the root of its batch, which are built
+    /// from one merkle tree. Return `None` if the batch is absent or incomplete.
+    pub fn get_sector_with_proof(
+        &self,
+        batch_index: u64,
+        sector_index: usize,
+    ) -> Result<Option<(Vec<u8>, FlowProof, DataRoot)>> {
+        let batch = try_option!(self.get_entry_batch(batch_index)?);
+        let merkle = try_option!(self.batch_merkle_tree(batch_index, &batch)?);
+        let data = batch.get_unsealed_data(sector_index, 1).ok_or_else(|| {
+            anyhow!(
+                "sector missing in batch, batch_index={} sector_index={}",
+                batch_index,
+                sector_index
+            )
+        })?;
+        Ok(Some((data, merkle.gen_proof(sector_index)?, merkle.root())))
+    }
+
+    /// Write the batches of `shard` to `writer` for another node to bootstrap from, and return
+    /// the number of exported batches. The data are exported unsealed, because the seals are
+    /// specific to the miner. Each batch is a record of
+    /// `EXPORT_RECORD_BATCH | batch_index: u64 | length: u32 | ssz(Vec<ChunkArray>)`, and the
+    /// records are followed by `EXPORT_RECORD_TRAILER | count: u64 | keccak(records)`.
+    pub fn export_shard(&self, shard: ShardConfig, writer: impl Write) -> Result<u64> {
+        self.export_shard_inner(shard, false, writer)
+    }
+
+    /// Like `export_shard`, but only the complete batches are exported, each with its root in
+    /// a record of `EXPORT_RECORD_COMPLETE_BATCH | batch_index: u64 | root: H256 | length: u32 |
+    /// ssz(Vec<ChunkArray>)`. `import_shard` checks the root of each batch before it is
+    /// appended, so the snapshot can be verified without trusting its source.
+    pub fn export_complete_shard(&self, shard: ShardConfig, writer: impl Write) -> Result<u64> {
+        self.export_shard_inner(shard, true, writer)
+    }
+
+    fn export_shard_inner(
+        &self,
+        shard: ShardConfig,
+        complete_only: bool,
+        mut writer: impl Write,
+    ) -> Result<u64> {
+        let end_batch = match self.iter_batch_indices_with_pending().last() {
+            Some(batch_index) => batch_index? + 1,
+            None => 0,
+        };
+        let batch_size = self.config.batch_size as u64;
+        let mut hasher = Keccak::v256();
+        let mut count = 0u64;
+        for (batch_start, _) in
+            batch_iter_sharded_lazy(0, end_batch * batch_size, self.config.batch_size, shard)
+        {
+            let batch_index = batch_start / batch_size;
+            let batch = match self.get_batch_with_pending(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            let root = if complete_only {
+                match batch.build_root(batch_index == 0)? {
+                    Some(root) => Some(root),
+                    None => continue,
+                }
+            } else {
+                None
+            };
+            let payload = batch
+                .into_data_list(batch_index * batch_size)
+                .as_ssz_bytes();
+            let mut record = match root {
+                Some(_) => vec![EXPORT_RECORD_COMPLETE_BATCH],
+                None => vec![EXPORT_RECORD_BATCH],
+            };
+            record.extend_from_slice(&batch_index.to_be_bytes());
+            if let Some(root) = root {
+                record.extend_from_slice(root.as_bytes());
+            }
+            record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+            record.extend_from_slice(&payload);
+            hasher.update(&record);
+            writer.write_all(&record)?;
+            count += 1;
+        }
+        let mut checksum = [0u8; 32];
+        hasher.finalize(&mut checksum);
+        writer.write_all(&[EXPORT_RECORD_TRAILER])?;
+        writer.write_all(&count.to_be_bytes())?;
+        writer.write_all(&checksum)?;
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Append the batches written by `export_shard` or `export_complete_shard`, and return the
+    /// number of imported batches. Every batch must be in our shard range, and a batch with a
+    /// root must match it. The batches are appended as they are read, so if an error is
+    /// returned, the imported data may be incomplete or corrupted and should be truncated by
+    /// the caller.
+    pub fn import_shard(&self, mut reader: impl Read) -> Result<u64> {
+        let shard_config = *self.config.shard_config.read();
+        let mut hasher = Keccak::v256();
+        let mut count = 0u64;
+        loop {
+            match read_export_record(&mut reader, &mut hasher)? {
+                ExportRecord::Batch {
+                    batch_index,
+                    root,
+                    payload,
+                } => {
+                    if !shard_config.in_range(batch_index) {
+                        bail!(
+                            "imported batch not in shard range: batch_index={} shard_config={:?}",
+                            batch_index,
+                            shard_config
+                        );
+                    }
+                    let chunks = self.decode_export_batch(batch_index, &payload)?;
+                    if let Some(root) = root {
+                        let actual = self.export_batch_root(batch_index, &chunks)?;
+                        if actual != Some(root) {
+                            bail!(
+                                "imported batch root mismatch: batch_index={} root={:?} actual={:?}",
+                                batch_index,
+                                root,
+                                actual
+                            );
+                        }
+                    }
+                    for chunk in chunks {
+                        self.append_entries(chunk)?;
+                    }
+                    count += 1;
+                }
+                ExportRecord::Trailer {
+                    count: expected_count,
+                    checksum_matches,
+                } => {
+                    if expected_count != count || !checksum_matches {
+                        bail!(
+                            "export checksum mismatch: count={} expected_count={}",
+                            count,
+                            expected_count
+                        );
+                    }
+                    return Ok(count);
+                }
+            }
+        }
+    }
+
+    /// Check an archive written by `export_shard` or `export_complete_shard` without writing
+    /// to db: the framing of each record, the data of each batch, the roots of the batches that
+    /// have them, and the trailer. The problems of a batch are collected in the report, and the
+    /// check stops at the first framing error, since the rest of the stream can not be parsed.
+    pub fn validate_archive(&self, mut reader: impl Read) -> Result<ArchiveReport> {
+        let shard_config = *self.config.shard_config.read();
+        let mut hasher = Keccak::v256();
+        let mut report = ArchiveReport::default();
+        loop {
+            let record = match read_export_record(&mut reader, &mut hasher) {
+                Ok(record) => record,
+                Err(e) => {
+                    report.framing_error = Some(e.to_string());
+                    return Ok(report);
+                }
+            };
+            match record {
+                ExportRecord::Batch {
+                    batch_index,
+                    root,
+                    payload,
+                } => {
+                    report.batches += 1;
+                    if !shard_config.in_range(batch_index) {
+                        report.out_of_shard.push(batch_index);
+                    }
+                    let chunks = match self.decode_export_batch(batch_index, &payload) {
+                        Ok(chunks) => chunks,
+                        Err(e) => {
+                            debug!(
+                                "Invalid archive batch: batch_index={} e={:?}",
+                                batch_index, e
+                            );
+                            report.decode_failures.push(batch_index);
+                            continue;
+                        }
+                    };
+                    if let Some(root) = root {
+                        report.checked_roots += 1;
+                        match self.export_batch_root(batch_index, &chunks) {
+                            Ok(actual) if actual == Some(root) => {}
+                            Ok(_) => report.root_mismatches.push(batch_index),
+                            Err(e) => {
+                                debug!(
+                                    "Invalid archive batch: batch_index={} e={:?}",
+                                    batch_index, e
+                                );
+                                report.decode_failures.push(batch_index);
+                            }
+                        }
+                    }
+                }
+                ExportRecord::Trailer {
+                    count,
+                    checksum_matches,
+                } => {
+                    report.trailer_valid = count == report.batches && checksum_matches;
+                    return Ok(report);
+                }
+            }
+        }
+    }
+
+    /// Decode the data of an export record and check that they are within the batch.
+    fn decode_export_batch(&self, batch_index: u64, payload: &[u8]) -> Result<Vec<ChunkArray>> {
+        let batch_size = self.config.batch_size as u64;
+        let batch_start = batch_index * batch_size;
+        let chunks = Vec::<ChunkArray>::from_ssz_bytes(payload).map_err(Error::from)?;
+        for chunk in &chunks {
+            let chunk_end = chunk.start_index + self.bytes_to_sectors(chunk.data.len());
+            if chunk.start_index < batch_start || chunk_end > batch_start + batch_size {
+                bail!(
+                    "imported data out of batch: batch_index={} start={} end={}",
+                    batch_index,
+                    chunk.start_index,
+                    chunk_end
+                );
+            }
+        }
+        Ok(chunks)
+    }
+
+    /// Build the root of an exported batch, or `None` if the data are incomplete.
+    fn export_batch_root(
+        &self,
+        batch_index: u64,
+        chunks: &[ChunkArray],
+    ) -> Result<Option<DataRoot>> {
+        let batch_start = batch_index * self.config.batch_size as u64;
+        let mut batch = EntryBatch::new(batch_index);
+        for chunk in chunks {
+            batch.insert_data(
+                (chunk.start_index - batch_start) as usize,
+                chunk.data.clone(),
+            )?;
+        }
+        batch.build_root(batch_index == 0)
+    }
+
+    /// Like `truncate`, but the removed complete batches with sealed data are quarantined if
+    /// `seal_quarantine_window` is set. In a shallow reorg, the same data are usually appended
+    /// again at the same position, so their seals can be restored instead of recomputed.
+    pub fn truncate_soft(&self, start_index: u64) -> Result<()> {
+        self.ensure_writable()?;
+        self.check_truncate_allowed(start_index)?;
+        if let Some(window) = self.config.seal_quarantine_window {
+            self.flush_append_buffer()?;
+            let batch_size = self.config.batch_size as u64;
+            let first_removed_batch = (start_index + batch_size - 1) / batch_size;
+            self.data_db
+                .quarantine_sealed_batches(first_removed_batch, window)?;
+        }
+        self.truncate(start_index)
+    }
+
+    fn check_truncate_allowed(&self, start_index: u64) -> Result<()> {
+        let min_retained_index = self.config.archive_min_retained_index;
+        if self.config.archive_mode && start_index < min_retained_index {
+            error!(
+                "Truncate blocked in archive mode: start_index={} min_retained_index={}",
+                start_index, min_retained_index
+            );
+            bail!(Error::TruncateBlocked {
+                start_index,
+                min_retained_index,
+            });
+        }
+        Ok(())
+    }
+
+    /// Drop all the seal tasks, including the leased ones, move the seal version to a new epoch
+    /// and rebuild the tasks from the stored batches with `resync_seal_set`. The answers to the
+    /// tasks pulled before are returned as stale by `submit_seal_result`. Use it to recover if
+    /// the versions get into a bad state.
+    pub fn reset_seal_versions(&self) -> Result<()> {
+        self.ensure_writable()?;
+        let version = {
+            let mut to_seal_set = self.seal_manager.to_seal_set.write();
+            to_seal_set.clear();
+            self.seal_manager.in_flight.lock().clear();
+            metrics::SEAL_LEASES_OUTSTANDING.update(0);
+            self.seal_manager.start_seal_version_epoch()
+        };
+        warn!("Reset seal versions: version={}", version);
+        self.resync_seal_set()
+    }
+
+    /// Finish a truncate interrupted by a crash, which may have left the batches from its start
+    /// index partially deleted. Return its start index, or `None` if there is none.
+    pub fn resume_truncate(&self) -> Result<Option<u64>> {
+        let start_index = try_option!(self.data_db.pending_truncate()?);
+        warn!("Resume truncate: start_index={}", start_index);
+        self.truncate(start_index)?;
+        Ok(Some(start_index))
+    }
+
+    /// Take the last `warmup_batches` batches of the flow in our shard range, load the sealed
+    /// data of the stored ones into the cache if `sealed_data_cache_capacity` is set, and add
+    /// their missing seal tasks with `rebuild_seal_set` unless the store is read-only.
+    ///
+    /// The batches are found by walking back from the end of the flow set by
+    /// `set_flow_length`, so only they are read. Nothing is done if the flow length is not set.
+    pub fn warmup(&self) -> Result<()> {
+        let count = self.config.warmup_batches;
+        if count == 0 {
+            return Ok(());
+        }
+        let flow_length = match self.flow_length() {
+            Some(flow_length) => flow_length,
+            None => return Ok(()),
+        };
+        let shard_config = *self.config.shard_config.read();
+        let tail: Vec<u64> = (0..self.config.expected_batch_count(flow_length))
+            .rev()
+            .filter(|batch_index| shard_config.in_range(*batch_index))
+            .take(count)
+            .collect();
+        let (first, last) = match (tail.last(), tail.first()) {
+            (Some(&first), Some(&last)) => (first, last),
+            _ => return Ok(()),
+        };
+        let start_time = Instant::now();
+        if self.sealed_data_cache.is_some() {
+            for &batch_index in &tail {
+                self.load_sealed_data(batch_index)?;
+            }
+        }
+        let added = if self.read_only {
+            0
+        } else {
+            self.rebuild_seal_set(first, last + 1)?
+        };
+        info!(
+            "Flow store warmed up: batches={} first={} last={} added_seals={} elapsed={:?}",
+            tail.len(),
+            first,
+            last,
+            added,
+            start_time.elapsed()
+        );
+        Ok(())
+    }
+
+    /// Record the genesis state of the flow: the reserved first sector and, on a fresh store,
+    /// the empty bottom MPT layer the flow merkle tree starts with. A store that already has
+    /// data is only marked as initialized. Calling it again has no effect.
+    pub fn initialize_genesis(&self) -> Result<()> {
+        self.ensure_writable()?;
+        if self.data_db.get_genesis()?.is_some() {
+            return Ok(());
+        }
+        if self.init_state_of_data()? == FlowInitState::Empty
+            && self.flow_db.get_layer_size(0)?.is_none()
+        {
+            let mut tx = self.flow_db.start_transaction();
+            tx.save_layer_size(0, 0);
+            self.flow_db.commit(tx)?;
+        }
+        self.data_db.put_genesis(GENESIS_RESERVED_SECTORS)
+    }
+
+    /// Return the reserved sector at index 0 of the flow, or `None` if `initialize_genesis` has
+    /// not been called. The sector is not part of any tx: its MPT leaf is zero rather than the
+    /// hash of its data, `get_entries` skips it, and `append_entries` rejects non-zero data
+    /// for it. So it reads as zeros unless an older version of the store wrote data there.
+    pub fn genesis_sector(&self) -> Result<Option<Vec<u8>>> {
+        if self.data_db.get_genesis()?.is_none() {
+            return Ok(None);
+        }
+        let stored = self
+            .get_entry_batch(0)?
+            .and_then(|batch| batch.get_unsealed_data(0, GENESIS_RESERVED_SECTORS as usize));
+        Ok(Some(stored.unwrap_or_else(|| {
+            vec![0; GENESIS_RESERVED_SECTORS as usize * self.config.sector_size]
+        })))
+    }
+
+    /// Tell a genuinely empty flow from a store that `initialize_genesis` has not set up.
+    pub fn init_state(&self) -> Result<FlowInitState> {
+        match self.data_db.get_genesis()? {
+            None => Ok(FlowInitState::Uninitialized),
+            Some(reserved) if reserved != GENESIS_RESERVED_SECTORS => {
+                bail!("unexpected genesis reserved sectors: {}", reserved)
+            }
+            Some(_) => self.init_state_of_data(),
+        }
+    }
+
+    /// The flow has entries if the MPT has leaves or any batch is stored, since the batches
+    /// of the other shards are not stored.
+    fn init_state_of_data(&self) -> Result<FlowInitState> {
+        let has_leaves = self.flow_db.get_layer_size(0)?.unwrap_or(0) > 0;
+        let has_batches = self
+            .iter_batch_indices_with_pending()
+            .next()
+            .transpose()?
+            .is_some();
+        Ok(if has_leaves || has_batches {
+            FlowInitState::NonEmpty
+        } else {
+            FlowInitState::Empty
+        })
+    }
+
+    /// Return the number of sectors stored by this node. Unlike `get_num_entries`, which
+    /// assumes every present batch is full, this sums the filled sectors of the batches, so
+    /// it is the storage used by our shard rather than the flow length it covers.
+    pub fn stored_entries_in_shard(&self) -> Result<u64> {
+        let mut stored = 0;
+        for batch_index in self.iter_batch_indices_with_pending() {
+            if let Some(batch) = self.get_batch_with_pending(batch_index?)? {
+                stored += batch.num_stored_sectors() as u64;
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Return the `[start, end)` range of the longest run of present batches at or after
+    /// `from_batch`, preferring the earliest one on ties. With sharding, the batches of a run are
+    /// consecutive within our shard. A batch is present if it has any data, so the batches are
+    /// not decoded to check if they are complete. `(from_batch, from_batch)` is returned if no
+    /// batch is present.
+    pub fn longest_present_run(&self, from_batch: u64) -> Result<(u64, u64)> {
+        let step = self.config.shard_config.read().num_shard as u64;
+        let mut longest = (from_batch, from_batch);
+        let mut current: Option<(u64, u64)> = None;
+        for batch_index in self.iter_batch_indices_with_pending() {
+            let batch_index = batch_index?;
+            if batch_index < from_batch {
+                continue;
+            }
+            current = match current {
+                Some((start, last)) if last + step == batch_index => Some((start, batch_index)),
+                _ => Some((batch_index, batch_index)),
+            };
+            let (start, last) = current.expect("set above");
+            if last + 1 - start > longest.1 - longest.0 {
+                longest = (start, last + 1);
+            }
+        }
+        Ok(longest)
+    }
+
+    /// Return the number of seals waiting to be sealed and the configured high-water mark.
+    pub fn seal_backlog(&self) -> SealBacklog {
+        SealBacklog {
+            backlog: self.seal_manager.to_seal_set.read().len(),
+            high_water_mark: self.config.seal_backlog_high_water_mark,
+        }
+    }
+
+    /// Return `Error::SealBacklogFull` if an append would be rejected for the seal backlog, so
+    /// `LogManager` can reject it before it updates its merkle tree.
+    pub fn check_seal_backlog(&self) -> Result<()> {
+        self.check_seal_backlog_locked(&self.seal_manager.to_seal_set.read())
+    }
+
+    fn check_seal_backlog_locked(&self, to_seal_set: &BTreeMap<usize, u64>) -> Result<()> {
+        if let Some(limit) = self.config.seal_backlog_high_water_mark {
+            if self.seal_manager.seal_worker_available() && to_seal_set.len() >= limit {
+                bail!(Error::SealBacklogFull {
+                    backlog: to_seal_set.len(),
+                    limit,
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the average number of seals accepted per second in the last minute, and the
+    /// number of seals accepted since the store is opened.
+    pub fn seal_throughput(&self) -> (f64, u64) {
+        (
+            self.seal_manager.seal_rate(),
+            self.seal_manager.total_sealed(),
+        )
+    }
+
+    /// Return a summary of the store state. Only the batches near the end of the last append
+    /// are read, so this is cheap enough to be polled. A failed query is recorded in
+    /// `FlowHealth::errors` instead of failing the whole call.
+    pub fn health(&self) -> Result<FlowHealth> {
+        let last_append = *self.last_append.lock();
+        let mut health = FlowHealth {
+            seal_backlog: self.seal_manager.to_seal_set.read().len(),
+            last_append_succeeded: last_append.map(|(_, ok)| ok),
+            ..Default::default()
+        };
+        match self.get_num_entries() {
+            Ok(num_entries) => health.num_entries = Some(num_entries),
+            Err(e) => health.errors.push(format!("num_entries: {:?}", e)),
+        }
+        match self.get_pad_data_sync_height() {
+            Ok(height) => health.pad_data_sync_height = height,
+            Err(e) => health.errors.push(format!("pad_data_sync_height: {:?}", e)),
+        }
+        match self.init_state() {
+            Ok(state) => health.init_state = Some(state),
+            Err(e) => health.errors.push(format!("init_state: {:?}", e)),
+        }
+        if let Some((end_index, _)) = last_append {
+            match self.count_incomplete_batches_before(end_index) {
+                Ok(count) => health.incomplete_tail_batches = Some(count),
+                Err(e) => health
+                    .errors
+                    .push(format!("incomplete_tail_batches: {:?}", e)),
+            }
+        }
+        Ok(health)
+    }
+
+    fn count_incomplete_batches_before(&self, end_index: u64) -> Result<usize> {
+        let end_batch = self.config.expected_batch_count(end_index);
+        let mut count = 0;
+        // The batches are read without being recorded as accessed, so the polls do not change
+        // which seals are prioritized, and checked without building their merkle trees.
+        for batch_index in end_batch.saturating_sub(HEALTH_TAIL_BATCHES)..end_batch {
+            if let Some(batch) = self.get_batch_with_pending(batch_index)? {
+                if !batch.is_complete(batch_index == 0) {
+                    count += 1;
+                }
+            }
+        }
+        Ok(count)
+    }
+
+    /// Subscribe to the truncations of the flow, e.g. to drop the cached or indexed state of the
+    /// removed entries. An event is sent after each successful `truncate`. The channel keeps
+    /// the last 64 events, and a subscriber that falls further behind drops the older ones and
+    /// receives `RecvError::Lagged` once.
+    pub fn subscribe_truncations(&self) -> broadcast::Receiver<TruncationEvent> {
+        self.truncation_events.subscribe()
+    }
+
+    /// Like `append_entries`, but the roots of the batches completed by the append are supplied,
+    /// e.g. by a trusted snapshot during fast sync. If `verify_supplied_batch_roots` is set, a
+    /// completed batch whose local root differs from the supplied one fails the append with
+    /// `Error::BatchRootMismatch` before the db transaction of the batch is written. Otherwise,
+    /// the supplied roots of the fully stored batches are used without building their merkle
+    /// trees.
+    pub fn append_entries_with_roots(
+        &self,
+        data: ChunkArray,
+        expected_roots: &[(u64, DataRoot)],
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        let epoch = self.truncation_epoch();
+        self.append_entries_logged(data, &expected_roots.iter().copied().collect(), epoch)
+    }
+
+    /// The number of truncates so far. A caller that prepares the data of an append before it
+    /// is called, e.g. by downloading them, can read it before and pass it to
+    /// `append_entries_since`.
+    pub fn truncation_epoch(&self) -> u64 {
+        self.truncation_history.lock().epoch
+    }
+
+    /// Like `append_entries`, but the data written at or after the start index of a truncate
+    /// done after `truncation_epoch` are handled by `FlowConfig::truncation_race`, so an append
+    /// prepared before a truncate does not resurrect the truncated batches. `append_entries`
+    /// reads the epoch when it is called.
+    pub fn append_entries_since(
+        &self,
+        data: ChunkArray,
+        truncation_epoch: u64,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        self.append_entries_logged(data, &BTreeMap::new(), truncation_epoch)
+    }
+
+    #[instrument(
+        skip_all,
+        fields(
+            operation = "append_entries",
+            shard_id = self.shard_id(),
+            batch_index = data.start_index / self.config.batch_size as u64,
+        )
+    )]
+    fn append_entries_logged(
+        &self,
+        data: ChunkArray,
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        truncation_epoch: u64,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        self.ensure_writable()?;
+        if let Some(log) = &self.operation_log {
+            log.log_append_entries(&data);
+        }
+        let end_index = data.start_index + self.bytes_to_sectors(data.data.len());
+        let result = self.append_entries_inner(data, supplied_roots, truncation_epoch);
+        *self.last_append.lock() = Some((end_index, result.is_ok()));
+        let (completed_batches, written_end) = result?;
+        if let Some(written_end) = written_end {
+            self.extend_flow_length(written_end);
+        }
+        Ok(completed_batches)
+    }
+
+    /// Set the flow length that `get_entries` checks if `reject_reads_past_flow_end` is set.
+    /// `LogManager` sets it from its merkle tree when it is opened.
+    pub fn set_flow_length(&self, flow_length: u64) {
+        *self.flow_length.lock() = Some(flow_length);
+    }
+
+    pub fn flow_length(&self) -> Option<u64> {
+        *self.flow_length.lock()
+    }
+
+    /// Put the pad data sync height. A height below the stored one is rejected if
+    /// `monotonic_pad_data_sync_height` is set, unless `force` is passed for a rollback.
+    pub fn set_pad_data_sync_height(&self, height: u64, force: bool) -> Result<()> {
+        self.ensure_writable()?;
+        let _guard = self.pad_data_sync_height_lock.lock();
+        self.put_pad_data_sync_height_locked(height, force)
+    }
+
+    /// Put the pad data sync height only if the stored one is `expected`, atomically with
+    /// respect to the other writes of the height through this store.
+    /// Return `false` if the stored height is different and nothing is written.
+    pub fn compare_and_set_pad_data_sync_height(
+        &self,
+        expected: Option<u64>,
+        height: u64,
+    ) -> Result<bool> {
+        self.ensure_writable()?;
+        let _guard = self.pad_data_sync_height_lock.lock();
+        if self.data_db.get_pad_data_sync_height()? != expected {
+            return Ok(false);
+        }
+        self.put_pad_data_sync_height_locked(height, false)?;
+        Ok(true)
+    }
+
+    /// The caller should hold `pad_data_sync_height_lock`.
+    fn put_pad_data_sync_height_locked(&self, height: u64, force: bool) -> Result<()> {
+        if self.config.monotonic_pad_data_sync_height && !force {
+            if let Some(current) = self.data_db.get_pad_data_sync_height()? {
+                if height < current {
+                    bail!(Error::PadDataSyncHeightRegression {
+                        current,
+                        requested: height,
+                    });
+                }
+            }
+        }
+        self.data_db.put_pad_data_sync_height(height)
+    }
+
+    /// The shard id recorded in the spans of the flow store operations.
+    fn shard_id(&self) -> usize {
+        self.config.shard_config.read().shard_id
+    }
+
+    fn extend_flow_length(&self, end_index: u64) {
+        if let Some(flow_length) = self.flow_length.lock().as_mut() {
+            *flow_length = cmp::max(*flow_length, end_index);
+        }
+    }
+
+    /// Return the completed batches and the end index of the written data, which is `None` if
+    /// all the data are skipped for a truncate.
+    fn append_entries_inner(
+        &self,
+        data: ChunkArray,
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        truncation_epoch: u64,
+    ) -> Result<(Vec<(u64, DataRoot)>, Option<u64>)> {
+        data.validate()?;
+        let start_time = Instant::now();
+        let mut to_seal_set = self.seal_manager.to_seal_set.write();
+        self.wait_for_seal_writes();
+        // The truncates also hold the seal lock, so none can happen until the append is done.
+        let mut end_index = data.start_index + self.bytes_to_sectors(data.data.len());
+        let boundary = self
+            .truncation_history
+            .lock()
+            .boundary_since(truncation_epoch)?;
+        if let Some(boundary) = boundary.filter(|boundary| *boundary < end_index) {
+            let truncated_at = cmp::max(boundary, data.start_index);
+            let batch_size = self.config.batch_size as u64;
+            let truncated_batches = (end_index - 1) / batch_size + 1 - truncated_at / batch_size;
+            warn!(
+                "append_entries: data truncated during the append, start_index={} end_index={} truncated_at={} batches={}",
+                data.start_index, end_index, boundary, truncated_batches
+            );
+            match self.config.truncation_race {
+                TruncationRaceMode::Skip => {
+                    metrics::APPEND_ENTRIES_TRUNCATED_BATCH.mark(truncated_batches as usize);
+                    end_index = truncated_at;
+                }
+                TruncationRaceMode::Reject => bail!(Error::TruncatedDuringAppend {
+                    start_index: data.start_index,
+                    truncated_at: boundary,
+                }),
+            }
+        }
+        if end_index == data.start_index {
+            return Ok((Vec::new(), None));
+        }
+        trace!("append_entries: {} {}", data.start_index, data.data.len());
+        if self.config.require_seal_aligned_appends
+            && data.start_index % SECTORS_PER_SEAL as u64 != 0
+        {
+            bail!(
+                "append_entries: start index is not aligned to a seal, start_index={} seal_size={}",
+                data.start_index,
+                SECTORS_PER_SEAL
+            );
+        }
+        if data.start_index < GENESIS_RESERVED_SECTORS {
+            let reserved_bytes =
+                (GENESIS_RESERVED_SECTORS - data.start_index) as usize * self.config.sector_size;
+            if data.data[..cmp::min(reserved_bytes, data.data.len())]
+                .iter()
+                .any(|b| *b != 0)
+            {
+                bail!(
+                    "append_entries: non-zero data for the reserved genesis sector, start_index={}",
+                    data.start_index
+                );
+            }
+        }
+        self.check_seal_backlog_locked(&to_seal_set)?;
+        let mut append_buffer = self.append_buffer.lock();
+        let mut batch_list = Vec::new();
+        let mut buffered_list = Vec::new();
+        let mut completed_batches = Vec::new();
+        let mut restored_batches = HashSet::new();
+        for (start_entry_index, end_entry_index) in
+            batch_iter_lazy(data.start_index, end_index, self.config.batch_size)
+        {
+            // TODO: Avoid mem-copy if possible.
+            let chunk = data
+                .sub_array(start_entry_index, end_entry_index)
+                .expect("in range");
+
+            let chunk_index = chunk.start_index / self.config.batch_size as u64;
+            if !self.config.shard_config.read().in_range(chunk_index) {
+                // The data are in a shard range that we are not storing.
+                continue;
+            }
+
+            // TODO: Try to avoid loading from db if possible.
+            let mut batch = match append_buffer.get(&chunk_index) {
+                Some((batch, _)) => batch.clone(),
+                None => self
+                    .get_written_batch(chunk_index)?
+                    .unwrap_or_else(|| EntryBatch::new(chunk_index)),
+            };
+            let offset = (chunk.start_index % self.config.batch_size as u64) as usize;
+            // During sync, the same data may be received from multiple peers. Appends are
+            // serialized by the `to_seal_set` lock, so a duplicate always sees the data of
+            // the first one and can skip rewriting the batch. The root of a complete batch is
+            // still returned, so the caller can fill an MPT leaf lost since the first append.
+            if batch.contains_data(offset, &chunk.data) {
+                metrics::APPEND_ENTRIES_DUPLICATE_BATCH.mark(1);
+                if let Some(root) = batch.build_root(chunk_index == 0)? {
+                    completed_batches.push((chunk_index, root));
+                }
+                continue;
+            }
+            let mut completed_seals = batch.insert_data(offset, chunk.data)?;
+            if let Some(window) = self.config.seal_quarantine_window {
+                if let Some(restored) =
+                    self.data_db
+                        .get_quarantined_batch(chunk_index, &batch, window)?
+                {
+                    metrics::SEAL_QUARANTINE_RESTORE.mark(1);
+                    restored_batches.insert(chunk_index);
+                    for seal_index in 0..SEALS_PER_LOAD {
+                        if restored.is_sealed(seal_index as u16) {
+                            to_seal_set
+                                .remove(&(chunk_index as usize * SEALS_PER_LOAD + seal_index));
+                        }
+                    }
+                    completed_seals.retain(|seal_index| !restored.is_sealed(*seal_index));
+                    batch = restored;
+                }
+            }
+            if self.seal_manager.seal_worker_available() && !completed_seals.is_empty() {
+                completed_seals.into_iter().for_each(|x| {
+                    to_seal_set.insert(
+                        chunk_index as usize * SEALS_PER_LOAD + x as usize,
+                        self.seal_manager.to_seal_version(),
+                    );
+                });
+                self.seal_manager.notify_new_tasks();
+            }
+
+            if self.config.coalesce_small_appends && batch.build_root(chunk_index == 0)?.is_none() {
+                buffered_list.push((chunk_index, batch));
+                continue;
+            }
+            batch_list.push((chunk_index, batch));
+            if self
+                .config
+                .max_batches_per_append
+                .map_or(false, |max| batch_list.len() >= max)
+            {
+                completed_batches.append(&mut self.write_entry_batch_list(
+                    mem::take(&mut batch_list),
+                    &mut append_buffer,
+                    supplied_roots,
+                    &restored_batches,
+                )?);
+            }
+        }
+
+        metrics::APPEND_ENTRIES.update_since(start_time);
+        completed_batches.append(&mut self.write_entry_batch_list(
+            batch_list,
+            &mut append_buffer,
+            supplied_roots,
+            &restored_batches,
+        )?);
+
+        let now = Instant::now();
+        for (chunk_index, batch) in buffered_list {
+            let buffered_at = append_buffer
+                .get(&chunk_index)
+                .map_or(now, |(_, buffered_at)| *buffered_at);
+            append_buffer.insert(chunk_index, (batch, buffered_at));
+        }
+        // Flush all if the buffer is full, or only the batches buffered for too long.
+        let to_flush = if append_buffer.len() > self.config.append_buffer_capacity {
+            append_buffer.keys().copied().collect()
+        } else {
+            append_buffer
+                .iter()
+                .filter(|(_, (_, buffered_at))| {
+                    now.duration_since(*buffered_at) >= self.config.append_buffer_flush_interval
+                })
+                .map(|(index, _)| *index)
+                .collect()
+        };
+        self.flush_buffered_batches(&mut append_buffer, to_flush)?;
+        Ok((completed_batches, Some(end_index)))
+    }
+}
+
+/// A proof generated against the partial merkle state of an incomplete batch.
+/// Missing sectors are padded with zeros, so `partial_root` must not be treated as the
+/// final batch root.
+#[derive(Clone, Debug)]
+pub struct PartialBatchProof {
+    pub proof: FlowProof,
+    pub partial_root: DataRoot,
+}
+
+/// The data returned by `FlowStore::get_entries_any`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ChunkArrayTagged {
+    pub start_index: u64,
+    /// The contiguous regions in index order. The adjacent regions differ in `sealed`.
+    pub regions: Vec<TaggedRegion>,
+}
+
+/// A part of `ChunkArrayTagged` whose sectors are all sealed or all unsealed.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TaggedRegion {
+    pub start_index: u64,
+    pub sealed: bool,
+    pub data: Vec<u8>,
+}
+
+/// A pad reported by `FlowStore::verify_pad_consistency`. The range is in sectors.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PadInconsistency {
+    pub tx_seq: u64,
+    pub start_index: u64,
+    pub end_index: u64,
+    pub kind: PadInconsistencyKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PadInconsistencyKind {
+    /// The pad starts before the end of a pad of an earlier or the same tx.
+    Overlap,
+    /// The pad ends after the flow length.
+    BeyondFlowEnd,
+    /// The pad covers stored sectors with non-zero data.
+    RealData,
+}
+
+/// The result of `FlowStore::validate_archive`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ArchiveReport {
+    /// The number of batch records read.
+    pub batches: u64,
+    /// The number of batch records with a root, which is recomputed from the data.
+    pub checked_roots: u64,
+    /// The batches whose data can not be decoded or are out of the batch.
+    pub decode_failures: Vec<u64>,
+    /// The batches whose data do not match their root.
+    pub root_mismatches: Vec<u64>,
+    /// The batches not in the shard range of this store, which `import_shard` rejects.
+    pub out_of_shard: Vec<u64>,
+    /// Whether the trailer is read and matches the count and the checksum of the records.
+    pub trailer_valid: bool,
+    /// The error that stopped the parsing, e.g. a truncated record or an invalid tag.
+    pub framing_error: Option<String>,
+}
+
+impl ArchiveReport {
+    /// Whether `import_shard` is expected to import the archive without an error.
+    pub fn is_valid(&self) -> bool {
+        self.trailer_valid
+            && self.framing_error.is_none()
+            && self.decode_failures.is_empty()
+            && self.root_mismatches.is_empty()
+            && self.out_of_shard.is_empty()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FlowConfig {
+    pub batch_size: usize,
+    /// The size in bytes of a sector in the length and offset math of the flow store. Only
+    /// `BYTES_PER_SECTOR` passes `validate` for now, because the batches, the seals and the
+    /// merkle leaves still use the constant.
+    pub sector_size: usize,
+    pub merkle_node_cache_capacity: MerkleNodeCacheCapacity,
+    /// What `insert_subtree_list_for_batch` does if the batch already has subtrees, e.g. when a
+    /// caller inserts the list of a batch twice.
+    pub subtree_list_conflict: SubtreeListConflictMode,
+    pub shard_config: Arc<RwLock<ShardConfig>>,
+    /// If set, `append_entries` returns `Error::SealBacklogFull` once the number of seals
+    /// waiting to be sealed reaches this value. This only applies when sealing is enabled.
+    pub seal_backlog_high_water_mark: Option<usize>,
+    /// The maximum number of batches deleted in one db transaction in `delete_batch_list` and
+    /// `truncate`, and indexed in one in `rebuild_root_index`.
+    pub delete_batch_chunk_size: usize,
+    /// The number of the transactions of `delete_batch_list` and `truncate` committed at the
+    /// same time. The transactions are committed in waves of this many and a wave finishes
+    /// before the next one starts, so a truncate interrupted by a crash only leaves holes
+    /// within the last wave, which `resume_truncate` removes. `1` commits them serially.
+    pub delete_concurrency: usize,
+    /// If set, `append_entries` writes the updated batches in db transactions of at most this
+    /// many batches to bound its memory usage, so a wide append is no longer atomic as a whole.
+    pub max_batches_per_append: Option<usize>,
+    /// What `append_entries` does with the data at or after the start index of a truncate
+    /// done after the append is prepared, see `FlowStore::append_entries_since`.
+    pub truncation_race: TruncationRaceMode,
+    /// If set, `append_entries` rejects the data that do not start at a seal boundary, which
+    /// are usually caused by a wrong index derived by the caller.
+    pub require_seal_aligned_appends: bool,
+    /// If set, the appends that leave a batch incomplete are buffered in memory and the batch
+    /// is written to db when it becomes complete, so it is not rewritten on every small append.
+    /// The buffered data are lost on a crash, but the txs are not finalized before the buffer
+    /// is flushed, so they will be synced again.
+    pub coalesce_small_appends: bool,
+    /// A buffered batch is flushed by the next append after this interval.
+    pub append_buffer_flush_interval: Duration,
+    /// The maximum number of buffered batches. Each one takes up to 256 KB.
+    pub append_buffer_capacity: usize,
+    /// If set, the batches written by `append_entries` are collected in one pending db
+    /// transaction, which is written once it reaches this many bytes or after
+    /// `append_write_window_interval`, so a stream of small appends makes fewer commits.
+    /// Unlike `coalesce_small_appends`, the batches are written whole on every append and only
+    /// their commit is delayed. The reads and the scans, e.g. `verify_commitment`, see the
+    /// pending batches, and `flush_append_buffer` writes them. A failed commit keeps them
+    /// pending for the next one.
+    pub append_write_window_bytes: Option<usize>,
+    /// The write window is written by the first append after this interval.
+    pub append_write_window_interval: Duration,
+    /// If set, `truncate_soft` keeps the truncated complete batches with sealed data for this
+    /// window, and a batch re-appended with the same root within it reuses the seals.
+    pub seal_quarantine_window: Option<Duration>,
+    /// If set, the seal set is persisted in a range-encoded form at this interval by the task
+    /// of `LogManager::start_seal_set_compaction` and on `flush`, and it is restored when the
+    /// store is opened, so the seal tasks survive a restart.
+    pub seal_set_compaction_interval: Option<Duration>,
+    /// If set, `submit_seal_result` releases the seal lock after processing the load chunks
+    /// of at most this many answers, so a large submission does not stall the appends.
+    pub max_seal_answers_per_lock: Option<usize>,
+    /// If set, `submit_seal_result` writes the sealed batches in transactions of at most this
+    /// many batches instead of one transaction per round.
+    pub seal_result_write_chunk_size: Option<usize>,
+    /// If set, `submit_seal_result` releases the seal lock after the seal tasks are removed
+    /// and before the sealed batches are written, so the pulls and the reads do not wait for
+    /// the disk. The other writers wait for the write when they take the seal lock. The
+    /// tasks of the batches that fail to be written are queued again.
+    pub release_seal_lock_before_write: bool,
+    /// The maximum number of consecutive load chunks whose seal tasks are returned by one
+    /// `pull_seal_chunk`.
+    pub max_seal_load_chunks_per_pull: usize,
+    /// If set, at most this many seal tasks are leased and not submitted or expired at a time,
+    /// so a few miners cannot lease the whole seal set. `pull_seal_chunk` also leases the tasks
+    /// it returns, and returns `None` once the cap is reached until some leases are released.
+    pub max_outstanding_seal_leases: Option<usize>,
+    /// The fraction (0.0-1.0) of the answers in `submit_seal_result` whose seals are recomputed
+    /// and checked before any answer is accepted. A wrong sampled seal rejects the whole
+    /// submission with `Error::SealVerificationFailed`. `0.0` disables the verification.
+    pub seal_verify_sample_rate: f64,
+    /// The seed of the rng that samples the answers to verify. Random if not set.
+    pub seal_verify_seed: Option<u64>,
+    /// The number of threads that recompute the sampled seals in parallel. `0` recomputes them
+    /// in the calling thread.
+    pub seal_verify_threads: usize,
+    /// If set, the calls to `append_entries`, `truncate`, `submit_seal_result` and
+    /// `put_pad_data` are recorded to this file for `op_log::replay`. `truncate_soft` is
+    /// recorded as `truncate`.
+    pub operation_log_path: Option<PathBuf>,
+    /// The kvdb columns of the flow store.
+    pub columns: FlowColumns,
+    /// Whether `append_entries_with_roots` checks the supplied roots against the local ones.
+    pub verify_supplied_batch_roots: bool,
+    /// If set, at most this many `get_entries` calls read the db at the same time and the
+    /// others wait, so the reads for sync and RPC cannot take all the disk IO from the appends
+    /// and seals. The number of reads in flight is reported by `READS_IN_FLIGHT`.
+    pub max_concurrent_reads: Option<usize>,
+    /// The number of batches read ahead by `proof_sweep`. `0` reads each batch when its proof
+    /// is built.
+    pub proof_sweep_prefetch: usize,
+    /// If set, `truncate` and `truncate_soft` reject the start indices below
+    /// `archive_min_retained_index` with `Error::TruncateBlocked`, so an archival node can not
+    /// lose its history to a buggy deep reorg. The truncations above it, e.g. for the shallow
+    /// reorgs, still proceed.
+    pub archive_mode: bool,
+    /// The lowest entry index a truncate may start from in archive mode.
+    pub archive_min_retained_index: u64,
+    /// If set, `get_entries` returns `Error::BeyondFlowEnd` for a range that starts at or after
+    /// the flow length set by `set_flow_length`, so it can be told from a missing batch, for
+    /// which `None` is returned.
+    pub reject_reads_past_flow_end: bool,
+    /// If set, `put_pad_data_sync_height` only advances the stored height and a smaller one is
+    /// rejected with `Error::PadDataSyncHeightRegression`. The rollback in `truncate` is
+    /// forced, so it is not affected.
+    pub monotonic_pad_data_sync_height: bool,
+    /// Where `LogManager::start_metrics_export` pushes the log store metrics.
+    pub metrics_export: MetricsExport,
+    /// The interval between two metrics exports.
+    pub metrics_export_interval: Duration,
+    /// The number of proofs cached by `gen_proof_in_batch`. `0` disables the cache.
+    pub proof_cache_capacity: usize,
+    /// The number of recently read batches whose last access time is tracked for
+    /// `coldest_batches`. Each entry takes about 50 bytes. `0` disables the tracking.
+    pub batch_access_tracking_capacity: usize,
+    /// If set, `pull_seal_chunk` pulls from the most recently read batch with seal tasks
+    /// first, so the hot data become minable sooner. The tasks of a batch are still pulled in
+    /// index order with their versions. It needs `batch_access_tracking_capacity`, and the
+    /// tasks are pulled in index order without it.
+    pub prioritize_recently_read_seals: bool,
+    /// The number of batches whose sealed data are cached for `load_sealed_data`.
+    /// Each entry takes 256 KB. `0` disables the cache.
+    pub sealed_data_cache_capacity: usize,
+    /// The number of the last batches of the flow in our shard range that `LogManager` preloads
+    /// when it is opened, with their MPT nodes, so the first reads and proofs after a restart
+    /// are not served cold. `0` disables the warmup.
+    pub warmup_batches: usize,
+    /// Whether `LogManager` repairs the store indices when it is opened. For now this
+    /// rebuilds the root index with `rebuild_root_index`, which reads every stored batch.
+    pub self_check: bool,
+    /// If set, the merkle tree of a complete batch built for a proof is stored with all its
+    /// nodes, and the later proofs of the batch load it instead of hashing the sectors again.
+    /// Each tree takes about 64 KB. A stored tree is dropped when its batch is written or
+    /// truncated, and only used if its root matches the batch root in the flow MPT.
+    pub persist_batch_merkle: bool,
+}
+
+impl Default for FlowConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: SECTORS_PER_LOAD,
+            sector_size: BYTES_PER_SECTOR,
+            // Each node takes (8+8+32=)48 Bytes, so the default value is 1.5 GB memory size.
+            merkle_node_cache_capacity: MerkleNodeCacheCapacity::Nodes(32 * 1024 * 1024),
+            subtree_list_conflict: Default::default(),
+            shard_config: Default::default(),
+            seal_backlog_high_water_mark: None,
+            delete_batch_chunk_size: 10_000,
+            delete_concurrency: 1,
+            max_batches_per_append: None,
+            truncation_race: Default::default(),
+            require_seal_aligned_appends: false,
+            coalesce_small_appends: false,
+            append_buffer_flush_interval: Duration::from_secs(5),
+            append_buffer_capacity: 64,
+            append_write_window_bytes: None,
+            append_write_window_interval: Duration::from_secs(1),
+            seal_quarantine_window: None,
+            seal_set_compaction_interval: None,
+            max_seal_answers_per_lock: None,
+            seal_result_write_chunk_size: None,
+            release_seal_lock_before_write: false,
+            max_seal_load_chunks_per_pull: 1,
+            max_outstanding_seal_leases: None,
+            seal_verify_sample_rate: 0.0,
+            seal_verify_seed: None,
+            seal_verify_threads: 0,
+            operation_log_path: None,
+            columns: Default::default(),
+            verify_supplied_batch_roots: true,
+            max_concurrent_reads: None,
+            proof_sweep_prefetch: 2,
+            archive_mode: false,
+            archive_min_retained_index: 0,
+            reject_reads_past_flow_end: false,
+            monotonic_pad_data_sync_height: false,
+            metrics_export: Default::default(),
+            metrics_export_interval: Duration::from_secs(10),
+            proof_cache_capacity: 0,
+            batch_access_tracking_capacity: 0,
+            prioritize_recently_read_seals: false,
+            sealed_data_cache_capacity: 0,
+            warmup_batches: 0,
+            self_check: false,
+            persist_batch_merkle: false,
+        }
+    }
+}
+
+impl FlowConfig {
+    /// Check the options that the store cannot run with. It is called when `LogManager` opens
+    /// the store.
+    pub fn validate(&self) -> Result<()> {
+        if self.sector_size == 0 || !self.sector_size.is_power_of_two() {
+            bail!(
+                "invalid sector size: {} is not a power of two",
+                self.sector_size
+            );
+        }
+        if self.sector_size != BYTES_PER_SECTOR {
+            bail!(
+                "unsupported sector size: {}, only {} is supported",
+                self.sector_size,
+                BYTES_PER_SECTOR
+            );
+        }
+        Ok(())
+    }
+
+    /// The number of batches that a flow of `flow_length` sectors spans. The flow length counts
+    /// the reserved genesis sector, but a flow with only that sector has no batch.
+    pub fn expected_batch_count(&self, flow_length: u64) -> u64 {
+        if flow_length <= GENESIS_RESERVED_SECTORS {
+            return 0;
+        }
+        flow_length.div_ceil(self.batch_size as u64)
+    }
+
+    /// Like `expected_batch_count`, but only the batches in our shard range are counted.
+    pub fn expected_owned_batch_count(&self, flow_length: u64) -> u64 {
+        let shard_config = *self.shard_config.read();
+        let batch_count = self.expected_batch_count(flow_length);
+        (batch_count + shard_config.num_shard as u64 - 1)
+            .saturating_sub(shard_config.shard_id as u64)
+            / shard_config.num_shard as u64
+    }
+}
+
+/// The capacity of the cache of the flow MPT nodes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MerkleNodeCacheCapacity {
+    /// The number of cached nodes. `0` disables the cache.
+    Nodes(usize),
+    /// A memory budget in bytes. It is divided by the size of a cache entry, so the budget
+    /// holds if the node type changes.
+    Bytes(usize),
+}
+
+impl MerkleNodeCacheCapacity {
+    /// The number of nodes the cache is created with.
+    pub fn node_count(&self) -> usize {
+        match *self {
+            Self::Nodes(count) => count,
+            Self::Bytes(bytes) => bytes / NodeManager::<DataRoot>::cache_entry_size(),
+        }
+    }
+}
+
+/// What `FlowStore::insert_subtree_list_for_batch` does if the batch already has subtrees.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SubtreeListConflictMode {
+    /// Replace the stored subtrees.
+    #[default]
+    Overwrite,
+    /// Reject a list that differs from the stored one with `Error::SubtreeListConflict`.
+    Verify,
+    /// Add the inserted subtrees to the stored ones. Overlapping subtrees that differ are
+    /// rejected with `Error::SubtreeListConflict`.
+    Merge,
+}
+
+impl FromStr for SubtreeListConflictMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "overwrite" => Ok(Self::Overwrite),
+            "verify" => Ok(Self::Verify),
+            "merge" => Ok(Self::Merge),
+            _ => Err(format!("invalid subtree list conflict mode: {}", s)),
+        }
+    }
+}
+
+/// What `FlowStore::append_entries` does with the data of an append that are at or after the
+/// start index of a truncate done after the append is prepared.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TruncationRaceMode {
+    /// Write the data before the truncated index and skip the rest.
+    #[default]
+    Skip,
+    /// Reject the whole append with `Error::TruncatedDuringAppend`.
+    Reject,
+}
+
+impl FromStr for TruncationRaceMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "skip" => Ok(Self::Skip),
+            "reject" => Ok(Self::Reject),
+            _ => Err(format!("invalid truncation race mode: {}", s)),
+        }
+    }
+}
+
+/// The kvdb columns used by the flow store. Setting them allows several flow stores to share
+/// one db, as long as the db is opened with enough columns.
+#[derive(Clone, Copy, Debug)]
+pub struct FlowColumns {
+    pub entry_batch: u32,
+    pub flow_mpt_nodes: u32,
+    pub pad_data_list: u32,
+    pub pad_data_sync_height: u32,
+    pub root_index: u32,
+    pub seal_quarantine: u32,
+    pub batch_merkle: u32,
+    pub misc: u32,
+}
+
+impl Default for FlowColumns {
+    fn default() -> Self {
+        Self {
+            entry_batch: COL_ENTRY_BATCH,
+            flow_mpt_nodes: COL_FLOW_MPT_NODES,
+            pad_data_list: COL_PAD_DATA_LIST,
+            pad_data_sync_height: COL_PAD_DATA_SYNC_HEIGH,
+            root_index: COL_ROOT_INDEX,
+            seal_quarantine: COL_SEAL_QUARANTINE,
+            batch_merkle: COL_BATCH_MERKLE,
+            misc: COL_MISC,
+        }
+    }
+}
+
+impl Drop for FlowStore {
+    fn drop(&mut self) {
+        if let Err(e) = self.flush_append_buffer() {
+            error!("Flush append buffer on drop failed: {:?}", e);
+        }
+    }
+}
+
+impl FlowRead for FlowStore {
+    /// Return `Ok(None)` if only partial data are available.
+    fn get_entries(&self, index_start: u64, index_end: u64) -> Result<Option<ChunkArray>> {
+        let mut data = Vec::new();
+        Ok(self
+            .get_entries_into(index_start, index_end, &mut data)?
+            .map(|start_index| ChunkArray { data, start_index }))
+    }
+
+    fn get_available_entries(&self, index_start: u64, index_end: u64) -> Result<Vec<ChunkArray>> {
+        // Both `index_start` and `index_end` are at the batch boundaries, so we do not need
+        // to check if the data is within range when we process each batch.
+        if index_end <= index_start
+            || index_start % self.config.batch_size as u64 != 0
+            || index_end % self.config.batch_size as u64 != 0
+        {
+            bail!(
+                "invalid entry index: start={} end={}",
+                index_start,
+                index_end
+            );
+        }
+        let mut entry_list = Vec::<ChunkArray>::new();
+        for (start_entry_index, _) in
+            batch_iter_lazy(index_start, index_end, self.config.batch_size)
+        {
+            let chunk_index = start_entry_index / self.config.batch_size as u64;
+
+            if let Some(mut data_list) = self
+                .get_entry_batch(chunk_index)?
+                .map(|b| b.into_data_list(start_entry_index))
+            {
+                if data_list.is_empty() {
+                    continue;
+                }
+                // This will not happen for now because we only get entries for the last chunk.
+                if let Some(last) = entry_list.last_mut() {
+                    if last.start_index + self.bytes_to_sectors(last.data.len())
+                        == data_list[0].start_index
+                    {
+                        // Merge the first element with the previous one.
+                        last.data.append(&mut data_list.remove(0).data);
+                    }
+                }
+                for data in data_list {
+                    entry_list.push(data);
+                }
+            }
+        }
+        Ok(entry_list)
+    }
+
+    fn load_sealed_data(&self, chunk_index: u64) -> Result<Option<MineLoadChunk>> {
+        let cache = match &self.sealed_data_cache {
+            Some(cache) => cache,
+            None => return self.load_sealed_data_from_db(chunk_index),
+        };
+        // Keep the cache locked while loading from db, so a concurrent write cannot be
+        // invalidated before the stale data are inserted.
+        let mut cache = cache.lock();
+        if let Some(mine_chunk) = cache.get(&chunk_index) {
+            return Ok(Some(mine_chunk.clone()));
+        }
+        let mine_chunk = try_option!(self.load_sealed_data_from_db(chunk_index)?);
+        cache.put(chunk_index, mine_chunk.clone());
+        Ok(Some(mine_chunk))
+    }
+
+    fn get_num_entries(&self) -> Result<u64> {
+        // This is an over-estimation as it assumes each batch is full.
+        self.data_db
+            .kvdb
+            .num_keys(self.data_db.columns.entry_batch)
+            .map(|num_batches| num_batches * self.config.batch_size as u64)
+            .map_err(Into::into)
+    }
+
+    fn get_shard_config(&self) -> ShardConfig {
+        *self.config.shard_config.read()
+    }
+
+    fn get_pad_data(&self, start_index: u64) -> crate::error::Result<Option<Vec<PadPair>>> {
+        self.flow_db.get_pad_data(start_index)
+    }
+
+    fn get_pad_data_sync_height(&self) -> Result<Option<u64>> {
+        self.data_db.get_pad_data_sync_height()
+    }
+}
+
+impl FlowWrite for FlowStore {
+    /// Return the roots of completed chunks. The order is guaranteed to be increasing
+    /// by chunk index.
+    fn append_entries(&self, data: ChunkArray) -> Result<Vec<(u64, DataRoot)>> {
+        let epoch = self.truncation_epoch();
+        self.append_entries_logged(data, &BTreeMap::new(), epoch)
+    }
+
+    #[instrument(
+        skip(self),
+        fields(
+            operation = "truncate",
+            shard_id = self.shard_id(),
+            batch_index = start_index / self.config.batch_size as u64,
+        )
+    )]
+    fn truncate(&self, start_index: u64) -> crate::error::Result<()> {
+        self.ensure_writable()?;
+        self.check_truncate_allowed(start_index)?;
+        if let Some(log) = &self.operation_log {
+            log.log_truncate(start_index);
+        }
+        let mut to_seal_set = self.seal_manager.to_seal_set.write();
+        self.wait_for_seal_writes();
+        self.flush_append_buffer()?;
+        let result = self.data_db.truncate(
+            start_index,
+            self.config.batch_size,
+            self.config.delete_batch_chunk_size,
+            self.config.delete_concurrency,
+        );
+        self.clear_batch_caches();
+        let to_reseal = result?;
+        if let Some(flow_length) = self.flow_length.lock().as_mut() {
+            *flow_length = cmp::min(*flow_length, start_index);
+        }
+        let resealed_seals = to_reseal.len();
+        if let Some(tx_seq) = self.flow_db.truncate_pad_data(start_index)? {
+            // The pads of `tx_seq` are applied again when it is synced after the reorg.
+            let _guard = self.pad_data_sync_height_lock.lock();
+            if self
+                .data_db
+                .get_pad_data_sync_height()?
+                .map_or(false, |height| height > tx_seq)
+            {
+                self.put_pad_data_sync_height_locked(tx_seq, true)?;
+            }
+        }
+
+        self.truncation_history.lock().record(start_index);
+        to_seal_set.split_off(&(start_index as usize / SECTORS_PER_SEAL));
+        let new_seal_version = self.seal_manager.inc_seal_version();
+
+        if !to_reseal.is_empty() {
+            to_reseal.into_iter().for_each(|x| {
+                to_seal_set.insert(x, new_seal_version);
+            });
+            self.seal_manager.notify_new_tasks();
+        }
+        // An error only means there is no subscriber.
+        let _ = self.truncation_events.send(TruncationEvent {
+            start_index,
+            resealed_seals,
+        });
+        Ok(())
+    }
+
+    fn update_shard_config(&self, shard_config: ShardConfig) {
+        *self.config.shard_config.write() = shard_config;
+    }
+
+    fn put_pad_data(&self, data_sizes: &[PadPair], tx_seq: u64) -> crate::error::Result<()> {
+        self.ensure_writable()?;
+        if let Some(log) = &self.operation_log {
+            log.log_put_pad_data(data_sizes, tx_seq);
+        }
+        self.flow_db.put_pad_data(data_sizes, tx_seq)?;
+        if let Some(end_index) = data_sizes
+            .iter()
+            .map(|pad| pad.start_index + pad.data_size)
+            .max()
+        {
+            self.extend_flow_length(end_index);
+        }
+        Ok(())
+    }
+
+    fn put_pad_data_sync_height(&self, sync_index: u64) -> crate::error::Result<()> {
+        self.set_pad_data_sync_height(sync_index, false)
+    }
+}
+
+impl FlowSeal for FlowStore {
+    #[instrument(
+        skip(self),
+        fields(
+            operation = "pull_seal_chunk",
+            shard_id = self.shard_id(),
+            batch_index = field::Empty,
+        )
+    )]
+    fn pull_seal_chunk(&self, seal_index_max: usize) -> Result<Option<Vec<SealTask>>> {
+        let to_seal_set = self.seal_manager.to_seal_set.read();
+        self.seal_manager.update_pull_time();
+        // With `max_outstanding_seal_leases`, the pulled tasks are leased like the tasks of
+        // `seal_task_stream`, so the leased tasks are skipped and the pulls are capped too.
+        let mut in_flight = self
+            .config
+            .max_outstanding_seal_leases
+            .map(|_| self.seal_manager.in_flight.lock());
+        let available = match in_flight.as_mut() {
+            Some(in_flight) => self.drop_finished_leases(&to_seal_set, in_flight),
+            None => usize::MAX,
+        };
+        if available == 0 {
+            return Ok(None);
+        }
+        let is_leased = |seal_index: &usize| {
+            in_flight
+                .as_ref()
+                .map_or(false, |in_flight| in_flight.contains_key(seal_index))
+        };
+
+        let first_index = match self
+            .recently_read_seal_start(&to_seal_set, seal_index_max)
+            .filter(|seal_index| !is_leased(seal_index))
+        {
+            Some(seal_index) => seal_index,
+            None => {
+                let &first_index =
+                    try_option!(to_seal_set.keys().find(|seal_index| !is_leased(seal_index)));
+                if first_index >= seal_index_max {
+                    return Ok(None);
+                }
+                first_index
+            }
+        };
+        // The tasks are pulled from the load chunks `[first_load_index, end_load_index)`.
+        let first_load_index = first_index / SEALS_PER_LOAD;
+        Span::current().record("batch_index", first_load_index);
+        let end_load_index =
+            first_load_index + cmp::max(self.config.max_seal_load_chunks_per_pull, 1);
+        let end_index = cmp::min(seal_index_max, end_load_index * SEALS_PER_LOAD);
+        let selected: Vec<(usize, u64)> = to_seal_set
+            .range(first_index..end_index)
+            .filter(|(seal_index, _)| !is_leased(seal_index))
+            .take(available)
+            .map(|(&seal_index, &version)| (seal_index, version))
+            .collect();
+
+        let now = Instant::now();
+        let mut tasks = Vec::with_capacity(SEALS_PER_LOAD);
+        let mut loaded: Option<(usize, EntryBatch)> = None;
+        for (seal_index, version) in selected {
+            let load_index = seal_index / SEALS_PER_LOAD;
+            if loaded.as_ref().map(|(index, _)| *index) != Some(load_index) {
+                let batch_data = match self.get_written_batch(load_index as u64)? {
+                    Some(batch_data) => batch_data,
+                    None => bail!(Error::SealStateDesync {
+                        seal_index: seal_index as u64,
+                    }),
+                };
+                loaded = Some((load_index, batch_data));
+            }
+            let (_, batch_data) = loaded.as_ref().expect("loaded above");
+            let seal_index_local = seal_index % SEALS_PER_LOAD;
+            let non_sealed_data = match batch_data.get_non_sealed_data(seal_index_local as u16) {
+                Some(data) => data,
+                None => bail!(Error::SealStateDesync {
+                    seal_index: seal_index as u64,
+                }),
+            };
+            if let Some(in_flight) = in_flight.as_mut() {
+                in_flight.insert(seal_index, (version, now));
+            }
+            tasks.push(SealTask {
+                seal_index: seal_index as u64,
+                version,
+                non_sealed_data,
+            })
+        }
+        if let Some(in_flight) = &in_flight {
+            metrics::SEAL_LEASES_OUTSTANDING.update(in_flight.len());
+        }
+
+        Ok(Some(tasks))
+    }
+
+    fn seal_task_stream(&self, seal_index_max: usize) -> impl Stream<Item = SealTask> + '_ {
+        stream::unfold(VecDeque::new(), move |mut pending| async move {
+            loop {
+                if let Some(task) = pending.pop_front() {
+                    return Some((task, pending));
+                }
+                // Register for the notification before checking the seal set, so the tasks
+                // added in between are not missed.
+                let notified = self.seal_manager.new_task_notify().notified();
+                tokio::pin!(notified);
+                notified.as_mut().enable();
+                match self.lease_seal_chunk(seal_index_max) {
+                    Ok(tasks) if !tasks.is_empty() => pending.extend(tasks),
+                    Ok(_) => {
+                        // Also wake up when the leases of other tasks expire.
+                        let _ = tokio::time::timeout(SEAL_TASK_LEASE_TIMEOUT, notified).await;
+                    }
+                    Err(e) => {
+                        warn!("Seal task stream stopped: {:?}", e);
+                        return None;
+                    }
+                }
+            }
+        })
+    }
+
+    #[instrument(
+        skip_all,
+        fields(
+            operation = "submit_seal_result",
+            shard_id = self.shard_id(),
+            batch_index = field::Empty,
+            answers = answers.len(),
+        )
+    )]
+    fn submit_seal_result(&self, mut answers: Vec<SealAnswer>) -> Result<Vec<SealAnswer>> {
+        self.ensure_writable()?;
+        if let Some(log) = &self.operation_log {
+            log.log_submit_seal_result(&answers);
+        }
+        if self.config.seal_verify_sample_rate > 0.0 {
+            self.verify_seal_sample(&answers)?;
+        }
+        // Sorted before the lock is taken, so the stable sort in `partition_stale_answers` only
+        // makes a linear pass.
+        answers.sort_by_key(|answer| answer.seal_index);
+        if let Some(answer) = answers.first() {
+            // The first batch, as the answers may span several.
+            Span::current().record("batch_index", answer.seal_index / SEALS_PER_LOAD as u64);
+        }
+        let groups: Vec<(u64, Vec<SealAnswer>)> = answers
+            .into_iter()
+            .chunk_by(|answer| answer.seal_index / SEALS_PER_LOAD as u64)
+            .into_iter()
+            .map(|(load_index, answers_in_chunk)| (load_index, answers_in_chunk.collect()))
+            .collect();
+        let max_answers = self.config.max_seal_answers_per_lock.unwrap_or(usize::MAX);
+        let mut groups = groups.into_iter().peekable();
+        let mut sealed = 0;
+        let mut stale = Vec::new();
+        while groups.peek().is_some() {
+            // The lock is released between the rounds, so a truncate may have run in between
+            // and the versions are checked again in each round.
+            let mut to_seal_set = self.seal_manager.to_seal_set.write();
+            self.wait_for_seal_writes();
+            self.flush_append_buffer()?;
+
+            let mut answer_count = 0;
+            let mut round = Vec::new();
+            while let Some((_, answers_in_chunk)) = groups.next_if(|(_, group)| {
+                answer_count == 0 || answer_count + group.len() <= max_answers
+            }) {
+                answer_count += answers_in_chunk.len();
+                round.extend(answers_in_chunk);
+            }
+            let (consistent, stale_in_round) = partition_stale_answers(&to_seal_set, round);
+            stale.extend(stale_in_round);
+
+            let mut updated_chunk = vec![];
+            let chunks = consistent
+                .into_iter()
+                .chunk_by(|answer| answer.seal_index / SEALS_PER_LOAD as u64);
+            for (load_index, answers_in_chunk) in &chunks {
+                let mut answers_in_chunk = answers_in_chunk.peekable();
+                let seal_index = answers_in_chunk.peek().expect("non-empty group").seal_index;
+                let mut batch_chunk = match self.data_db.get_entry_batch(load_index)? {
+                    Some(batch_chunk) => batch_chunk,
+                    None => bail!(Error::SealStateDesync { seal_index }),
+                };
+                let mut removed_seal_index = Vec::new();
+                for answer in answers_in_chunk {
+                    removed_seal_index.push(answer.seal_index as usize);
+                    batch_chunk.submit_seal_result(answer)?;
+                }
+                updated_chunk.push((load_index, batch_chunk, removed_seal_index));
+            }
+
+            // The removed tasks are kept with their versions to queue them again if the write
+            // fails.
+            let mut removed_count = 0;
+            let updated_chunk: Vec<SealedBatch> = updated_chunk
+                .into_iter()
+                .map(|(load_index, batch_chunk, removed_seal_index)| {
+                    debug!("Seal chunks: indices = {:?}", removed_seal_index);
+                    let removed: Vec<(usize, u64)> = removed_seal_index
+                        .into_iter()
+                        .filter_map(|idx| Some((idx, to_seal_set.remove(&idx)?)))
+                        .collect();
+                    removed_count += removed.len();
+                    (load_index, batch_chunk, removed)
+                })
+                .collect();
+
+            if self.config.release_seal_lock_before_write {
+                let seal_write = self.seal_write_lock.lock();
+                drop(to_seal_set);
+                let result = self.write_sealed_batches(updated_chunk);
+                drop(seal_write);
+                if let Err((e, unwritten)) = result {
+                    let mut to_seal_set = self.seal_manager.to_seal_set.write();
+                    self.requeue_unwritten_seals(&mut to_seal_set, unwritten, true);
+                    return Err(e);
+                }
+            } else if let Err((e, unwritten)) = self.write_sealed_batches(updated_chunk) {
+                self.requeue_unwritten_seals(&mut to_seal_set, unwritten, false);
+                return Err(e);
+            }
+            sealed += removed_count;
+        }
+        // Recorded after the lock is released.
+        self.seal_manager.record_sealed(sealed);
+        if sealed > 0 && self.config.max_outstanding_seal_leases.is_some() {
+            // Wake up the seal task streams waiting for the leases under the cap.
+            self.seal_manager.notify_new_tasks();
+        }
+        if !stale.is_empty() {
+            debug!("Drop stale seal answers: count={}", stale.len());
+        }
+        Ok(stale)
+    }
+
+    #[instrument(
+        skip(self),
+        fields(operation = "resync_seal_set", shard_id = self.shard_id())
+    )]
+    fn resync_seal_set(&self) -> Result<()> {
+        self.ensure_writable()?;
+        let mut to_seal_set = self.seal_manager.to_seal_set.write();
+        self.flush_append_buffer()?;
+        let version = self.seal_manager.to_seal_version();
+        let mut rebuilt = BTreeMap::new();
+        for batch_index in self.data_db.iter_batch_indices() {
+            let batch_index = batch_index?;
+            let batch = match self.data_db.get_entry_batch(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            for seal_index in 0..SEALS_PER_LOAD {
+                if batch.get_non_sealed_data(seal_index as u16).is_some() {
+                    rebuilt.insert(batch_index as usize * SEALS_PER_LOAD + seal_index, version);
+                }
+            }
+        }
+        warn!(
+            "Resync seal set: before={} after={}",
+            to_seal_set.len(),
+            rebuilt.len()
+        );
+        *to_seal_set = rebuilt;
+        if !to_seal_set.is_empty() {
+            self.seal_manager.notify_new_tasks();
+        }
+        Ok(())
+    }
+
+    fn rebuild_seal_set(&self, start_batch: u64, end_batch: u64) -> Result<usize> {
+        self.ensure_writable()?;
+        let mut to_seal_set = self.seal_manager.to_seal_set.write();
+        self.flush_append_buffer()?;
+        let version = self.seal_manager.to_seal_version();
+        let mut added = 0;
+        for batch_index in self.data_db.iter_batch_indices() {
+            let batch_index = batch_index?;
+            if batch_index < start_batch {
+                continue;
+            }
+            if batch_index >= end_batch {
+                break;
+            }
+            let batch = match self.data_db.get_entry_batch(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            for seal_index in 0..SEALS_PER_LOAD {
+                if batch.get_non_sealed_data(seal_index as u16).is_some() {
+                    if let Entry::Vacant(e) =
+                        to_seal_set.entry(batch_index as usize * SEALS_PER_LOAD + seal_index)
+                    {
+                        e.insert(version);
+                        added += 1;
+                    }
+                }
+            }
+        }
+        if added > 0 {
+            debug!(
+                "Rebuild seal set: start_batch={} end_batch={} added={}",
+                start_batch, end_batch, added
+            );
+            self.seal_manager.notify_new_tasks();
+        }
+        Ok(added)
+    }
+
+    fn seal_set_summary(&self) -> SealSetSummary {
+        let to_seal_set = self.seal_manager.to_seal_set.read();
+        let mut summary = SealSetSummary {
+            count: to_seal_set.len(),
+            min_index: to_seal_set.keys().next().copied(),
+            max_index: to_seal_set.keys().next_back().copied(),
+            load_chunks: 0,
+        };
+        // The indices are sorted, so the seals of a load chunk are consecutive.
+        let mut last_load_index = None;
+        for &seal_index in to_seal_set.keys() {
+            let load_index = seal_index / SEALS_PER_LOAD;
+            if last_load_index != Some(load_index) {
+                summary.load_chunks += 1;
+                last_load_index = Some(load_index);
+            }
+        }
+        summary
+    }
+
+    fn estimated_seal_eta(&self) -> Option<Duration> {
+        let backlog = self.seal_manager.to_seal_set.read().len();
+        if backlog == 0 {
+            return Some(Duration::ZERO);
+        }
+        let rate = self.seal_manager.seal_rate();
+        if rate <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(backlog as f64 / rate))
+    }
+
+    fn pending_seals_in_range(&self, start_batch: u64, end_batch: u64) -> Vec<usize> {
+        if end_batch <= start_batch {
+            return Vec::new();
+        }
+        let start = start_batch as usize * SEALS_PER_LOAD;
+        let end = end_batch as usize * SEALS_PER_LOAD;
+        self.seal_manager
+            .to_seal_set
+            .read()
+            .range(start..end)
+            .map(|(seal_index, _)| *seal_index)
+            .collect()
+    }
+
+    fn inspect_seal(&self, seal_index: usize) -> Result<Option<SealInspect>> {
+        let to_seal_set = self.seal_manager.to_seal_set.read();
+        let queued_version = to_seal_set.get(&seal_index).copied();
+        let leased = match (
+            queued_version,
+            self.seal_manager.in_flight.lock().get(&seal_index),
+        ) {
+            (Some(queued), Some((version, leased_at))) => {
+                queued == *version && leased_at.elapsed() < SEAL_TASK_LEASE_TIMEOUT
+            }
+            _ => false,
+        };
+        // The batch is read directly, so the inspection is not counted as an access.
+        let batch_index = (seal_index / SEALS_PER_LOAD) as u64;
+        let buffered = self
+            .append_buffer
+            .lock()
+            .get(&batch_index)
+            .map(|(batch, _)| batch.clone());
+        let batch = match buffered {
+            Some(batch) => Some(batch),
+            None => self.get_written_batch(batch_index)?,
+        };
+        if queued_version.is_none() && batch.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(SealInspect {
+            queued_version,
+            current_version: self.seal_manager.to_seal_version(),
+            leased,
+            batch_exists: batch.is_some(),
+            non_sealed_data_available: batch.map_or(false, |batch| {
+                batch
+                    .get_non_sealed_data((seal_index % SEALS_PER_LOAD) as u16)
+                    .is_some()
+            }),
+        }))
+    }
+}
+
+/// A batch removed by `truncate_soft` whose seals may be restored.
+#[derive(DeriveEncode, DeriveDecode)]
+struct QuarantinedBatch {
+    root: DataRoot,
+    quarantined_at: u64,
+    batch: EntryBatch,
+}
+
+#[derive(Debug, PartialEq, DeriveEncode, DeriveDecode)]
+pub struct PadPair {
+    pub start_index: u64,
+    pub data_size: u64,
+}
+
+pub struct FlowDBStore {
+    kvdb: Arc<dyn ZgsKeyValueDB>,
+    columns: FlowColumns,
+}
+
+impl FlowDBStore {
+    pub fn new(kvdb: Arc<dyn ZgsKeyValueDB>) -> Self {
+        Self::new_with_columns(kvdb, Default::default())
+    }
+
+    pub fn new_with_columns(kvdb: Arc<dyn ZgsKeyValueDB>, columns: FlowColumns) -> Self {
+        Self { kvdb, columns }
+    }
+
+    /// Write `tx` and record its op count and approximate byte size for the write `path`.
+    fn write_tx(&self, path: &'static str, tx: DBTransaction) -> Result<()> {
+        metrics::record_db_tx(path, tx.ops.len(), tx_size(&tx));
+        self.kvdb.write(tx)?;
+        Ok(())
+    }
+
+    /// Write `txs` in waves of `concurrency` transactions, each written by its own thread.
+    /// The next wave is only built after the previous one is written, and the transactions
+    /// after a failed wave are not written.
+    fn write_txs_concurrently(
+        &self,
+        path: &'static str,
+        txs: impl Iterator<Item = DBTransaction>,
+        concurrency: usize,
+    ) -> Result<()> {
+        if concurrency <= 1 {
+            for tx in txs {
+                self.write_tx(path, tx)?;
+            }
+            return Ok(());
+        }
+        let mut txs = txs.peekable();
+        while txs.peek().is_some() {
+            let wave: Vec<DBTransaction> = txs.by_ref().take(concurrency).collect();
+            std::thread::scope(|scope| {
+                let handles: Vec<_> = wave
+                    .into_iter()
+                    .map(|tx| scope.spawn(move || self.write_tx(path, tx)))
+                    .collect();
+                // Join all the threads before returning, otherwise the scope rethrows the
+                // panic of a thread left unjoined.
+                let results: Vec<_> = handles.into_iter().map(|handle| handle.join()).collect();
+                results.into_iter().try_for_each(|result| {
+                    result.map_err(|_| anyhow!("db write thread panicked: path={}", path))?
+                })
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Count the stored MPT nodes of each layer and rewrite the layer sizes that disagree.
+    /// Return the corrected sizes. A layer exists if its size is recorded or it has nodes.
+    fn recompute_layer_sizes(&self) -> Result<Vec<usize>> {
+        let count_nodes = |layer: usize| -> Result<usize> {
+            let mut count = 0;
+            for item in self
+                .kvdb
+                .iter_with_prefix(self.columns.flow_mpt_nodes, &layer.to_be_bytes())
+            {
+                item?;
+                count += 1;
+            }
+            Ok(count)
+        };
+        let mut sizes = Vec::new();
+        let mut tx = self.start_transaction();
+        let mut updated = false;
+        loop {
+            let layer = sizes.len();
+            let recorded = self.get_layer_size(layer)?;
+            let count = count_nodes(layer)?;
+            if recorded.is_none() && count == 0 {
+                break;
+            }
+            if recorded != Some(count) {
+                warn!(
+                    "fix mpt layer size: layer={} recorded={:?} actual={}",
+                    layer, recorded, count
+                );
+                tx.save_layer_size(layer, count);
+                updated = true;
+            }
+            sizes.push(count);
+        }
+        // The recorded sizes after a missing layer are never loaded, so they are removed.
+        let prefix = "layer_size".as_bytes();
+        for item in self
+            .kvdb
+            .iter_with_prefix(self.columns.flow_mpt_nodes, prefix)
+        {
+            let (key, _) = item?;
+            let layer = try_decode_usize(&key[prefix.len()..])?;
+            if layer >= sizes.len() {
+                warn!("remove stale mpt layer size: layer={}", layer);
+                tx.remove_layer_size(layer);
+                updated = true;
+            }
+        }
+        if updated {
+            self.commit(tx)?;
+        }
+        Ok(sizes)
+    }
+
+    /// Rewrite every key `k` in `column` to `transform(k)`, in db transactions of at most
+    /// `chunk_size` keys. Return the number of rewritten keys.
+    ///
+    /// The db iterators are snapshots, so the rewritten keys are not visited again. The keys
+    /// that `transform` leaves unchanged are skipped, so an interrupted migration can be
+    /// resumed if `transform` keeps the migrated keys (e.g. it only adds a missing prefix).
+    /// `transform` must not map two keys, or a key and an existing unmigrated key, to the same key.
+    pub fn migrate_keys(
+        &self,
+        column: u32,
+        chunk_size: usize,
+        transform: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> Result<usize> {
+        let chunk_size = cmp::max(chunk_size, 1);
+        let mut migrated = 0;
+        let mut tx = self.kvdb.transaction();
+        let mut tx_size = 0;
+        for item in self.kvdb.iter(column) {
+            let (key, value) = item?;
+            let new_key = transform(&key);
+            if new_key[..] == key[..] {
+                continue;
+            }
+            tx.delete(column, &key);
+            tx.put_vec(column, &new_key, value);
+            tx_size += 1;
+            if tx_size == chunk_size {
+                self.write_tx(
+                    "migrate_keys",
+                    mem::replace(&mut tx, self.kvdb.transaction()),
+                )?;
+                migrated += tx_size;
+                tx_size = 0;
+            }
+        }
+        self.write_tx("migrate_keys", tx)?;
+        migrated += tx_size;
+        Ok(migrated)
+    }
+
+    fn put_entry_batch_list(
+        &self,
+        batch_list: Vec<(u64, EntryBatch)>,
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        restored: &HashSet<u64>,
+        verify_supplied_roots: bool,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        let start_time = Instant::now();
+        let mut tx = self.kvdb.transaction();
+        let completed_batches = self.add_entry_batch_list(
+            &mut tx,
+            &batch_list,
+            supplied_roots,
+            restored,
+            verify_supplied_roots,
+        )?;
+        self.write_tx("put_entry_batch_list", tx)?;
+        metrics::PUT_ENTRY_BATCH_LIST.update_since(start_time);
+        Ok(completed_batches)
+    }
+
+    /// Add the writes of `put_entry_batch_list` to `tx` and return the completed batches with
+    /// their roots. `tx` may be partially filled if an error is returned.
+    fn add_entry_batch_list(
+        &self,
+        tx: &mut DBTransaction,
+        batch_list: &[(u64, EntryBatch)],
+        supplied_roots: &BTreeMap<u64, DataRoot>,
+        restored: &HashSet<u64>,
+        verify_supplied_roots: bool,
+    ) -> Result<Vec<(u64, DataRoot)>> {
+        let mut completed_batches = Vec::new();
+        for &(batch_index, ref batch) in batch_list {
+            tx.put(
+                self.columns.entry_batch,
+                &batch_index.to_be_bytes(),
+                &encode_entry_batch(&batch),
+            );
+            tx.delete(self.columns.batch_merkle, &batch_index.to_be_bytes());
+            if restored.contains(&batch_index) {
+                tx.delete(self.columns.seal_quarantine, &batch_index.to_be_bytes());
+            }
+            let root = match supplied_roots.get(&batch_index) {
+                Some(expected)
+                    if !verify_supplied_roots && batch.num_stored_sectors() == SECTORS_PER_LOAD =>
+                {
+                    Some(*expected)
+                }
+                Some(expected) => match batch.build_root(batch_index == 0)? {
+                    Some(actual) if actual != *expected => {
+                        bail!(Error::BatchRootMismatch {
+                            batch_index,
+                            expected: *expected,
+                            actual,
+                        })
+                    }
+                    root => root,
+                },
+                None => batch.build_root(batch_index == 0)?,
+            };
+            if let Some(root) = root {
+                trace!("complete batch: index={}", batch_index);
+                tx.put(
+                    self.columns.root_index,
+                    &encode_root_index_key(&root, batch_index),
+                    &[],
+                );
+                completed_batches.push((batch_index, root));
+            }
+        }
+        Ok(completed_batches)
+    }
+
+    /// Used for the seal results and the subtree lists, which do not change the merkle tree
+    /// of a complete batch, so the stored batch merkle trees are kept.
+    fn put_entry_raw(&self, batch_list: Vec<(u64, EntryBatch)>) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+        for (batch_index, batch) in batch_list {
+            tx.put(
+                self.columns.entry_batch,
+                &batch_index.to_be_bytes(),
+                &encode_entry_batch(&batch),
+            );
+        }
+        self.write_tx("put_entry_raw", tx)?;
+        Ok(())
+    }
+
+    /// Write the batches back, or delete them if they have become empty.
+    fn update_entry_batch_list(&self, batch_list: Vec<(u64, EntryBatch)>) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+        for (batch_index, batch) in batch_list {
+            tx.delete(self.columns.batch_merkle, &batch_index.to_be_bytes());
+            if batch.is_empty() {
+                tx.delete(self.columns.entry_batch, &batch_index.to_be_bytes());
+            } else {
+                tx.put(
+                    self.columns.entry_batch,
+                    &batch_index.to_be_bytes(),
+                    &encode_entry_batch(&batch),
+                );
+            }
+        }
+        self.write_tx("update_entry_batch_list", tx)?;
+        Ok(())
+    }
+
+    /// Move the complete batches with sealed data at or after `from_batch` to the quarantine,
+    /// and drop the quarantined batches older than `window`.
+    fn quarantine_sealed_batches(&self, from_batch: u64, window: Duration) -> Result<()> {
+        let now = current_timestamp();
+        let mut tx = self.kvdb.transaction();
+        for item in self.kvdb.iter(self.columns.seal_quarantine) {
+            let (key, value) = item?;
+            let entry = QuarantinedBatch::from_ssz_bytes(&value).map_err(Error::from)?;
+            if now.saturating_sub(entry.quarantined_at) >= window.as_secs() {
+                tx.delete(self.columns.seal_quarantine, &key);
+            }
+        }
+        for batch_index in self.iter_batch_indices() {
+            let batch_index = batch_index?;
+            if batch_index < from_batch {
+                continue;
+            }
+            let batch = match self.get_entry_batch(batch_index)? {
+                Some(batch) if batch.has_sealed_data() => batch,
+                _ => continue,
+            };
+            if let Some(root) = batch.build_root(batch_index == 0)? {
+                let entry = QuarantinedBatch {
+                    root,
+                    quarantined_at: now,
+                    batch,
+                };
+                tx.put(
+                    self.columns.seal_quarantine,
+                    &batch_index.to_be_bytes(),
+                    &entry.as_ssz_bytes(),
+                );
+            }
+        }
+        self.write_tx("quarantine_sealed_batches", tx)?;
+        Ok(())
+    }
+
+    /// Return the quarantined batch at `batch_index` if `batch` is complete and has the same
+    /// root. The quarantine entry is kept here. It is deleted by `add_entry_batch_list` in the
+    /// transaction writing the restored batch, or dropped by `quarantine_sealed_batches` once
+    /// it has expired.
+    fn get_quarantined_batch(
+        &self,
+        batch_index: u64,
+        batch: &EntryBatch,
+        window: Duration,
+    ) -> Result<Option<EntryBatch>> {
+        let key = batch_index.to_be_bytes();
+        let raw = try_option!(self.kvdb.get(self.columns.seal_quarantine, &key)?);
+        let root = try_option!(batch.build_root(batch_index == 0)?);
+        let entry = QuarantinedBatch::from_ssz_bytes(&raw).map_err(Error::from)?;
+        if current_timestamp().saturating_sub(entry.quarantined_at) >= window.as_secs()
+            || entry.root != root
+        {
+            return Ok(None);
+        }
+        Ok(Some(entry.batch))
+    }
+
+    /// The root index is keyed by `(root, batch_index)`, so the batches sharing a root are all
+    /// kept. Its entries are not removed when the batches are truncated or deleted, so each
+    /// candidate is checked against the stored batch.
+    fn get_batch_index_of_root(&self, root: &DataRoot) -> Result<Option<u64>> {
+        for item in self
+            .kvdb
+            .iter_with_prefix(self.columns.root_index, root.as_bytes())
+        {
+            let (key, _) = item?;
+            let batch_index = decode_batch_index(&key[DataRoot::len_bytes()..])? as u64;
+            if let Some(batch) = self.get_entry_batch(batch_index)? {
+                if batch.build_root(batch_index == 0)?.as_ref() == Some(root) {
+                    return Ok(Some(batch_index));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    /// Rewrite the root index from the stored batches in one transaction.
+    fn clear_root_index(&self) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+        tx.delete_prefix(self.columns.root_index, &[]);
+        self.write_tx("clear_root_index", tx)
+    }
+
+    /// Index the roots of the complete batches in `[start_batch, end_batch)` in one transaction.
+    /// Return the number of indexed batches.
+    fn index_batch_roots(&self, start_batch: u64, end_batch: u64) -> Result<usize> {
+        let mut tx = self.kvdb.transaction();
+        let mut count = 0;
+        for batch_index in start_batch..end_batch {
+            let batch = match self.get_entry_batch(batch_index)? {
+                Some(batch) => batch,
+                None => continue,
+            };
+            if let Some(root) = batch.build_root(batch_index == 0)? {
+                tx.put(
+                    self.columns.root_index,
+                    &encode_root_index_key(&root, batch_index),
+                    &[],
+                );
+                count += 1;
+            }
+        }
+        self.write_tx("index_batch_roots", tx)?;
+        Ok(count)
+    }
+
+    /// Return the merkle tree nodes stored by `put_batch_merkle`, from the leaves to the root.
+    fn get_batch_merkle(&self, batch_index: u64) -> Result<Option<Vec<Vec<DataRoot>>>> {
+        let raw = try_option!(self
+            .kvdb
+            .get(self.columns.batch_merkle, &batch_index.to_be_bytes())?);
+        Ok(Some(
+            Vec::<Vec<DataRoot>>::from_ssz_bytes(&raw).map_err(Error::from)?,
+        ))
+    }
+
+    fn put_batch_merkle(&self, batch_index: u64, layers: &[Vec<DataRoot>]) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+        tx.put(
+            self.columns.batch_merkle,
+            &batch_index.to_be_bytes(),
+            &layers.to_vec().as_ssz_bytes(),
+        );
+        self.write_tx("put_batch_merkle", tx)
+    }
+
+    /// Iterate over the indices of the stored batches in increasing order.
+    fn iter_batch_indices(&self) -> impl Iterator<Item = Result<u64>> + '_ {
+        self.kvdb.iter(self.columns.entry_batch).map(|item| {
+            let (key, _) = item?;
+            Ok(decode_batch_index(key.as_ref())? as u64)
+        })
+    }
+
+    fn get_entry_batch(&self, batch_index: u64) -> Result<Option<EntryBatch>> {
+        let raw = try_option!(self.get_entry_batch_raw(batch_index)?);
+        Ok(Some(decode_entry_batch(&raw)?))
+    }
+
+    /// Return the stored bytes of a batch without decoding them, e.g. to dump a batch that
+    /// fails to decode. The bytes are the versioned encoding of `encode_entry_batch`.
+    pub fn get_entry_batch_raw(&self, batch_index: u64) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .kvdb
+            .get(self.columns.entry_batch, &batch_index.to_be_bytes())?)
+    }
+
+    /// Store the bytes returned by `get_entry_batch_raw`. The bytes are decoded first, so a
+    /// corrupted batch is rejected, and the root index is updated if the batch is complete.
+    /// The caches of a `FlowStore` opened on this db are not invalidated.
+    pub fn put_entry_batch_raw(&self, batch_index: u64, raw: &[u8]) -> Result<()> {
+        let batch = decode_entry_batch(raw)?;
+        let mut tx = self.kvdb.transaction();
+        tx.put(self.columns.entry_batch, &batch_index.to_be_bytes(), raw);
+        tx.delete(self.columns.batch_merkle, &batch_index.to_be_bytes());
+        if let Some(root) = batch.build_root(batch_index == 0)? {
+            tx.put(
+                self.columns.root_index,
+                &encode_root_index_key(&root, batch_index),
+                &[],
+            );
+        }
+        self.write_tx("put_entry_batch_raw", tx)?;
+        Ok(())
+    }
+
+    /// Remove the batches from `start_index` in transactions of at most `chunk_size` keys, so
+    /// a deep truncate does not build one huge transaction. The batches are deleted from the
+    /// end and the partial first batch is written last, so after a crash the stored batches
+    /// are still a prefix of the flow. The start index is recorded until the truncate is done,
+    /// and `pending_truncate` returns it so the truncate can be run again.
+    /// The MPT nodes are reverted separately by the flow merkle tree.
+    fn truncate(
+        &self,
+        start_index: u64,
+        batch_size: usize,
+        chunk_size: usize,
+        concurrency: usize,
+    ) -> crate::error::Result<Vec<usize>> {
+        let mut start_batch_index = start_index / batch_size as u64;
+        let first_batch_offset = start_index as usize % batch_size;
+        let mut index_to_reseal = Vec::new();
+        let mut first_batch_update = None;
+        if first_batch_offset != 0 {
+            if let Some(mut first_batch) = self.get_entry_batch(start_batch_index)? {
+                index_to_reseal = first_batch
+                    .truncate(first_batch_offset)
+                    .into_iter()
+                    .map(|x| start_batch_index as usize * SEALS_PER_LOAD + x as usize)
+                    .collect();
+                first_batch_update = Some((start_batch_index, first_batch));
+            }
+
+            start_batch_index += 1;
+        }
+        // TODO: `kvdb` and `kvdb-rocksdb` does not support `seek_to_last` yet.
+        // We'll need to fork it or use another wrapper for a better performance in this.
+        let end = match self.kvdb.iter(self.columns.entry_batch).last() {
+            Some(Ok((k, _))) => decode_batch_index(k.as_ref())? as u64 + 1,
+            Some(Err(e)) => {
+                error!("truncate db error: e={:?}", e);
+                return Err(e.into());
+            }
+            None => {
+                // The db has no data, so we can just return;
+                return Ok(index_to_reseal);
+            }
+        };
+        self.kvdb.put(
+            self.columns.misc,
+            TRUNCATE_PROGRESS_KEY,
+            &start_index.to_be_bytes(),
+        )?;
+        let chunk_size = cmp::max(chunk_size, 1) as u64;
+        let mut chunk_end = end;
+        let txs = std::iter::from_fn(|| {
+            if chunk_end <= start_batch_index {
+                return None;
+            }
+            let chunk_start = cmp::max(chunk_end.saturating_sub(chunk_size), start_batch_index);
+            let mut tx = self.kvdb.transaction();
+            for batch_index in chunk_start..chunk_end {
+                tx.delete(self.columns.entry_batch, &batch_index.to_be_bytes());
+                tx.delete(self.columns.batch_merkle, &batch_index.to_be_bytes());
+            }
+            chunk_end = chunk_start;
+            Some(tx)
+        });
+        self.write_txs_concurrently("truncate", txs, concurrency)?;
+        let mut tx = self.kvdb.transaction();
+        if let Some((batch_index, first_batch)) = first_batch_update {
+            tx.delete(self.columns.batch_merkle, &batch_index.to_be_bytes());
+            if !first_batch.is_empty() {
+                tx.put(
+                    self.columns.entry_batch,
+                    &batch_index.to_be_bytes(),
+                    &encode_entry_batch(&first_batch),
+                );
+            } else {
+                tx.delete(self.columns.entry_batch, &batch_index.to_be_bytes());
+            }
+        }
+        tx.delete(self.columns.misc, TRUNCATE_PROGRESS_KEY);
+        self.write_tx("truncate", tx)?;
+        Ok(index_to_reseal)
+    }
+
+    fn put_genesis(&self, reserved_sectors: u64) -> Result<()> {
+        self.kvdb.put(
+            self.columns.misc,
+            GENESIS_KEY,
+            &reserved_sectors.to_be_bytes(),
+        )?;
+        Ok(())
+    }
+
+    fn get_genesis(&self) -> Result<Option<u64>> {
+        match self.kvdb.get(self.columns.misc, GENESIS_KEY)? {
+            Some(v) => Ok(Some(u64::from_be_bytes(
+                v.try_into().map_err(|e| anyhow!("{:?}", e))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the start index of a truncate interrupted by a crash.
+    fn pending_truncate(&self) -> Result<Option<u64>> {
+        match self.kvdb.get(self.columns.misc, TRUNCATE_PROGRESS_KEY)? {
+            Some(v) => Ok(Some(u64::from_be_bytes(
+                v.try_into().map_err(|e| anyhow!("{:?}", e))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    /// Delete the batches in transactions of at most `chunk_size` keys to bound the memory
+    /// usage and write stalls of a large prune. Deleting a missing key is a no-op, so the
+    /// deletion can be retried after a crash in the middle.
+    fn delete_batch_list(
+        &self,
+        batch_list: &[u64],
+        chunk_size: usize,
+        concurrency: usize,
+    ) -> Result<()> {
+        let txs = batch_list.chunks(cmp::max(chunk_size, 1)).map(|chunk| {
+            let mut tx = self.kvdb.transaction();
+            for i in chunk {
+                tx.delete(self.columns.entry_batch, &i.to_be_bytes());
+                tx.delete(self.columns.batch_merkle, &i.to_be_bytes());
+            }
+            tx
+        });
+        self.write_txs_concurrently("delete_batch_list", txs, concurrency)
+    }
+
+    fn put_pad_data(&self, data_sizes: &[PadPair], tx_seq: u64) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+
+        tx.put(
+            self.columns.pad_data_list,
+            &tx_seq.to_be_bytes(),
+            &encode_pad_data(data_sizes),
+        );
+        self.write_tx("put_pad_data", tx)?;
+        Ok(())
+    }
+
+    fn put_pad_data_sync_height(&self, tx_seq: u64) -> Result<()> {
+        let mut tx = self.kvdb.transaction();
+        tx.put(
+            self.columns.pad_data_sync_height,
+            b"sync_height",
+            &tx_seq.to_be_bytes(),
+        );
+        self.write_tx("put_pad_data_sync_height", tx)?;
+        Ok(())
+    }
+
+    fn get_pad_data_sync_height(&self) -> Result<Option<u64>> {
+        match self
+            .kvdb
+            .get(self.columns.pad_data_sync_height, b"sync_height")?
+        {
+            Some(v) => Ok(Some(u64::from_be_bytes(
+                v.try_into().map_err(|e| anyhow!("{:?}", e))?,
+            ))),
+            None => Ok(None),
+        }
+    }
+
+    fn put_seal_set(&self, encoded: &[u8]) -> Result<()> {
+        self.kvdb.put(self.columns.misc, SEAL_SET_KEY, encoded)?;
+        Ok(())
+    }
+
+    fn get_seal_set(&self) -> Result<Option<Vec<u8>>> {
+        Ok(self.kvdb.get(self.columns.misc, SEAL_SET_KEY)?)
+    }
+
+    /// Drop the pads at or after `start_index` from the pad data lists, and trim the pad that
+    /// straddles it. A list left empty is deleted. The lists are keyed by tx seq rather than
+    /// flow position, so all of them are scanned.
+    /// Return the smallest tx seq whose pad data are changed.
+    fn truncate_pad_data(&self, start_index: u64) -> Result<Option<u64>> {
+        let mut tx = self.kvdb.transaction();
+        let mut first_changed = None;
+        for item in self.kvdb.iter(self.columns.pad_data_list) {
+            let (key, value) = item?;
+            let tx_seq = u64::from_be_bytes(key.as_ref().try_into()?);
+            let pad_list = decode_pad_data(tx_seq, &value)?;
+            if pad_list
+                .iter()
+                .all(|pad| pad.start_index + pad.data_size <= start_index)
+            {
+                continue;
+            }
+            first_changed.get_or_insert(tx_seq);
+            let truncated: Vec<PadPair> = pad_list
+                .into_iter()
+                .filter(|pad| pad.start_index < start_index)
+                .map(|pad| PadPair {
+                    start_index: pad.start_index,
+                    data_size: cmp::min(pad.data_size, start_index - pad.start_index),
+                })
+                .collect();
+            if truncated.is_empty() {
+                tx.delete(self.columns.pad_data_list, &key);
+            } else {
+                tx.put(
+                    self.columns.pad_data_list,
+                    &key,
+                    &encode_pad_data(&truncated),
+                );
+            }
+        }
+        self.write_tx("truncate_pad_data", tx)?;
+        Ok(first_changed)
+    }
+
+    fn get_pad_data(&self, tx_seq: u64) -> Result<Option<Vec<PadPair>>> {
+        match self
+            .kvdb
+            .get(self.columns.pad_data_list, &tx_seq.to_be_bytes())?
+        {
+            Some(v) => Ok(Some(decode_pad_data(tx_seq, &v)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Return the tx seqs that have pad data in ascending order. Only the keys are decoded.
+    pub fn pad_data_tx_seqs(&self) -> Result<Vec<u64>> {
+        let mut tx_seqs = self
+            .kvdb
+            .iter(self.columns.pad_data_list)
+            .map(|item| {
+                let (key, _) = item?;
+                Ok(u64::from_be_bytes(key.as_ref().try_into().map_err(
+                    |_| anyhow!("invalid pad data key: length={}", key.len()),
+                )?))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        tx_seqs.sort_unstable();
+        Ok(tx_seqs)
+    }
+}
+
+/// Records in `COL_ENTRY_BATCH` and `COL_PAD_DATA_LIST` start with a `VERSION_TAG | version`
+/// byte so that their format can evolve. Records written before the versioning have no prefix
+/// and are decoded as `LEGACY_VERSION`. They are upgraded the next time they are written instead
+/// of on read, because `kvdb` has no compare-and-swap and a rewrite on read could overwrite a
+/// concurrent write of the same record.
+const VERSION_TAG: u8 = 0x80;
+const LEGACY_VERSION: u8 = 0;
+const ENTRY_BATCH_VERSION: u8 = 1;
+const PAD_DATA_VERSION: u8 = 2;
+/// Version 1 has no header and its payload is only the `PadPair`s.
+const PAD_DATA_VERSION_NO_HEADER: u8 = 1;
+/// `count: u32 | entry_size: u16`, which precedes the `PadPair`s since version 2.
+const PAD_DATA_HEADER_SIZE: usize = 6;
+
+fn encode_versioned<T: Encode>(version: u8, value: &T) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + value.ssz_bytes_len());
+    data.push(VERSION_TAG | version);
+    value.ssz_append(&mut data);
+    data
+}
+
+/// Split a record into its version and payload.
+fn decode_versioned(data: &[u8], is_legacy: bool) -> Result<(u8, &[u8])> {
+    if is_legacy {
+        return Ok((LEGACY_VERSION, data));
+    }
+    match data.split_first() {
+        Some((&tag, payload)) if tag & VERSION_TAG != 0 => Ok((tag & !VERSION_TAG, payload)),
+        _ => bail!("invalid record version tag"),
+    }
+}
+
+fn encode_entry_batch(batch: &EntryBatch) -> Vec<u8> {
+    encode_versioned(ENTRY_BATCH_VERSION, batch)
+}
+
+fn decode_entry_batch(data: &[u8]) -> Result<EntryBatch> {
+    // A legacy `EntryBatch` starts with the little-endian ssz offset of its first field, which is
+    // 8 and never has the tag bit set.
+    let is_legacy = data.first().map_or(true, |b| b & VERSION_TAG == 0);
+    match decode_versioned(data, is_legacy)? {
+        (LEGACY_VERSION | ENTRY_BATCH_VERSION, payload) => {
+            Ok(EntryBatch::from_ssz_bytes(payload).map_err(Error::from)?)
+        }
+        (version, _) => bail!("unsupported entry batch version: {}", version),
+    }
+}
+
+fn encode_pad_data(data_sizes: &[PadPair]) -> Vec<u8> {
+    let entry_size = <PadPair as Encode>::ssz_fixed_len();
+    let mut data = Vec::with_capacity(1 + PAD_DATA_HEADER_SIZE + data_sizes.len() * entry_size);
+    data.push(VERSION_TAG | PAD_DATA_VERSION);
+    data.extend_from_slice(&(data_sizes.len() as u32).to_be_bytes());
+    data.extend_from_slice(&(entry_size as u16).to_be_bytes());
+    for item in data_sizes {
+        item.ssz_append(&mut data);
+    }
+    data
+}
+
+/// Decode the pad data list of `tx_seq`, which is only used in the errors. The header of a
+/// record tells a truncated or corrupted list from a generic decoding failure.
+fn decode_pad_data(tx_seq: u64, data: &[u8]) -> Result<Vec<PadPair>> {
+    let entry_size = <PadPair as Decode>::ssz_fixed_len();
+    // A legacy record is a plain concatenation of fixed-size `PadPair`s.
+    let is_legacy = data.len() % entry_size == 0;
+    let payload = match decode_versioned(data, is_legacy)? {
+        (LEGACY_VERSION | PAD_DATA_VERSION_NO_HEADER, payload) => payload,
+        (PAD_DATA_VERSION, payload) => {
+            if payload.len() < PAD_DATA_HEADER_SIZE {
+                bail!(
+                    "pad data header truncated: tx_seq={} length={}",
+                    tx_seq,
+                    payload.len()
+                );
+            }
+            let (header, pairs) = payload.split_at(PAD_DATA_HEADER_SIZE);
+            let count = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+            let recorded_entry_size = u16::from_be_bytes(header[4..].try_into().unwrap()) as usize;
+            if recorded_entry_size != entry_size {
+                bail!(
+                    "unsupported pad data entry size: tx_seq={} entry_size={} expected={}",
+                    tx_seq,
+                    recorded_entry_size,
+                    entry_size
+                );
+            }
+            if pairs.len() != count * entry_size {
+                bail!(
+                    "pad data length mismatch: tx_seq={} count={} expected={} actual={}",
+                    tx_seq,
+                    count,
+                    count * entry_size,
+                    pairs.len()
+                );
+            }
+            pairs
+        }
+        (version, _) => bail!(
+            "unsupported pad data version: tx_seq={} version={}",
+            tx_seq,
+            version
+        ),
+    };
+    Ok(Vec::<PadPair>::from_ssz_bytes(payload).map_err(Error::from)?)
+}
+
+#[derive(DeriveEncode, DeriveDecode, Clone, Debug)]
+#[ssz(enum_behaviour = "union")]
+pub enum BatchRoot {
+    Single(DataRoot),
+    Multiple((usize, DataRoot)),
+}
+
+enum ExportRecord {
+    Batch {
+        batch_index: u64,
+        root: Option<DataRoot>,
+        payload: Vec<u8>,
+    },
+    Trailer {
+        count: u64,
+        checksum_matches: bool,
+    },
+}
+
+/// Read the next record of an export archive. The batch records are added to `hasher`, and
+/// the trailer is checked against it.
+fn read_export_record(reader: &mut impl Read, hasher: &mut Keccak) -> Result<ExportRecord> {
+    let mut tag = [0u8; 1];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        EXPORT_RECORD_BATCH | EXPORT_RECORD_COMPLETE_BATCH => {
+            let root_size = if tag[0] == EXPORT_RECORD_COMPLETE_BATCH {
+                32
+            } else {
+                0
+            };
+            let mut header = vec![0u8; 12 + root_size];
+            reader.read_exact(&mut header)?;
+            let batch_index = u64::from_be_bytes(header[..8].try_into().unwrap());
+            let root = (root_size != 0).then(|| DataRoot::from_slice(&header[8..40]));
+            let length = u32::from_be_bytes(header[8 + root_size..].try_into().unwrap()) as usize;
+            if length > MAX_EXPORT_RECORD_SIZE {
+                bail!("export record too large: length={}", length);
+            }
+            let mut payload = vec![0u8; length];
+            reader.read_exact(&mut payload)?;
+            hasher.update(&tag);
+            hasher.update(&header);
+            hasher.update(&payload);
+            Ok(ExportRecord::Batch {
+                batch_index,
+                root,
+                payload,
+            })
+        }
+        EXPORT_RECORD_TRAILER => {
+            let mut trailer = [0u8; 40];
+            reader.read_exact(&mut trailer)?;
+            let mut checksum = [0u8; 32];
+            hasher.clone().finalize(&mut checksum);
+            Ok(ExportRecord::Trailer {
+                count: u64::from_be_bytes(trailer[..8].try_into().unwrap()),
+                checksum_matches: trailer[8..] == checksum,
+            })
+        }
+        tag => bail!("invalid export record tag: {}", tag),
+    }
+}
+
+/// Merge two subtree lists sorted by the start sector. Return `None` if a subtree of one list
+/// overlaps a different subtree of the other.
+fn merge_subtree_lists(
+    existing: &[(usize, usize, DataRoot)],
+    inserted: &[(usize, usize, DataRoot)],
+) -> Option<Vec<(usize, usize, DataRoot)>> {
+    let end = |(start_sector, subtree_height, _): &(usize, usize, DataRoot)| {
+        start_sector + (1 << (subtree_height - 1))
+    };
+    let mut merged: Vec<(usize, usize, DataRoot)> = existing
+        .iter()
+        .chain(inserted)
+        .copied()
+        .sorted_by_key(|subtree| subtree.0)
+        .collect();
+    merged.dedup();
+    for pair in merged.windows(2) {
+        if end(&pair[0]) > pair[1].0 {
+            return None;
+        }
+    }
+    Some(merged)
+}
+
+/// A batch updated by `submit_seal_result` with the seal tasks removed for it.
+type SealedBatch = (u64, EntryBatch, Vec<(usize, u64)>);
+
+/// Split `answers` into the ones whose version matches their task in `to_seal_set` and the
+/// stale ones, in one merge of the sorted answers with the tasks in their range.
+fn partition_stale_answers(
+    to_seal_set: &BTreeMap<usize, u64>,
+    mut answers: Vec<SealAnswer>,
+) -> (Vec<SealAnswer>, Vec<SealAnswer>) {
+    answers.sort_by_key(|answer| answer.seal_index);
+    let (first, last) = match (answers.first(), answers.last()) {
+        (Some(first), Some(last)) => (first.seal_index as usize, last.seal_index as usize),
+        _ => return (answers, Vec::new()),
+    };
+    let mut tasks = to_seal_set.range(first..=last).peekable();
+    let mut consistent = Vec::with_capacity(answers.len());
+    let mut stale = Vec::new();
+    for answer in answers {
+        let seal_index = answer.seal_index as usize;
+        while tasks.next_if(|(index, _)| **index < seal_index).is_some() {}
+        match tasks.peek() {
+            Some((index, version)) if **index == seal_index && **version == answer.version => {
+                consistent.push(answer)
+            }
+            _ => stale.push(answer),
+        }
+    }
+    (consistent, stale)
+}
+
+/// Append `data` to the stores of several shards run in one process, each usually with its
+/// own `data_db`. The data are split by batch, and each batch is appended to the first store
+/// whose shard owns it. The batches owned by no store are skipped. At most `max_concurrency`
+/// stores are written in parallel.
+/// Return the roots of the batches completed in all the stores in increasing order of the
+/// batch index. If a store fails, the other stores may still have been written.
+pub fn append_entries_by_shard(
+    stores: &[&FlowStore],
+    data: ChunkArray,
+    max_concurrency: usize,
+) -> Result<Vec<(u64, DataRoot)>> {
+    data.validate()?;
+    let shard_configs: Vec<ShardConfig> = stores
+        .iter()
+        .map(|store| *store.config.shard_config.read())
+        .collect();
+    let batch_size = stores
+        .first()
+        .map_or(SECTORS_PER_LOAD, |store| store.config.batch_size);
+    let num_sectors = stores.first().map_or_else(
+        || bytes_to_entries(data.data.len() as u64),
+        |store| store.bytes_to_sectors(data.data.len()),
+    );
+    let mut groups = vec![Vec::new(); stores.len()];
+    for (start_entry_index, end_entry_index) in
+        batch_iter_lazy(data.start_index, data.start_index + num_sectors, batch_size)
+    {
+        let batch_index = start_entry_index / batch_size as u64;
+        if let Some(owner) = shard_configs
+            .iter()
+            .position(|shard_config| shard_config.in_range(batch_index))
+        {
+            groups[owner].push(
+                data.sub_array(start_entry_index, end_entry_index)
+                    .expect("in range"),
+            );
+        }
+    }
+    let work: Vec<(&FlowStore, Vec<ChunkArray>)> = stores
+        .iter()
+        .copied()
+        .zip(groups)
+        .filter(|(_, chunks)| !chunks.is_empty())
+        .collect();
+    let num_workers = cmp::min(max_concurrency.max(1), work.len());
+    let work = Mutex::new(work.into_iter());
+    let results = Mutex::new(Vec::new());
+    std::thread::scope(|scope| {
+        for _ in 0..num_workers {
+            scope.spawn(|| loop {
+                let (store, chunks) = match work.lock().next() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let result = chunks.into_iter().try_fold(Vec::new(), |mut roots, chunk| {
+                    roots.append(&mut store.append_entries(chunk)?);
+                    Ok::<_, anyhow::Error>(roots)
+                });
+                results.lock().push(result);
+            });
+        }
+    });
+    let mut completed_batches = Vec::new();
+    for result in results.into_inner() {
+        completed_batches.append(&mut result?);
+    }
+    completed_batches.sort_by_key(|(batch_index, _)| *batch_index);
+    Ok(completed_batches)
+}
+
+/// Return the batch boundaries `(batch_start_index, batch_end_index)` given the index range.
+pub fn batch_iter(start: u64, end: u64, batch_size: usize) -> Vec<(u64, u64)> {
+    batch_iter_lazy(start, end, batch_size).collect()
+}
+
+/// Like `batch_iter`, but the boundaries are yielded without collecting them.
+pub fn batch_iter_lazy(
+    start: u64,
+    end: u64,
+    batch_size: usize,
+) -> impl Iterator<Item = (u64, u64)> {
+    (start / batch_size as u64 * batch_size as u64..end)
+        .step_by(batch_size)
+        .map(move |i| (cmp::max(start, i), cmp::min(end, i + batch_size as u64)))
+}
+
+/// See `ShardConfig::owned_batch_ranges`.
+pub fn batch_iter_sharded(
+    start: u64,
+    end: u64,
+    batch_size: usize,
+    shard_config: ShardConfig,
+) -> Vec<(u64, u64)> {
+    shard_config.owned_batch_ranges(start, end, batch_size)
+}
+
+/// Like `batch_iter_sharded`, but the boundaries are yielded without collecting them.
+pub fn batch_iter_sharded_lazy(
+    start: u64,
+    end: u64,
+    batch_size: usize,
+    shard_config: ShardConfig,
+) -> impl Iterator<Item = (u64, u64)> {
+    batch_iter_lazy(start, end, batch_size)
+        .filter(move |(start, _)| shard_config.in_range(start / batch_size as u64))
+}
+
+/// The approximate byte size of the keys and values written by `tx`.
+fn tx_size(tx: &DBTransaction) -> usize {
+    tx.ops
+        .iter()
+        .map(|op| match op {
+            DBOp::Insert { key, value, .. } => key.len() + value.len(),
+            DBOp::Delete { key, .. } => key.len(),
+            DBOp::DeletePrefix { prefix, .. } => prefix.len(),
+        })
+        .sum()
+}
+
+fn try_decode_usize(data: &[u8]) -> Result<usize> {
+    Ok(usize::from_be_bytes(
+        data.try_into().map_err(|e| anyhow!("{:?}", e))?,
+    ))
+}
+
+fn decode_batch_index(data: &[u8]) -> Result<usize> {
+    try_decode_usize(data)
+}
+
+fn encode_root_index_key(root: &DataRoot, batch_index: u64) -> Vec<u8> {
+    let mut key = root.as_bytes().to_vec();
+    key.extend_from_slice(&batch_index.to_be_bytes());
+    key
+}
+
+fn encode_mpt_node_key(layer_index: usize, position: usize) -> Vec<u8> {
+    let mut key = layer_index.to_be_bytes().to_vec();
+    key.extend_from_slice(&position.to_be_bytes());
+    key
+}
+
+fn layer_size_key(layer: usize) -> Vec<u8> {
+    let mut key = "layer_size".as_bytes().to_vec();
+    key.extend_from_slice(&layer.to_be_bytes());
+    key
+}
+
+pub struct NodeDBTransaction {
+    tx: DBTransaction,
+    column: u32,
+}
+
+impl NodeDatabase<DataRoot> for FlowDBStore {
+    fn get_node(&self, layer: usize, pos: usize) -> Result<Option<DataRoot>> {
+        let v = try_option!(self.kvdb.get(
+            self.columns.flow_mpt_nodes,
+            &encode_mpt_node_key(layer, pos)
+        )?);
+        if v.len() != DataRoot::len_bytes() {
+            bail!(Error::CorruptNode {
+                layer,
+                pos,
+                len: v.len(),
+            });
+        }
+        Ok(Some(DataRoot::from_slice(&v)))
+    }
+
+    fn get_layer_size(&self, layer: usize) -> Result<Option<usize>> {
+        match self
+            .kvdb
+            .get(self.columns.flow_mpt_nodes, &layer_size_key(layer))?
+        {
+            Some(v) => Ok(Some(try_decode_usize(&v)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn start_transaction(&self) -> Box<dyn NodeTransaction<DataRoot>> {
+        Box::new(NodeDBTransaction {
+            tx: self.kvdb.transaction(),
+            column: self.columns.flow_mpt_nodes,
+        })
+    }
+
+    fn commit(&self, tx: Box<dyn NodeTransaction<DataRoot>>) -> Result<()> {
+        let db_tx: Box<NodeDBTransaction> = tx
+            .into_any()
+            .downcast()
+            .map_err(|e| anyhow!("downcast failed, e={:?}", e))?;
+        self.write_tx("commit_mpt_nodes", db_tx.tx)
+    }
+}
+
+impl NodeTransaction<DataRoot> for NodeDBTransaction {
+    fn save_node(&mut self, layer: usize, pos: usize, node: &DataRoot) {
+        self.tx.put(
+            self.column,
+            &encode_mpt_node_key(layer, pos),
+            node.as_bytes(),
+        );
+    }
+
+    fn save_node_list(&mut self, nodes: &[(usize, usize, &DataRoot)]) {
+        for (layer_index, position, data) in nodes {
+            self.tx.put(
+                self.column,
+                &encode_mpt_node_key(*layer_index, *position),
+                data.as_bytes(),
+            );
+        }
+    }
+
+    fn remove_node_list(&mut self, nodes: &[(usize, usize)]) {
+        for (layer_index, position) in nodes {
+            self.tx
+                .delete(self.column, &encode_mpt_node_key(*layer_index, *position));
+        }
+    }
+
+    fn save_layer_size(&mut self, layer: usize, size: usize) {
+        self.tx
+            .put(self.column, &layer_size_key(layer), &size.to_be_bytes());
+    }
+
+    fn remove_layer_size(&mut self, layer: usize) {
+        self.tx.delete(self.column, &layer_size_key(layer));
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::log_store::log_manager::COL_NUM;
+    use crate::log_store::seal_task_manager::SEAL_VERSION_EPOCH_SIZE;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use zgs_spec::BYTES_PER_LOAD;
+
+    fn memory_db() -> Arc<FlowDBStore> {
+        Arc::new(FlowDBStore::new(Arc::new(kvdb_memorydb::create(COL_NUM))))
+    }
+
+    /// A store whose flow and data share one in-memory db.
+    fn memory_store(config: FlowConfig) -> FlowStore {
+        memory_store_with_db(config).0
+    }
+
+    fn memory_store_with_db(config: FlowConfig) -> (FlowStore, Arc<FlowDBStore>) {
+        let db = memory_db();
+        (FlowStore::new(db.clone(), db.clone(), config), db)
+    }
+
+    #[test]
+    fn test_entry_batch_versioned_encoding() {
+        let mut batch = EntryBatch::new(1);
+        batch
+            .insert_data(0, vec![3u8; 4 * BYTES_PER_SECTOR])
+            .unwrap();
+        let legacy = batch.as_ssz_bytes();
+
+        let encoded = encode_entry_batch(&batch);
+        assert_eq!(encoded[0], VERSION_TAG | ENTRY_BATCH_VERSION);
+        assert_eq!(decode_entry_batch(&encoded).unwrap().as_ssz_bytes(), legacy);
+        // Records written before the versioning are still decodable.
+        assert_eq!(decode_entry_batch(&legacy).unwrap().as_ssz_bytes(), legacy);
+
+        // A record of a future version is rejected instead of being misread.
+        let next_version = ENTRY_BATCH_VERSION + 1;
+        let next = encode_versioned(next_version, &batch);
+        assert!(decode_entry_batch(&next).is_err());
+        let (version, payload) = decode_versioned(&next, false).unwrap();
+        assert_eq!(version, next_version);
+        assert_eq!(payload, legacy.as_slice());
+    }
+
+    #[test]
+    fn test_pad_data_versioned_encoding() {
+        let pad_list = vec![
+            PadPair {
+                start_index: 0x80,
+                data_size: 1024,
+            },
+            PadPair {
+                start_index: 4096,
+                data_size: 256,
+            },
+        ];
+        let mut legacy = Vec::new();
+        for item in &pad_list {
+            legacy.extend(item.as_ssz_bytes());
+        }
+
+        let encoded = encode_pad_data(&pad_list);
+        assert_eq!(encoded[0], VERSION_TAG | PAD_DATA_VERSION);
+        assert_eq!(decode_pad_data(0, &encoded).unwrap(), pad_list);
+        // The first byte of this legacy record has the tag bit set, but its length tells that
+        // it is not versioned.
+        assert_eq!(legacy[0], 0x80);
+        assert_eq!(decode_pad_data(0, &legacy).unwrap(), pad_list);
+        assert!(decode_pad_data(0, &encode_pad_data(&[]))
+            .unwrap()
+            .is_empty());
+        assert!(decode_pad_data(0, &[]).unwrap().is_empty());
+        // A version 1 record has no header.
+        let mut no_header = vec![VERSION_TAG | PAD_DATA_VERSION_NO_HEADER];
+        no_header.extend_from_slice(&legacy);
+        assert_eq!(decode_pad_data(0, &no_header).unwrap(), pad_list);
+
+        // A record of a future version is rejected instead of being misread.
+        let next_version = PAD_DATA_VERSION + 1;
+        let mut next = encoded.clone();
+        next[0] = VERSION_TAG | next_version;
+        assert!(decode_pad_data(0, &next).is_err());
+        let (version, payload) = decode_versioned(&next, false).unwrap();
+        assert_eq!(version, next_version);
+        assert_eq!(&payload[PAD_DATA_HEADER_SIZE..], legacy.as_slice());
+    }
+
+    #[test]
+    fn test_pad_data_header() {
+        let pad_list: Vec<PadPair> = (0..3)
+            .map(|i| PadPair {
+                start_index: i * 100,
+                data_size: 10,
+            })
+            .collect();
+        let encoded = encode_pad_data(&pad_list);
+        // A lost pair is reported with the tx seq instead of being dropped silently.
+        let truncated = &encoded[..encoded.len() - 16];
+        let err = decode_pad_data(7, truncated).unwrap_err().to_string();
+        assert!(
+            err.contains("length mismatch") && err.contains("tx_seq=7"),
+            "{}",
+            err
+        );
+        assert!(decode_pad_data(7, &encoded[..4]).is_err());
+
+        let mut wrong_count = encoded.clone();
+        wrong_count[1..5].copy_from_slice(&4u32.to_be_bytes());
+        assert!(decode_pad_data(7, &wrong_count).is_err());
+        let mut wrong_entry_size = encoded;
+        wrong_entry_size[5..7].copy_from_slice(&24u16.to_be_bytes());
+        assert!(decode_pad_data(7, &wrong_entry_size).is_err());
+    }
+
+    #[test]
+    fn test_resync_seal_set() {
+        let flow_store = memory_store(Default::default());
+        // Mark the seal worker available.
+        flow_store.pull_seal_chunk(usize::MAX).unwrap();
+        flow_store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+
+        // A seal task without data in db is reported instead of panicking.
+        flow_store.seal_manager.to_seal_set.write().insert(0, 0);
+        let err = flow_store.pull_seal_chunk(usize::MAX).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::SealStateDesync { seal_index: 0 })
+        ));
+
+        flow_store.resync_seal_set().unwrap();
+        let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+        assert_eq!(tasks.len(), SEALS_PER_LOAD);
+        assert_eq!(tasks[0].seal_index, SEALS_PER_LOAD as u64);
+    }
+
+    #[test]
+    fn test_rebuild_seal_set() {
+        let flow_store = memory_store(Default::default());
+        flow_store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 3 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        // The seal tasks are lost, e.g. they are appended during a seal worker outage.
+        flow_store.seal_manager.to_seal_set.write().clear();
+
+        assert_eq!(flow_store.rebuild_seal_set(2, 3).unwrap(), SEALS_PER_LOAD);
+        let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+        assert_eq!(tasks[0].seal_index, 2 * SEALS_PER_LOAD as u64);
+        // The queued seals are not added again.
+        assert_eq!(
+            flow_store.rebuild_seal_set(0, 10).unwrap(),
+            2 * SEALS_PER_LOAD
+        );
+        assert_eq!(flow_store.rebuild_seal_set(0, 10).unwrap(), 0);
+        assert_eq!(
+            flow_store.seal_manager.to_seal_set.read().len(),
+            3 * SEALS_PER_LOAD
+        );
+    }
+
+    #[test]
+    fn test_flow_columns() {
+        let kvdb: Arc<dyn ZgsKeyValueDB> = Arc::new(kvdb_memorydb::create(2 * COL_NUM));
+        let shifted = FlowColumns {
+            entry_batch: COL_ENTRY_BATCH + COL_NUM,
+            flow_mpt_nodes: COL_FLOW_MPT_NODES + COL_NUM,
+            pad_data_list: COL_PAD_DATA_LIST + COL_NUM,
+            pad_data_sync_height: COL_PAD_DATA_SYNC_HEIGH + COL_NUM,
+            root_index: COL_ROOT_INDEX + COL_NUM,
+            seal_quarantine: COL_SEAL_QUARANTINE + COL_NUM,
+            batch_merkle: COL_BATCH_MERKLE + COL_NUM,
+            misc: COL_MISC + COL_NUM,
+        };
+        let stores: Vec<FlowStore> = [FlowColumns::default(), shifted]
+            .into_iter()
+            .map(|columns| {
+                let db = Arc::new(FlowDBStore::new_with_columns(kvdb.clone(), columns));
+                FlowStore::new(db.clone(), db, Default::default())
+            })
+            .collect();
+
+        // The stores sharing one db do not see the data of each other.
+        for (i, store) in stores.iter().enumerate() {
+            store
+                .append_entries(ChunkArray {
+                    data: vec![i as u8 + 1; BYTES_PER_LOAD],
+                    start_index: SECTORS_PER_LOAD as u64,
+                })
+                .unwrap();
+            store.put_pad_data_sync_height(i as u64).unwrap();
+        }
+        for (i, store) in stores.iter().enumerate() {
+            let entries = store
+                .get_entries(SECTORS_PER_LOAD as u64, 2 * SECTORS_PER_LOAD as u64)
+                .unwrap()
+                .unwrap();
+            assert_eq!(entries.data, vec![i as u8 + 1; BYTES_PER_LOAD]);
+            assert_eq!(store.get_pad_data_sync_height().unwrap(), Some(i as u64));
+            assert_eq!(store.get_num_entries().unwrap(), SECTORS_PER_LOAD as u64);
+        }
+    }
 
-    fn get_pad_data(&self, start_index: u64) -> crate::error::Result<Option<Vec<PadPair>>> {
-        self.flow_db.get_pad_data(start_index)
+    #[test]
+    fn test_batch_iter_lazy() {
+        assert_eq!(
+            batch_iter(10, 2050, 1024),
+            vec![(10, 1024), (1024, 2048), (2048, 2050)]
+        );
+        assert!(batch_iter(10, 10, 1024).is_empty());
+        for (start, end) in [(0, 1), (1, 1024), (1023, 4097), (4096, 8192)] {
+            assert_eq!(
+                batch_iter_lazy(start, end, 1024).collect::<Vec<_>>(),
+                batch_iter(start, end, 1024)
+            );
+        }
+        let shard_config = ShardConfig {
+            shard_id: 1,
+            num_shard: 2,
+        };
+        assert_eq!(
+            batch_iter_sharded_lazy(10, 4100, 1024, shard_config).collect::<Vec<_>>(),
+            vec![(1024, 2048), (3072, 4096)]
+        );
+        assert_eq!(
+            batch_iter_sharded(10, 4100, 1024, shard_config),
+            vec![(1024, 2048), (3072, 4096)]
+        );
     }
 
-    fn get_pad_data_sync_height(&self) -> Result<Option<u64>> {
-        self.data_db.get_pad_data_sync_height()
+    #[test]
+    fn test_migrate_keys() {
+        let kvdb: Arc<dyn ZgsKeyValueDB> = Arc::new(kvdb_memorydb::create(COL_NUM));
+        let db = Arc::new(FlowDBStore::new(kvdb.clone()));
+        let store = FlowStore::new(db.clone(), db.clone(), Default::default());
+        let data = ChunkArray {
+            data: vec![1u8; 3 * BYTES_PER_LOAD],
+            start_index: SECTORS_PER_LOAD as u64,
+        };
+        store.append_entries(data.clone()).unwrap();
+
+        let add_prefix = |key: &[u8]| {
+            if key.starts_with(b"v1") {
+                key.to_vec()
+            } else {
+                [&b"v1"[..], key].concat()
+            }
+        };
+        assert_eq!(db.migrate_keys(COL_ENTRY_BATCH, 2, add_prefix).unwrap(), 3);
+        assert_eq!(db.migrate_keys(COL_ENTRY_BATCH, 2, add_prefix).unwrap(), 0);
+        for item in kvdb.iter(COL_ENTRY_BATCH) {
+            assert!(item.unwrap().0.starts_with(b"v1"));
+        }
+        assert!(store
+            .get_entries(data.start_index, data.start_index + 1)
+            .unwrap()
+            .is_none());
+
+        let strip_prefix = |key: &[u8]| key.strip_prefix(b"v1").unwrap_or(key).to_vec();
+        assert_eq!(
+            db.migrate_keys(COL_ENTRY_BATCH, 2, strip_prefix).unwrap(),
+            3
+        );
+        assert_eq!(
+            store
+                .get_entries(
+                    data.start_index,
+                    data.start_index + 3 * SECTORS_PER_LOAD as u64
+                )
+                .unwrap()
+                .unwrap(),
+            data
+        );
     }
-}
 
-impl FlowWrite for FlowStore {
-    /// Return the roots of completed chunks. The order is guaranteed to be increasing
-    /// by chunk index.
-    fn append_entries(&self, data: ChunkArray) -> Result<Vec<(u64, DataRoot)>> {
-        let start_time = Instant::now();
-        let mut to_seal_set = self.seal_manager.to_seal_set.write();
-        trace!("append_entries: {} {}", data.start_index, data.data.len());
-        if data.data.len() % BYTES_PER_SECTOR != 0 {
-            bail!("append_entries: invalid data size, len={}", data.data.len());
+    #[test]
+    fn test_recompute_layer_sizes() {
+        let kvdb: Arc<dyn ZgsKeyValueDB> = Arc::new(kvdb_memorydb::create(COL_NUM));
+        let db = Arc::new(FlowDBStore::new(kvdb));
+        let store = FlowStore::new(db.clone(), db.clone(), Default::default());
+        let mut tx = db.start_transaction();
+        let node = DataRoot::repeat_byte(1);
+        tx.save_node_list(&[(0, 0, &node), (0, 1, &node), (0, 2, &node), (1, 0, &node)]);
+        tx.save_layer_size(0, 3);
+        tx.save_layer_size(1, 1);
+        tx.save_layer_size(2, 0);
+        db.commit(tx).unwrap();
+        assert_eq!(store.recompute_layer_sizes().unwrap(), vec![3, 1, 0]);
+
+        // A size recorded before the crash disagrees with the nodes.
+        let mut tx = db.start_transaction();
+        tx.save_node(1, 1, &node);
+        tx.save_layer_size(0, 5);
+        tx.save_layer_size(4, 2);
+        db.commit(tx).unwrap();
+        assert_eq!(store.recompute_layer_sizes().unwrap(), vec![3, 2, 0]);
+        assert_eq!(db.get_layer_size(0).unwrap(), Some(3));
+        assert_eq!(db.get_layer_size(1).unwrap(), Some(2));
+        assert_eq!(db.get_layer_size(4).unwrap(), None);
+    }
+
+    #[test]
+    fn test_open_read_only() {
+        let db = memory_db();
+        let data = ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: SECTORS_PER_LOAD as u64,
+        };
+        FlowStore::new(db.clone(), db.clone(), Default::default())
+            .append_entries(data.clone())
+            .unwrap();
+
+        let store = FlowStore::open_read_only(db.clone(), db, Default::default());
+        assert_eq!(
+            store
+                .get_entries(data.start_index, data.start_index + SECTORS_PER_LOAD as u64)
+                .unwrap(),
+            Some(data.clone())
+        );
+        let is_read_only =
+            |err: anyhow::Error| matches!(err.downcast_ref::<Error>(), Some(Error::ReadOnly));
+        assert!(is_read_only(store.append_entries(data).unwrap_err()));
+        assert!(is_read_only(store.truncate(0).unwrap_err()));
+        assert!(is_read_only(store.put_pad_data_sync_height(1).unwrap_err()));
+        assert!(is_read_only(store.delete_batch_list(&[1]).unwrap_err()));
+        assert!(is_read_only(store.resync_seal_set().unwrap_err()));
+        assert_eq!(store.get_num_entries().unwrap(), SECTORS_PER_LOAD as u64);
+    }
+
+    #[test]
+    fn test_flush() {
+        let config = FlowConfig {
+            coalesce_small_appends: true,
+            append_buffer_flush_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD / 2],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert!(db.get_entry_batch(1).unwrap().is_none());
+
+        store.flush().unwrap();
+        assert!(store.append_buffer.lock().is_empty());
+        assert!(db.get_entry_batch(1).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_coldest_batches() {
+        let (store, db) = memory_store_with_db(Default::default());
+        assert!(store.coldest_batches(1).is_err());
+
+        let config = FlowConfig {
+            batch_access_tracking_capacity: 2,
+            ..Default::default()
+        };
+        let store = FlowStore::new(db.clone(), db, config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 4 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let read_batch = |batch_index: u64| {
+            let start_index = batch_index * SECTORS_PER_LOAD as u64;
+            store
+                .get_entries(start_index, start_index + 1)
+                .unwrap()
+                .unwrap();
+        };
+        read_batch(1);
+        read_batch(4);
+        read_batch(2);
+
+        // Batch 1 is evicted from the tracker, and batch 4 is read before batch 2.
+        assert_eq!(store.coldest_batches(10).unwrap(), vec![1, 3, 4, 2]);
+        assert_eq!(store.coldest_batches(3).unwrap(), vec![1, 3, 4]);
+        assert_eq!(store.coldest_batches(1).unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn test_subscribe_truncations() {
+        let store = memory_store(Default::default());
+        let mut receiver = store.subscribe_truncations();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 2 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let start_index = 2 * SECTORS_PER_LOAD as u64 + 1;
+        store.truncate(start_index).unwrap();
+        let event = receiver.try_recv().unwrap();
+        assert_eq!(event.start_index, start_index);
+        assert_eq!(event.resealed_seals, 0);
+        assert!(receiver.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_entry_batch_raw() {
+        let (store, db) = memory_store_with_db(Default::default());
+        let roots = store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert!(db.get_entry_batch_raw(2).unwrap().is_none());
+        let raw = db.get_entry_batch_raw(1).unwrap().unwrap();
+        assert_eq!(
+            raw,
+            encode_entry_batch(&db.get_entry_batch(1).unwrap().unwrap())
+        );
+
+        let imported = memory_db();
+        assert!(imported
+            .put_entry_batch_raw(1, &raw[..raw.len() / 2])
+            .is_err());
+        assert!(imported.get_entry_batch_raw(1).unwrap().is_none());
+        imported.put_entry_batch_raw(1, &raw).unwrap();
+        assert_eq!(imported.get_entry_batch_raw(1).unwrap().unwrap(), raw);
+        assert_eq!(
+            imported.get_batch_index_of_root(&roots[0].1).unwrap(),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_append_entries_by_shard() {
+        let data = ChunkArray {
+            data: (0..4 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+            start_index: SECTORS_PER_LOAD as u64,
+        };
+        let expected = memory_store(Default::default())
+            .append_entries(data.clone())
+            .unwrap();
+
+        let create_shard_store = |shard_id| {
+            let config = FlowConfig {
+                shard_config: Arc::new(RwLock::new(ShardConfig::new(shard_id, 2).unwrap())),
+                ..Default::default()
+            };
+            memory_store_with_db(config)
+        };
+        let (store_0, db_0) = create_shard_store(0);
+        let (store_1, db_1) = create_shard_store(1);
+        let completed = append_entries_by_shard(&[&store_0, &store_1], data, 2).unwrap();
+        assert_eq!(completed, expected);
+        for batch_index in 1..5 {
+            let owned_by_0 = batch_index % 2 == 0;
+            assert_eq!(
+                db_0.get_entry_batch(batch_index).unwrap().is_some(),
+                owned_by_0
+            );
+            assert_eq!(
+                db_1.get_entry_batch(batch_index).unwrap().is_some(),
+                !owned_by_0
+            );
         }
-        let mut batch_list = Vec::new();
-        for (start_entry_index, end_entry_index) in batch_iter(
-            data.start_index,
-            data.start_index + bytes_to_entries(data.data.len() as u64),
-            self.config.batch_size,
-        ) {
-            // TODO: Avoid mem-copy if possible.
-            let chunk = data
-                .sub_array(start_entry_index, end_entry_index)
-                .expect("in range");
+    }
 
-            let chunk_index = chunk.start_index / self.config.batch_size as u64;
-            if !self.config.shard_config.read().in_range(chunk_index) {
-                // The data are in a shard range that we are not storing.
-                continue;
+    #[test]
+    fn test_persist_seal_set() {
+        let config = FlowConfig {
+            seal_set_compaction_interval: Some(Duration::from_secs(60)),
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config.clone());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 2 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let to_seal_set = store.seal_manager.to_seal_set.read().clone();
+        assert_eq!(to_seal_set.len(), 2 * SEALS_PER_LOAD);
+
+        // The consecutive seals of the same version are persisted as one run.
+        assert_eq!(store.persist_seal_set().unwrap(), 1);
+        let reopened = FlowStore::new(db.clone(), db.clone(), config);
+        assert_eq!(reopened.restore_seal_set().unwrap(), 2 * SEALS_PER_LOAD);
+        assert_eq!(*reopened.seal_manager.to_seal_set.read(), to_seal_set);
+
+        let empty = memory_store(Default::default());
+        assert_eq!(empty.restore_seal_set().unwrap(), 0);
+
+        // A corrupt run longer than the stored batches can hold is rejected.
+        let mut corrupt = db.get_seal_set().unwrap().unwrap();
+        corrupt[8..16].copy_from_slice(&(1u64 << 40).to_be_bytes());
+        db.put_seal_set(&corrupt).unwrap();
+        assert!(reopened.restore_seal_set().is_err());
+        assert_eq!(*reopened.seal_manager.to_seal_set.read(), to_seal_set);
+    }
+
+    #[test]
+    fn test_truncate_pad_data() {
+        let store = memory_store(Default::default());
+        let pad = |start_index, data_size| PadPair {
+            start_index,
+            data_size,
+        };
+        store.put_pad_data(&[pad(0, 10), pad(20, 10)], 0).unwrap();
+        // The second pad straddles the truncation point and the third one is after it.
+        store
+            .put_pad_data(&[pad(30, 5), pad(40, 10), pad(50, 10)], 1)
+            .unwrap();
+        store.put_pad_data(&[pad(60, 5)], 2).unwrap();
+
+        store.truncate(45).unwrap();
+        assert_eq!(
+            store.get_pad_data(0).unwrap().unwrap(),
+            vec![pad(0, 10), pad(20, 10)]
+        );
+        assert_eq!(
+            store.get_pad_data(1).unwrap().unwrap(),
+            vec![pad(30, 5), pad(40, 5)]
+        );
+        assert!(store.get_pad_data(2).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_read_limiter() {
+        let limiter = Arc::new(ReadLimiter::new(1));
+        let permit = limiter.acquire();
+        let acquired = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let limiter = limiter.clone();
+            let acquired = acquired.clone();
+            std::thread::spawn(move || {
+                let _permit = limiter.acquire();
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+        std::thread::sleep(Duration::from_millis(100));
+        assert!(!acquired.load(Ordering::SeqCst));
+
+        drop(permit);
+        handle.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        assert_eq!(*limiter.in_flight.lock(), 0);
+    }
+
+    #[test]
+    fn test_verify_commitment() {
+        let flow_db = memory_db();
+        let data_db = memory_db();
+        let store = FlowStore::new(flow_db.clone(), data_db, Default::default());
+        let roots = store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 2 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(roots.len(), 2);
+        let expected_root = DataRoot::repeat_byte(9);
+        // The MPT is written by the flow merkle tree of `LogManager`, so the nodes are saved
+        // directly here. Batch 0 has no data in this store.
+        let mut tx = flow_db.start_transaction();
+        tx.save_node(0, 0, &DataRoot::repeat_byte(7));
+        for (batch_index, root) in &roots {
+            tx.save_node(0, *batch_index as usize, root);
+        }
+        tx.save_layer_size(0, 3);
+        tx.save_node(1, 0, &expected_root);
+        tx.save_layer_size(1, 1);
+        flow_db.commit(tx).unwrap();
+
+        assert_eq!(
+            store.verify_commitment(&expected_root).unwrap(),
+            CommitmentCheck::Match
+        );
+        // The stored batches agree with the tree, so the divergence is elsewhere.
+        assert_eq!(
+            store.verify_commitment(&DataRoot::repeat_byte(8)).unwrap(),
+            CommitmentCheck::Mismatch {
+                local_root: Some(expected_root),
+                first_divergent_batch: None,
             }
+        );
 
-            // TODO: Try to avoid loading from db if possible.
-            let mut batch = self
-                .data_db
-                .get_entry_batch(chunk_index)?
-                .unwrap_or_else(|| EntryBatch::new(chunk_index));
-            let completed_seals = batch.insert_data(
-                (chunk.start_index % self.config.batch_size as u64) as usize,
-                chunk.data,
-            )?;
-            if self.seal_manager.seal_worker_available() {
-                completed_seals.into_iter().for_each(|x| {
-                    to_seal_set.insert(
-                        chunk_index as usize * SEALS_PER_LOAD + x as usize,
-                        self.seal_manager.to_seal_version(),
-                    );
-                });
+        let mut tx = flow_db.start_transaction();
+        tx.save_node(0, 2, &DataRoot::repeat_byte(6));
+        flow_db.commit(tx).unwrap();
+        assert_eq!(
+            store.verify_commitment(&DataRoot::repeat_byte(8)).unwrap(),
+            CommitmentCheck::Mismatch {
+                local_root: Some(expected_root),
+                first_divergent_batch: Some(2),
             }
+        );
+    }
 
-            batch_list.push((chunk_index, batch));
+    #[test]
+    fn test_truncate_in_chunks() {
+        let config = FlowConfig {
+            delete_batch_chunk_size: 2,
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 6 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(store.resume_truncate().unwrap(), None);
+
+        store.truncate(2 * SECTORS_PER_LOAD as u64 + 10).unwrap();
+        assert_eq!(db.pending_truncate().unwrap(), None);
+        assert_eq!(
+            db.get_entry_batch(2).unwrap().unwrap().num_stored_sectors(),
+            10
+        );
+        for batch_index in 3..7 {
+            assert!(db.get_entry_batch(batch_index).unwrap().is_none());
         }
 
-        metrics::APPEND_ENTRIES.update_since(start_time);
-        self.data_db.put_entry_batch_list(batch_list)
+        // A crash after the tail is deleted leaves a prefix of the flow and the progress key,
+        // so the truncate is finished on the next open.
+        db.kvdb
+            .put(
+                db.columns.misc,
+                TRUNCATE_PROGRESS_KEY,
+                &(SECTORS_PER_LOAD as u64 + 5).to_be_bytes(),
+            )
+            .unwrap();
+        assert_eq!(
+            store.resume_truncate().unwrap(),
+            Some(SECTORS_PER_LOAD as u64 + 5)
+        );
+        assert_eq!(db.pending_truncate().unwrap(), None);
+        assert_eq!(
+            db.get_entry_batch(1).unwrap().unwrap().num_stored_sectors(),
+            5
+        );
+        assert!(db.get_entry_batch(2).unwrap().is_none());
     }
 
-    fn truncate(&self, start_index: u64) -> crate::error::Result<()> {
-        let mut to_seal_set = self.seal_manager.to_seal_set.write();
-        let to_reseal = self.data_db.truncate(start_index, self.config.batch_size)?;
+    #[test]
+    fn test_initialize_genesis() {
+        let flow_db = memory_db();
+        let data_db = memory_db();
+        let store = FlowStore::new(flow_db.clone(), data_db, Default::default());
+        assert_eq!(store.init_state().unwrap(), FlowInitState::Uninitialized);
 
-        to_seal_set.split_off(&(start_index as usize / SECTORS_PER_SEAL));
-        let new_seal_version = self.seal_manager.inc_seal_version();
+        store.initialize_genesis().unwrap();
+        assert_eq!(store.init_state().unwrap(), FlowInitState::Empty);
+        assert_eq!(flow_db.get_layer_size(0).unwrap(), Some(0));
+        assert_eq!(store.flow_root().unwrap(), None);
+        assert_eq!(
+            store.health().unwrap().init_state,
+            Some(FlowInitState::Empty)
+        );
+
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(store.init_state().unwrap(), FlowInitState::NonEmpty);
+        store.initialize_genesis().unwrap();
+        assert_eq!(store.init_state().unwrap(), FlowInitState::NonEmpty);
+
+        // A store written before the genesis state is recorded is only marked.
+        let (legacy, db) = memory_store_with_db(Default::default());
+        legacy
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(legacy.init_state().unwrap(), FlowInitState::Uninitialized);
+        legacy.initialize_genesis().unwrap();
+        assert_eq!(legacy.init_state().unwrap(), FlowInitState::NonEmpty);
+        assert_eq!(db.get_layer_size(0).unwrap(), None);
+    }
+
+    #[test]
+    fn test_inspect_seal() {
+        let store = memory_store(Default::default());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(store.inspect_seal(0).unwrap(), None);
 
-        to_reseal.into_iter().for_each(|x| {
-            to_seal_set.insert(x, new_seal_version);
+        let seal_index = SEALS_PER_LOAD;
+        let inspect = store.inspect_seal(seal_index).unwrap().unwrap();
+        assert_eq!(
+            inspect,
+            SealInspect {
+                queued_version: Some(0),
+                current_version: 0,
+                leased: false,
+                batch_exists: true,
+                non_sealed_data_available: true,
+            }
+        );
+
+        // A task whose batch is lost cannot be pulled.
+        store
+            .seal_manager
+            .to_seal_set
+            .write()
+            .insert(3 * SEALS_PER_LOAD, 0);
+        let inspect = store.inspect_seal(3 * SEALS_PER_LOAD).unwrap().unwrap();
+        assert!(!inspect.batch_exists);
+        assert!(!inspect.non_sealed_data_available);
+    }
+
+    #[test]
+    fn test_proof_sweep() {
+        let db = memory_db();
+        let stores = [0, 2].map(|proof_sweep_prefetch| {
+            FlowStore::new(
+                db.clone(),
+                db.clone(),
+                FlowConfig {
+                    proof_sweep_prefetch,
+                    ..Default::default()
+                },
+            )
         });
-        Ok(())
+        stores[0]
+            .append_entries(ChunkArray {
+                data: (0..4 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let batches = [1, 2, 3, 4];
+        let selector = |batch_index: u64| batch_index as usize * 7;
+        let expected: Vec<FlowProof> = batches
+            .iter()
+            .map(|&batch_index| {
+                stores[0]
+                    .gen_proof_in_batch(batch_index as usize, selector(batch_index))
+                    .unwrap()
+            })
+            .collect();
+        for store in &stores {
+            assert_eq!(store.proof_sweep(&batches, selector).unwrap(), expected);
+        }
+
+        // A missing batch fails the sweep.
+        assert!(stores[1].proof_sweep(&[1, 2, 9, 3], selector).is_err());
     }
 
-    fn update_shard_config(&self, shard_config: ShardConfig) {
-        *self.config.shard_config.write() = shard_config;
+    #[test]
+    fn test_seal_set_summary() {
+        let store = memory_store(Default::default());
+        assert_eq!(store.seal_set_summary(), SealSetSummary::default());
+
+        {
+            let mut to_seal_set = store.seal_manager.to_seal_set.write();
+            for seal_index in [SEALS_PER_LOAD + 3, SEALS_PER_LOAD + 5, 4 * SEALS_PER_LOAD] {
+                to_seal_set.insert(seal_index, 0);
+            }
+        }
+        assert_eq!(
+            store.seal_set_summary(),
+            SealSetSummary {
+                count: 3,
+                min_index: Some(SEALS_PER_LOAD + 3),
+                max_index: Some(4 * SEALS_PER_LOAD),
+                load_chunks: 2,
+            }
+        );
     }
 
-    fn put_pad_data(&self, data_sizes: &[PadPair], tx_seq: u64) -> crate::error::Result<()> {
-        self.flow_db.put_pad_data(data_sizes, tx_seq)
+    #[test]
+    fn test_archive_mode_truncate() {
+        let config = FlowConfig {
+            archive_mode: true,
+            archive_min_retained_index: 3 * SECTORS_PER_LOAD as u64,
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 4 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+
+        // A deep truncate is rejected and does not delete anything.
+        for start_index in [0, 2 * SECTORS_PER_LOAD as u64 + 10] {
+            let err = store.truncate(start_index).unwrap_err();
+            assert!(matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::TruncateBlocked { start_index: s, min_retained_index })
+                    if *s == start_index && *min_retained_index == 3 * SECTORS_PER_LOAD as u64
+            ));
+            assert!(store.truncate_soft(start_index).is_err());
+        }
+        for batch_index in 1..5 {
+            assert_eq!(
+                db.get_entry_batch(batch_index)
+                    .unwrap()
+                    .unwrap()
+                    .num_stored_sectors(),
+                SECTORS_PER_LOAD
+            );
+        }
+
+        // A shallow truncate proceeds.
+        store.truncate(4 * SECTORS_PER_LOAD as u64).unwrap();
+        assert!(db.get_entry_batch(3).unwrap().is_some());
+        assert!(db.get_entry_batch(4).unwrap().is_none());
     }
 
-    fn put_pad_data_sync_height(&self, sync_index: u64) -> crate::error::Result<()> {
-        self.data_db.put_pad_data_sync_height(sync_index)
+    #[test]
+    fn test_pad_data_tx_seqs() {
+        let db = memory_db();
+        assert!(db.pad_data_tx_seqs().unwrap().is_empty());
+        for tx_seq in [300, 1, 5] {
+            let pad = PadPair {
+                start_index: tx_seq * 10,
+                data_size: 10,
+            };
+            db.put_pad_data(&[pad], tx_seq).unwrap();
+        }
+        assert_eq!(db.pad_data_tx_seqs().unwrap(), vec![1, 5, 300]);
     }
-}
 
-impl FlowSeal for FlowStore {
-    fn pull_seal_chunk(&self, seal_index_max: usize) -> Result<Option<Vec<SealTask>>> {
-        let to_seal_set = self.seal_manager.to_seal_set.read();
-        self.seal_manager.update_pull_time();
+    #[test]
+    fn test_read_beyond_flow_end() {
+        let config = FlowConfig {
+            reject_reads_past_flow_end: true,
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        let batch_start = SECTORS_PER_LOAD as u64;
+        // Nothing is checked before the flow length is known.
+        assert!(store
+            .get_entries(batch_start, batch_start + 1)
+            .unwrap()
+            .is_none());
 
-        let mut to_seal_iter = to_seal_set.iter();
-        let (&first_index, &first_version) = try_option!(to_seal_iter.next());
-        if first_index >= seal_index_max {
-            return Ok(None);
+        store.set_flow_length(batch_start);
+        let append = |start_index| {
+            store
+                .append_entries(ChunkArray {
+                    data: vec![1u8; BYTES_PER_LOAD],
+                    start_index,
+                })
+                .unwrap()
+        };
+        append(batch_start);
+        assert_eq!(store.flow_length(), Some(2 * batch_start));
+        assert!(store
+            .get_entries(batch_start, batch_start + 1)
+            .unwrap()
+            .is_some());
+        let beyond_end = |start: u64| {
+            let err = store.get_entries(start, start + 1).unwrap_err();
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::BeyondFlowEnd { requested, flow_length })
+                    if *requested == start && Some(*flow_length) == store.flow_length()
+            )
+        };
+        assert!(beyond_end(2 * batch_start));
+        assert!(beyond_end(5 * batch_start));
+
+        // A missing batch inside the flow is still reported as `None`.
+        append(3 * batch_start);
+        assert_eq!(store.flow_length(), Some(4 * batch_start));
+        assert!(store
+            .get_entries(2 * batch_start, 2 * batch_start + 1)
+            .unwrap()
+            .is_none());
+
+        store.truncate(batch_start + 10).unwrap();
+        assert_eq!(store.flow_length(), Some(batch_start + 10));
+        assert!(beyond_end(batch_start + 10));
+    }
+
+    #[test]
+    fn test_get_entries_owned() {
+        let store = memory_store(Default::default());
+        let batch_size = SECTORS_PER_LOAD as u64;
+        store
+            .append_entries(ChunkArray {
+                data: (0..4 * BYTES_PER_LOAD)
+                    .map(|i| (i / BYTES_PER_LOAD) as u8)
+                    .collect(),
+                start_index: batch_size,
+            })
+            .unwrap();
+
+        // A single shard owns all the batches, so the range is read in one piece.
+        let entries = store
+            .get_entries_owned(batch_size + 10, 5 * batch_size)
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].start_index, batch_size + 10);
+        assert_eq!(
+            entries[0].data.len(),
+            (4 * batch_size - 10) as usize * BYTES_PER_SECTOR
+        );
+        // The batches after the flow end are skipped.
+        assert_eq!(
+            store.get_entries_owned(batch_size, 8 * batch_size).unwrap()[0]
+                .data
+                .len(),
+            4 * BYTES_PER_LOAD
+        );
+
+        store.update_shard_config(ShardConfig {
+            num_shard: 2,
+            shard_id: 1,
+        });
+        let entries = store
+            .get_entries_owned(batch_size + 10, 5 * batch_size)
+            .unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].start_index, batch_size + 10);
+        assert_eq!(
+            entries[0].data,
+            vec![0u8; (batch_size - 10) as usize * BYTES_PER_SECTOR]
+        );
+        assert_eq!(entries[1].start_index, 3 * batch_size);
+        assert_eq!(entries[1].data, vec![2u8; BYTES_PER_LOAD]);
+    }
+
+    #[test]
+    fn test_reset_seal_versions() {
+        let store = memory_store(Default::default());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let tasks = store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+
+        store.reset_seal_versions().unwrap();
+        let answers = tasks
+            .into_iter()
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        // The answers to the tasks pulled before the reset are stale.
+        assert_eq!(
+            store.submit_seal_result(answers).unwrap().len(),
+            SEALS_PER_LOAD
+        );
+        let tasks = store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+        assert_eq!(tasks.len(), SEALS_PER_LOAD);
+        assert!(tasks
+            .iter()
+            .all(|task| task.version == SEAL_VERSION_EPOCH_SIZE));
+    }
+
+    #[test]
+    fn test_sealed_seal_indices() {
+        let store = memory_store(Default::default());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(store.sealed_seal_indices(1).unwrap(), Some(vec![]));
+        assert_eq!(store.sealed_seal_indices(2).unwrap(), None);
+
+        let answers = store
+            .pull_seal_chunk(usize::MAX)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .filter(|task| task.seal_index % SEALS_PER_LOAD as u64 % 3 == 0)
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        store.submit_seal_result(answers).unwrap();
+        assert_eq!(
+            store.sealed_seal_indices(1).unwrap(),
+            Some((0..SEALS_PER_LOAD as u16).step_by(3).collect())
+        );
+    }
+
+    #[test]
+    fn test_append_write_window() {
+        let config = FlowConfig {
+            append_write_window_bytes: Some(4 * BYTES_PER_LOAD),
+            append_write_window_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        let batch_size = SECTORS_PER_LOAD as u64;
+        let quarter = SECTORS_PER_LOAD / 4;
+        let mut completed = Vec::new();
+        for i in 0..4 {
+            completed = store
+                .append_entries(ChunkArray {
+                    data: vec![i as u8 + 1; quarter * BYTES_PER_SECTOR],
+                    start_index: batch_size + (i * quarter) as u64,
+                })
+                .unwrap();
         }
+        // The roots are returned and the data are readable before they are committed.
+        assert_eq!(completed.len(), 1);
+        assert_eq!(completed[0].0, 1);
+        assert!(db.get_entry_batch(1).unwrap().is_none());
+        let entries = store
+            .get_entries(batch_size, 2 * batch_size)
+            .unwrap()
+            .unwrap();
+        assert_eq!(entries.data.len(), BYTES_PER_LOAD);
+        assert_eq!(entries.data[BYTES_PER_LOAD - 1], 4);
+        assert_eq!(store.batch_index_of_root(&completed[0].1).unwrap(), None);
 
-        let mut tasks = Vec::with_capacity(SEALS_PER_LOAD);
+        // The window reaches its size with the next full batch and is committed once.
+        store
+            .append_entries(ChunkArray {
+                data: vec![5u8; 2 * BYTES_PER_LOAD],
+                start_index: 2 * batch_size,
+            })
+            .unwrap();
+        assert!(store.write_window.lock().batches.is_empty());
+        assert_eq!(
+            db.get_entry_batch(1).unwrap().unwrap().num_stored_sectors(),
+            SECTORS_PER_LOAD
+        );
+        assert!(db.get_entry_batch(3).unwrap().is_some());
+        assert_eq!(store.batch_index_of_root(&completed[0].1).unwrap(), Some(1));
 
-        let batch_data = self
-            .data_db
-            .get_entry_batch((first_index / SEALS_PER_LOAD) as u64)?
-            .expect("Lost data chunk in to_seal_set");
+        store
+            .append_entries(ChunkArray {
+                data: vec![6u8; BYTES_PER_SECTOR],
+                start_index: 4 * batch_size,
+            })
+            .unwrap();
+        assert!(db.get_entry_batch(4).unwrap().is_none());
+        store.flush().unwrap();
+        assert!(db.get_entry_batch(4).unwrap().is_some());
+    }
 
-        for (&seal_index, &version) in
-            std::iter::once((&first_index, &first_version)).chain(to_seal_iter.filter(|(&x, _)| {
-                first_index / SEALS_PER_LOAD == x / SEALS_PER_LOAD && x < seal_index_max
-            }))
-        {
-            let seal_index_local = seal_index % SEALS_PER_LOAD;
-            let non_sealed_data = batch_data
-                .get_non_sealed_data(seal_index_local as u16)
-                .expect("Lost seal chunk in to_seal_set");
-            tasks.push(SealTask {
-                seal_index: seal_index as u64,
-                version,
-                non_sealed_data,
+    #[test]
+    fn test_rebuild_root_index() {
+        // The batches are indexed in several chunks.
+        let config = FlowConfig {
+            delete_batch_chunk_size: 2,
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        let completed = store
+            .append_entries(ChunkArray {
+                data: (0..3 * BYTES_PER_LOAD)
+                    .map(|i| (i / BYTES_PER_LOAD) as u8 + 1)
+                    .collect(),
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(completed.len(), 3);
+        let index_size = || db.kvdb.iter(db.columns.root_index).count();
+
+        // The truncate leaves the entries of the removed batches in the index.
+        store.truncate(2 * SECTORS_PER_LOAD as u64).unwrap();
+        assert_eq!(index_size(), 3);
+        assert_eq!(store.rebuild_root_index().unwrap(), 1);
+        assert_eq!(index_size(), 1);
+        assert_eq!(store.batch_index_of_root(&completed[0].1).unwrap(), Some(1));
+        assert_eq!(store.batch_index_of_root(&completed[2].1).unwrap(), None);
+
+        // The end of the flow bounds the rebuild once it is known.
+        store.set_flow_length(SECTORS_PER_LOAD as u64);
+        assert_eq!(store.rebuild_root_index().unwrap(), 0);
+        store.set_flow_length(2 * SECTORS_PER_LOAD as u64);
+        assert_eq!(store.rebuild_root_index().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_validate_sector_size() {
+        assert!(FlowConfig::default().validate().is_ok());
+        for sector_size in [0, 100, 2 * BYTES_PER_SECTOR] {
+            let config = FlowConfig {
+                sector_size,
+                ..Default::default()
+            };
+            assert!(config.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn test_prioritize_recently_read_seals() {
+        let config = FlowConfig {
+            batch_access_tracking_capacity: 16,
+            prioritize_recently_read_seals: true,
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        let batch_size = SECTORS_PER_LOAD as u64;
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 3 * BYTES_PER_LOAD],
+                start_index: batch_size,
+            })
+            .unwrap();
+        let first_pulled_batch = |seal_index_max| {
+            store
+                .pull_seal_chunk(seal_index_max)
+                .unwrap()
+                .unwrap()
+                .iter()
+                .map(|task| task.seal_index / SEALS_PER_LOAD as u64)
+                .min()
+                .unwrap()
+        };
+        // Without reads, the tasks are pulled in index order.
+        assert_eq!(first_pulled_batch(usize::MAX), 1);
+
+        store
+            .get_entries(3 * batch_size, 3 * batch_size + 1)
+            .unwrap();
+        assert_eq!(first_pulled_batch(usize::MAX), 3);
+        // A read batch beyond `seal_index_max` is not preferred.
+        assert_eq!(first_pulled_batch(3 * SEALS_PER_LOAD), 1);
+        store
+            .get_entries(2 * batch_size, 2 * batch_size + 1)
+            .unwrap();
+        assert_eq!(first_pulled_batch(usize::MAX), 2);
+    }
+
+    #[test]
+    fn test_monotonic_pad_data_sync_height() {
+        let config = FlowConfig {
+            monotonic_pad_data_sync_height: true,
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        store.put_pad_data_sync_height(5).unwrap();
+        store.put_pad_data_sync_height(5).unwrap();
+        let err = store.put_pad_data_sync_height(3).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::PadDataSyncHeightRegression {
+                current: 5,
+                requested: 3
+            })
+        ));
+        assert_eq!(store.get_pad_data_sync_height().unwrap(), Some(5));
+
+        store.set_pad_data_sync_height(3, true).unwrap();
+        assert_eq!(store.get_pad_data_sync_height().unwrap(), Some(3));
+
+        assert!(!store
+            .compare_and_set_pad_data_sync_height(Some(4), 6)
+            .unwrap());
+        assert!(store
+            .compare_and_set_pad_data_sync_height(Some(3), 6)
+            .unwrap());
+        assert_eq!(store.get_pad_data_sync_height().unwrap(), Some(6));
+
+        // The height rolls back to the first tx whose pads are truncated.
+        let pad = |start_index| PadPair {
+            start_index,
+            data_size: 10,
+        };
+        store.put_pad_data(&[pad(0)], 1).unwrap();
+        store.put_pad_data(&[pad(40)], 2).unwrap();
+        store.put_pad_data(&[pad(60)], 3).unwrap();
+        store.truncate(45).unwrap();
+        assert_eq!(store.get_pad_data_sync_height().unwrap(), Some(2));
+    }
+
+    #[test]
+    fn test_concurrent_delete() {
+        let config = FlowConfig {
+            delete_batch_chunk_size: 1,
+            delete_concurrency: 3,
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 10 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
             })
+            .unwrap();
+
+        store.delete_batch_list(&[1, 3, 4, 5, 6]).unwrap();
+        for batch_index in [1, 3, 4, 5, 6] {
+            assert!(db.get_entry_batch(batch_index).unwrap().is_none());
         }
+        assert!(db.get_entry_batch(2).unwrap().is_some());
+
+        store.truncate(7 * SECTORS_PER_LOAD as u64 + 10).unwrap();
+        assert_eq!(db.pending_truncate().unwrap(), None);
+        assert_eq!(
+            db.get_entry_batch(7).unwrap().unwrap().num_stored_sectors(),
+            10
+        );
+        for batch_index in 8..11 {
+            assert!(db.get_entry_batch(batch_index).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn test_merkle_node_cache_capacity() {
+        let entry_size = NodeManager::<DataRoot>::cache_entry_size();
+        assert_eq!(MerkleNodeCacheCapacity::Nodes(7).node_count(), 7);
+        assert_eq!(
+            MerkleNodeCacheCapacity::Bytes(10 * entry_size + 1).node_count(),
+            10
+        );
+        assert_eq!(
+            MerkleNodeCacheCapacity::Bytes(entry_size - 1).node_count(),
+            0
+        );
+    }
+
+    #[test]
+    fn test_get_entries_any() {
+        let store = memory_store(Default::default());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        let batch_start = SECTORS_PER_LOAD as u64;
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: batch_start,
+            })
+            .unwrap();
+        let task = store
+            .pull_seal_chunk(usize::MAX)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .find(|task| task.seal_index == SEALS_PER_LOAD as u64 + 1)
+            .unwrap();
+        store
+            .submit_seal_result(vec![SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: [9u8; BYTES_PER_SEAL],
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            }])
+            .unwrap();
+
+        let seal = SECTORS_PER_SEAL as u64;
+        let start = batch_start + seal / 2;
+        let tagged = store
+            .get_entries_any(start, batch_start + 3 * seal)
+            .unwrap()
+            .unwrap();
+        let region = |start_index, sealed, sectors: u64, byte| TaggedRegion {
+            start_index,
+            sealed,
+            data: vec![byte; sectors as usize * BYTES_PER_SECTOR],
+        };
+        assert_eq!(
+            tagged,
+            ChunkArrayTagged {
+                start_index: start,
+                regions: vec![
+                    region(start, false, seal / 2, 1),
+                    region(batch_start + seal, true, seal, 9),
+                    region(batch_start + 2 * seal, false, seal, 1),
+                ],
+            }
+        );
+        assert!(store
+            .get_entries_any(batch_start, 3 * batch_start)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_estimated_seal_eta() {
+        let store = memory_store(Default::default());
+        assert_eq!(store.estimated_seal_eta(), Some(Duration::ZERO));
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        // Nothing is sealed yet, so the rate is unknown.
+        assert_eq!(store.estimated_seal_eta(), None);
+
+        let answers = store
+            .pull_seal_chunk(usize::MAX)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .take(4)
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        store.submit_seal_result(answers).unwrap();
+        let (rate, _) = store.seal_throughput();
+        assert!(rate > 0.0);
+        assert_eq!(
+            store.estimated_seal_eta(),
+            Some(Duration::from_secs_f64((SEALS_PER_LOAD - 4) as f64 / rate))
+        );
+    }
+
+    #[test]
+    fn test_subtree_list_conflict() {
+        let root = DataRoot::repeat_byte;
+        let store_with_mode = |mode| {
+            let config = FlowConfig {
+                subtree_list_conflict: mode,
+                ..Default::default()
+            };
+            memory_store_with_db(config)
+        };
+        let stored = |db: &FlowDBStore| db.get_entry_batch(1).unwrap().unwrap().subtree_list();
+        let is_conflict = |err: anyhow::Error| {
+            matches!(
+                err.downcast_ref::<Error>(),
+                Some(Error::SubtreeListConflict { batch_index: 1 })
+            )
+        };
 
-        Ok(Some(tasks))
+        let (store, db) = store_with_mode(SubtreeListConflictMode::Overwrite);
+        store
+            .insert_subtree_list_for_batch(1, vec![(0, 2, root(1))])
+            .unwrap();
+        store
+            .insert_subtree_list_for_batch(1, vec![(4, 2, root(2))])
+            .unwrap();
+        assert_eq!(stored(&db), vec![(4, 2, root(2))]);
+
+        let (store, db) = store_with_mode(SubtreeListConflictMode::Verify);
+        store
+            .insert_subtree_list_for_batch(1, vec![(0, 2, root(1))])
+            .unwrap();
+        store
+            .insert_subtree_list_for_batch(1, vec![(0, 2, root(1))])
+            .unwrap();
+        assert!(is_conflict(
+            store
+                .insert_subtree_list_for_batch(1, vec![(0, 2, root(2))])
+                .unwrap_err()
+        ));
+        assert_eq!(stored(&db), vec![(0, 2, root(1))]);
+
+        let (store, db) = store_with_mode(SubtreeListConflictMode::Merge);
+        store
+            .insert_subtree_list_for_batch(1, vec![(0, 2, root(1))])
+            .unwrap();
+        store
+            .insert_subtree_list_for_batch(1, vec![(0, 2, root(1)), (4, 2, root(2))])
+            .unwrap();
+        assert_eq!(stored(&db), vec![(0, 2, root(1)), (4, 2, root(2))]);
+        assert!(is_conflict(
+            store
+                .insert_subtree_list_for_batch(1, vec![(0, 3, root(3))])
+                .unwrap_err()
+        ));
+        assert_eq!(stored(&db), vec![(0, 2, root(1)), (4, 2, root(2))]);
     }
 
-    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<()> {
-        let mut to_seal_set = self.seal_manager.to_seal_set.write();
-        let is_consistent = |answer: &SealAnswer| {
-            to_seal_set
-                .get(&(answer.seal_index as usize))
-                .map_or(false, |cur_ver| cur_ver == &answer.version)
+    #[test]
+    fn test_warmup() {
+        let config = FlowConfig {
+            warmup_batches: 2,
+            sealed_data_cache_capacity: 8,
+            ..Default::default()
         };
+        let store = memory_store(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 5 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        // Nothing is warmed up until the flow length is known.
+        store.warmup().unwrap();
+        assert_eq!(store.seal_set_summary().count, 0);
+        store.set_flow_length(6 * SECTORS_PER_LOAD as u64);
+        // The seal worker is not available, so no seal is queued by the append.
+        assert_eq!(store.seal_set_summary().count, 0);
+        // Batch 4 is stored but no longer in our shard range.
+        store.update_shard_config(ShardConfig::new(1, 2).unwrap());
 
-        let mut updated_chunk = vec![];
-        let mut removed_seal_index = Vec::new();
-        for (load_index, answers_in_chunk) in &answers
-            .into_iter()
-            .filter(is_consistent)
-            .chunk_by(|answer| answer.seal_index / SEALS_PER_LOAD as u64)
+        store.warmup().unwrap();
         {
-            let mut batch_chunk = self
-                .data_db
-                .get_entry_batch(load_index)?
-                .expect("Can not find chunk data");
-            for answer in answers_in_chunk {
-                removed_seal_index.push(answer.seal_index as usize);
-                batch_chunk.submit_seal_result(answer)?;
+            let cache = store.sealed_data_cache.as_ref().unwrap().lock();
+            for batch_index in [3, 5] {
+                assert!(cache.contains(&batch_index));
+            }
+            for batch_index in [1, 2, 4] {
+                assert!(!cache.contains(&batch_index));
             }
-            updated_chunk.push((load_index, batch_chunk));
         }
+        let summary = store.seal_set_summary();
+        assert_eq!(summary.count, 2 * SEALS_PER_LOAD);
+        assert_eq!(summary.min_index, Some(3 * SEALS_PER_LOAD));
+        assert_eq!(summary.load_chunks, 2);
+    }
 
-        debug!("Seal chunks: indices = {:?}", removed_seal_index);
+    #[test]
+    fn test_get_entries_into() {
+        let store = memory_store(Default::default());
+        let data: Vec<u8> = (0..2 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+        store
+            .append_entries(ChunkArray {
+                data,
+                start_index: 1,
+            })
+            .unwrap();
 
-        for idx in removed_seal_index.into_iter() {
-            to_seal_set.remove(&idx);
+        let mut buf = vec![7u8; 3];
+        let batch_size = SECTORS_PER_LOAD as u64;
+        // The ranges include the first sector of the flow, which is skipped as in
+        // `get_entries`, and ones across the batch boundary.
+        for (start, end) in [(0, 10), (1, 10), (batch_size - 3, batch_size + 5)] {
+            let expected = store.get_entries(start, end).unwrap().unwrap();
+            assert_eq!(
+                store.get_entries_into(start, end, &mut buf).unwrap(),
+                Some(expected.start_index)
+            );
+            assert_eq!(buf, expected.data);
         }
 
-        self.data_db.put_entry_raw(updated_chunk)?;
-
-        Ok(())
+        assert_eq!(
+            store
+                .get_entries_into(2 * batch_size, 3 * batch_size, &mut buf)
+                .unwrap(),
+            None
+        );
+        assert!(buf.is_empty());
     }
-}
-
-#[derive(Debug, PartialEq, DeriveEncode, DeriveDecode)]
-pub struct PadPair {
-    pub start_index: u64,
-    pub data_size: u64,
-}
 
-pub struct FlowDBStore {
-    kvdb: Arc<dyn ZgsKeyValueDB>,
-}
+    #[test]
+    fn test_verify_pad_consistency() {
+        let store = memory_store(FlowConfig::default());
+        let pad = |start_index, sectors: u64| PadPair {
+            start_index,
+            data_size: sectors * BYTES_PER_SECTOR as u64,
+        };
+        store
+            .append_entries(ChunkArray {
+                data: vec![1; 4 * BYTES_PER_SECTOR],
+                start_index: 100,
+            })
+            .unwrap();
+        store.put_pad_data(&[pad(1, 10)], 1).unwrap();
+        store.put_pad_data(&[pad(20, 10), pad(40, 5)], 2).unwrap();
+        assert!(store.verify_pad_consistency().unwrap().is_empty());
 
-impl FlowDBStore {
-    pub fn new(kvdb: Arc<dyn ZgsKeyValueDB>) -> Self {
-        Self { kvdb }
+        store.put_pad_data(&[pad(25, 10)], 3).unwrap();
+        store.put_pad_data(&[pad(98, 4)], 4).unwrap();
+        store.set_flow_length(100);
+        let inconsistency = |tx_seq, start_index, end_index, kind| PadInconsistency {
+            tx_seq,
+            start_index,
+            end_index,
+            kind,
+        };
+        assert_eq!(
+            store.verify_pad_consistency().unwrap(),
+            vec![
+                inconsistency(3, 25, 35, PadInconsistencyKind::Overlap),
+                inconsistency(4, 98, 102, PadInconsistencyKind::BeyondFlowEnd),
+                inconsistency(4, 98, 102, PadInconsistencyKind::RealData),
+            ]
+        );
     }
 
-    fn put_entry_batch_list(
-        &self,
-        batch_list: Vec<(u64, EntryBatch)>,
-    ) -> Result<Vec<(u64, DataRoot)>> {
-        let start_time = Instant::now();
-        let mut completed_batches = Vec::new();
-        let mut tx = self.kvdb.transaction();
-        for (batch_index, batch) in batch_list {
-            tx.put(
-                COL_ENTRY_BATCH,
-                &batch_index.to_be_bytes(),
-                &batch.as_ssz_bytes(),
-            );
-            if let Some(root) = batch.build_root(batch_index == 0)? {
-                trace!("complete batch: index={}", batch_index);
-                completed_batches.push((batch_index, root));
-            }
-        }
-        self.kvdb.write(tx)?;
-        metrics::PUT_ENTRY_BATCH_LIST.update_since(start_time);
-        Ok(completed_batches)
+    #[test]
+    fn test_pending_seals_in_range() {
+        let store = memory_store(Default::default());
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 2 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert!(store.pending_seals_in_range(0, 1).is_empty());
+        assert_eq!(
+            store.pending_seals_in_range(1, 2),
+            (SEALS_PER_LOAD..2 * SEALS_PER_LOAD).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            store.pending_seals_in_range(0, 10).len(),
+            2 * SEALS_PER_LOAD
+        );
+        assert!(store.pending_seals_in_range(2, 1).is_empty());
     }
 
-    fn put_entry_raw(&self, batch_list: Vec<(u64, EntryBatch)>) -> Result<()> {
-        let mut tx = self.kvdb.transaction();
-        for (batch_index, batch) in batch_list {
-            tx.put(
-                COL_ENTRY_BATCH,
-                &batch_index.to_be_bytes(),
-                &batch.as_ssz_bytes(),
+    #[test]
+    fn test_persist_batch_merkle() {
+        let config = FlowConfig {
+            persist_batch_merkle: true,
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        let data: Vec<u8> = (0..2 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+        let roots = store
+            .append_entries(ChunkArray {
+                data,
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(roots.len(), 2);
+        let batch = store.get_entry_batch(1).unwrap().unwrap();
+        let fresh = batch.to_merkle_tree(false).unwrap().unwrap();
+
+        // Nothing is stored before the MPT has the batch root.
+        store.gen_proof_in_batch(1, 0).unwrap();
+        assert!(db.get_batch_merkle(1).unwrap().is_none());
+
+        // The MPT is written by the flow merkle tree of `LogManager`, so the nodes are saved
+        // directly here.
+        let mut tx = db.start_transaction();
+        for (batch_index, root) in &roots {
+            tx.save_node(0, *batch_index as usize, root);
+        }
+        db.commit(tx).unwrap();
+        store.gen_proof_in_batch(1, 0).unwrap();
+        assert_eq!(db.get_batch_merkle(1).unwrap(), Some(fresh.layers()));
+        for sector_index in [0, 1, 511, SECTORS_PER_LOAD - 1] {
+            assert_eq!(
+                store.gen_proof_in_batch(1, sector_index).unwrap(),
+                fresh.gen_proof(sector_index).unwrap()
             );
         }
-        self.kvdb.write(tx)?;
-        Ok(())
-    }
 
-    fn get_entry_batch(&self, batch_index: u64) -> Result<Option<EntryBatch>> {
-        let raw = try_option!(self.kvdb.get(COL_ENTRY_BATCH, &batch_index.to_be_bytes())?);
-        Ok(Some(EntryBatch::from_ssz_bytes(&raw).map_err(Error::from)?))
+        // A stored tree whose root is not the batch root is rebuilt and replaced.
+        let other = store.get_entry_batch(2).unwrap().unwrap();
+        db.put_batch_merkle(1, &other.to_merkle_tree(false).unwrap().unwrap().layers())
+            .unwrap();
+        assert_eq!(
+            store.batch_merkle_tree(1, &batch).unwrap().unwrap().root(),
+            roots[0].1
+        );
+        assert_eq!(db.get_batch_merkle(1).unwrap(), Some(fresh.layers()));
+
+        store.truncate(SECTORS_PER_LOAD as u64 + 10).unwrap();
+        assert!(db.get_batch_merkle(1).unwrap().is_none());
     }
 
-    fn truncate(&self, start_index: u64, batch_size: usize) -> crate::error::Result<Vec<usize>> {
-        let mut tx = self.kvdb.transaction();
-        let mut start_batch_index = start_index / batch_size as u64;
-        let first_batch_offset = start_index as usize % batch_size;
-        let mut index_to_reseal = Vec::new();
-        if first_batch_offset != 0 {
-            if let Some(mut first_batch) = self.get_entry_batch(start_batch_index)? {
-                index_to_reseal = first_batch
-                    .truncate(first_batch_offset)
-                    .into_iter()
-                    .map(|x| start_batch_index as usize * SEALS_PER_LOAD + x as usize)
-                    .collect();
-                if !first_batch.is_empty() {
-                    tx.put(
-                        COL_ENTRY_BATCH,
-                        &start_batch_index.to_be_bytes(),
-                        &first_batch.as_ssz_bytes(),
-                    );
-                } else {
-                    tx.delete(COL_ENTRY_BATCH, &start_batch_index.to_be_bytes());
-                }
-            }
+    #[test]
+    fn test_corrupt_node() {
+        let db = memory_db();
+        let mut tx = db.start_transaction();
+        tx.save_node(1, 2, &DataRoot::repeat_byte(3));
+        db.commit(tx).unwrap();
+        assert_eq!(db.get_node(1, 2).unwrap(), Some(DataRoot::repeat_byte(3)));
 
-            start_batch_index += 1;
-        }
-        // TODO: `kvdb` and `kvdb-rocksdb` does not support `seek_to_last` yet.
-        // We'll need to fork it or use another wrapper for a better performance in this.
-        let end = match self.kvdb.iter(COL_ENTRY_BATCH).last() {
-            Some(Ok((k, _))) => decode_batch_index(k.as_ref())?,
-            Some(Err(e)) => {
-                error!("truncate db error: e={:?}", e);
-                return Err(e.into());
-            }
-            None => {
-                // The db has no data, so we can just return;
-                return Ok(index_to_reseal);
-            }
-        };
-        for batch_index in start_batch_index as usize..=end {
-            tx.delete(COL_ENTRY_BATCH, &batch_index.to_be_bytes());
-        }
-        self.kvdb.write(tx)?;
-        Ok(index_to_reseal)
+        db.kvdb
+            .put(
+                db.columns.flow_mpt_nodes,
+                &encode_mpt_node_key(1, 2),
+                &[3; 31],
+            )
+            .unwrap();
+        let err = db.get_node(1, 2).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::CorruptNode {
+                layer: 1,
+                pos: 2,
+                len: 31
+            })
+        ));
+        assert_eq!(db.get_node(1, 3).unwrap(), None);
     }
 
-    fn delete_batch_list(&self, batch_list: &[u64]) -> Result<()> {
-        let mut tx = self.kvdb.transaction();
-        for i in batch_list {
-            tx.delete(COL_ENTRY_BATCH, &i.to_be_bytes());
+    #[test]
+    fn test_release_seal_lock_before_write() {
+        let config = FlowConfig {
+            seal_result_write_chunk_size: Some(1),
+            release_seal_lock_before_write: true,
+            max_seal_load_chunks_per_pull: 3,
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 3 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        // The answers of the three batches are written in three transactions.
+        let answers: Vec<SealAnswer> = store
+            .pull_seal_chunk(usize::MAX)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        assert_eq!(answers.len(), 3 * SEALS_PER_LOAD);
+        assert!(store.submit_seal_result(answers).unwrap().is_empty());
+        assert!(store.seal_manager.to_seal_set.read().is_empty());
+        for load_index in 1..4 {
+            let mine_chunk = store.load_sealed_data(load_index).unwrap().unwrap();
+            assert!(mine_chunk.availabilities.iter().all(|x| *x));
         }
-        Ok(self.kvdb.write(tx)?)
     }
 
-    fn put_pad_data(&self, data_sizes: &[PadPair], tx_seq: u64) -> Result<()> {
-        let mut tx = self.kvdb.transaction();
+    #[test]
+    fn test_requeue_unwritten_seals() {
+        let store = memory_store(Default::default());
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let unwritten = vec![
+            (SEALS_PER_LOAD, 5),
+            (SEALS_PER_LOAD + 1, 5),
+            (5 * SEALS_PER_LOAD, 5),
+        ];
 
-        let mut buffer = Vec::new();
-        for item in data_sizes {
-            buffer.extend(item.as_ssz_bytes());
-        }
+        // The task queued meanwhile is kept, and the task of the missing batch is dropped.
+        let mut to_seal_set = BTreeMap::from([(SEALS_PER_LOAD + 1, 7)]);
+        store.requeue_unwritten_seals(&mut to_seal_set, unwritten.clone(), true);
+        assert_eq!(
+            to_seal_set,
+            BTreeMap::from([(SEALS_PER_LOAD, 5), (SEALS_PER_LOAD + 1, 7)])
+        );
 
-        tx.put(COL_PAD_DATA_LIST, &tx_seq.to_be_bytes(), &buffer);
-        self.kvdb.write(tx)?;
-        Ok(())
+        let mut to_seal_set = BTreeMap::new();
+        store.requeue_unwritten_seals(&mut to_seal_set, unwritten.clone(), false);
+        assert_eq!(to_seal_set, unwritten.into_iter().collect());
     }
 
-    fn put_pad_data_sync_height(&self, tx_seq: u64) -> Result<()> {
-        let mut tx = self.kvdb.transaction();
-        tx.put(
-            COL_PAD_DATA_SYNC_HEIGH,
-            b"sync_height",
-            &tx_seq.to_be_bytes(),
+    #[test]
+    fn test_genesis_sector() {
+        let store = memory_store(Default::default());
+        assert_eq!(store.genesis_sector().unwrap(), None);
+        store.initialize_genesis().unwrap();
+        assert_eq!(
+            store.genesis_sector().unwrap(),
+            Some(vec![0; BYTES_PER_SECTOR])
         );
-        self.kvdb.write(tx)?;
-        Ok(())
-    }
 
-    fn get_pad_data_sync_height(&self) -> Result<Option<u64>> {
-        match self.kvdb.get(COL_PAD_DATA_SYNC_HEIGH, b"sync_height")? {
-            Some(v) => Ok(Some(u64::from_be_bytes(
-                v.try_into().map_err(|e| anyhow!("{:?}", e))?,
-            ))),
-            None => Ok(None),
-        }
-    }
+        let mut data = vec![0; BYTES_PER_SECTOR];
+        data.extend_from_slice(&[1; 3 * BYTES_PER_SECTOR]);
+        let mut overwrite = data.clone();
+        overwrite[BYTES_PER_SECTOR - 1] = 1;
+        assert!(store
+            .append_entries(ChunkArray {
+                data: overwrite,
+                start_index: 0,
+            })
+            .is_err());
+        // A zero genesis sector can be written with the following sectors.
+        store
+            .append_entries(ChunkArray {
+                data,
+                start_index: 0,
+            })
+            .unwrap();
+        assert_eq!(
+            store.genesis_sector().unwrap(),
+            Some(vec![0; BYTES_PER_SECTOR])
+        );
 
-    fn get_pad_data(&self, tx_seq: u64) -> Result<Option<Vec<PadPair>>> {
-        match self.kvdb.get(COL_PAD_DATA_LIST, &tx_seq.to_be_bytes())? {
-            Some(v) => Ok(Some(
-                Vec::<PadPair>::from_ssz_bytes(&v).map_err(Error::from)?,
-            )),
-            None => Ok(None),
-        }
+        // The read from index 0 skips the genesis sector but keeps `start_index` at 0.
+        let from_zero = store.get_entries(0, 4).unwrap().unwrap();
+        assert_eq!(from_zero.start_index, 0);
+        assert_eq!(from_zero.data, vec![1; 3 * BYTES_PER_SECTOR]);
+        let from_one = store.get_entries(1, 4).unwrap().unwrap();
+        assert_eq!(from_one.start_index, 1);
+        assert_eq!(from_one.data, from_zero.data);
     }
-}
 
-#[derive(DeriveEncode, DeriveDecode, Clone, Debug)]
-#[ssz(enum_behaviour = "union")]
-pub enum BatchRoot {
-    Single(DataRoot),
-    Multiple((usize, DataRoot)),
-}
+    #[test]
+    fn test_expected_batch_count() {
+        let config = FlowConfig::default();
+        let batch_size = config.batch_size as u64;
+        assert_eq!(config.expected_batch_count(0), 0);
+        assert_eq!(config.expected_batch_count(1), 0);
+        assert_eq!(config.expected_batch_count(2), 1);
+        assert_eq!(config.expected_batch_count(batch_size), 1);
+        assert_eq!(config.expected_batch_count(batch_size + 1), 2);
+        assert_eq!(config.expected_batch_count(5 * batch_size), 5);
 
-/// Return the batch boundaries `(batch_start_index, batch_end_index)` given the index range.
-pub fn batch_iter(start: u64, end: u64, batch_size: usize) -> Vec<(u64, u64)> {
-    let mut list = Vec::new();
-    for i in (start / batch_size as u64 * batch_size as u64..end).step_by(batch_size) {
-        let batch_start = cmp::max(start, i);
-        let batch_end = cmp::min(end, i + batch_size as u64);
-        list.push((batch_start, batch_end));
+        let sharded = |shard_id| FlowConfig {
+            shard_config: Arc::new(RwLock::new(ShardConfig::new(shard_id, 4).unwrap())),
+            ..Default::default()
+        };
+        // Batches 0..5 are owned by the shards as 0, 1, 2, 3, 0.
+        let counts: Vec<u64> = (0..4)
+            .map(|shard_id| sharded(shard_id).expected_owned_batch_count(5 * batch_size))
+            .collect();
+        assert_eq!(counts, vec![2, 1, 1, 1]);
+        assert_eq!(sharded(3).expected_owned_batch_count(3 * batch_size), 0);
+        assert_eq!(sharded(0).expected_owned_batch_count(1), 0);
+        assert_eq!(config.expected_owned_batch_count(5 * batch_size), 5);
     }
-    list
-}
 
-pub fn batch_iter_sharded(
-    start: u64,
-    end: u64,
-    batch_size: usize,
-    shard_config: ShardConfig,
-) -> Vec<(u64, u64)> {
-    batch_iter(start, end, batch_size)
-        .into_iter()
-        .filter(|(start, _)| {
-            (start / batch_size as u64) % shard_config.num_shard as u64
-                == shard_config.shard_id as u64
-        })
-        .collect()
-}
+    #[test]
+    fn test_append_since_truncation() {
+        let create_store = |mode| {
+            let config = FlowConfig {
+                truncation_race: mode,
+                ..Default::default()
+            };
+            memory_store_with_db(config)
+        };
+        let batch_start = SECTORS_PER_LOAD as u64;
+        let data = ChunkArray {
+            data: vec![1u8; 3 * BYTES_PER_LOAD],
+            start_index: batch_start,
+        };
 
-fn try_decode_usize(data: &[u8]) -> Result<usize> {
-    Ok(usize::from_be_bytes(
-        data.try_into().map_err(|e| anyhow!("{:?}", e))?,
-    ))
-}
+        let (store, db) = create_store(TruncationRaceMode::Skip);
+        store.set_flow_length(batch_start);
+        store.append_entries(data.clone()).unwrap();
+        // The append of a stale peer is prepared, and then the flow is truncated in batch 2.
+        let epoch = store.truncation_epoch();
+        let truncated_at = 2 * batch_start + 10;
+        store.truncate(truncated_at).unwrap();
+        assert_eq!(store.truncation_epoch(), epoch + 1);
+        let completed = store.append_entries_since(data.clone(), epoch).unwrap();
+        assert_eq!(completed.len(), 0);
+        assert!(db.get_entry_batch(3).unwrap().is_none());
+        assert_eq!(store.flow_length(), Some(truncated_at));
+        let batch = store
+            .get_entries(batch_start, truncated_at)
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            batch.data.len(),
+            (truncated_at - batch_start) as usize * BYTES_PER_SECTOR
+        );
+        assert!(store
+            .get_entries(truncated_at, truncated_at + 1)
+            .unwrap()
+            .is_none());
+        // An append prepared after the truncate is written.
+        assert_eq!(store.append_entries(data.clone()).unwrap().len(), 2);
+        assert!(db.get_entry_batch(3).unwrap().is_some());
 
-fn decode_batch_index(data: &[u8]) -> Result<usize> {
-    try_decode_usize(data)
-}
+        let (store, db) = create_store(TruncationRaceMode::Reject);
+        store.append_entries(data.clone()).unwrap();
+        let epoch = store.truncation_epoch();
+        store.truncate(2 * batch_start).unwrap();
+        let err = store.append_entries_since(data.clone(), epoch).unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<Error>(),
+            Some(Error::TruncatedDuringAppend { truncated_at, .. }) if *truncated_at == 2 * batch_start
+        ));
+        assert!(db.get_entry_batch(2).unwrap().is_none());
+        // The data before the truncated index can still be appended with the old epoch.
+        store
+            .append_entries_since(data.sub_array(batch_start, 2 * batch_start).unwrap(), epoch)
+            .unwrap();
+    }
 
-fn encode_mpt_node_key(layer_index: usize, position: usize) -> Vec<u8> {
-    let mut key = layer_index.to_be_bytes().to_vec();
-    key.extend_from_slice(&position.to_be_bytes());
-    key
-}
+    #[test]
+    fn test_max_outstanding_seal_leases() {
+        let config = FlowConfig {
+            max_outstanding_seal_leases: Some(3),
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        // Mark the seal worker as available so the appended seals are queued.
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 2 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        let to_answer = |task: &SealTask| SealAnswer {
+            seal_index: task.seal_index,
+            version: task.version,
+            sealed_data: task.non_sealed_data,
+            miner_id: Default::default(),
+            seal_context: Default::default(),
+            context_end_seal: task.seal_index + 1,
+        };
 
-fn layer_size_key(layer: usize) -> Vec<u8> {
-    let mut key = "layer_size".as_bytes().to_vec();
-    key.extend_from_slice(&layer.to_be_bytes());
-    key
-}
+        // Only three tasks are leased, and the leases stop until they are released.
+        let leased = store.lease_seal_chunk(usize::MAX).unwrap();
+        assert_eq!(leased.len(), 3);
+        assert!(store.lease_seal_chunk(usize::MAX).unwrap().is_empty());
+        assert!(store.pull_seal_chunk(usize::MAX).unwrap().is_none());
 
-pub struct NodeDBTransaction(DBTransaction);
+        // Submitting one task releases its lease, and the pull leases the next task.
+        assert!(store
+            .submit_seal_result(vec![to_answer(&leased[0])])
+            .unwrap()
+            .is_empty());
+        let pulled = store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+        assert_eq!(pulled.len(), 1);
+        assert_eq!(pulled[0].seal_index, leased[2].seal_index + 1);
+        assert!(store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+        assert!(store.lease_seal_chunk(usize::MAX).unwrap().is_empty());
 
-impl NodeDatabase<DataRoot> for FlowDBStore {
-    fn get_node(&self, layer: usize, pos: usize) -> Result<Option<DataRoot>> {
-        Ok(self
-            .kvdb
-            .get(COL_FLOW_MPT_NODES, &encode_mpt_node_key(layer, pos))?
-            .map(|v| DataRoot::from_slice(&v)))
+        // The pulls are capped without the stream leases too.
+        let answers: Vec<SealAnswer> = leased[1..].iter().chain(&pulled).map(to_answer).collect();
+        assert!(store.submit_seal_result(answers).unwrap().is_empty());
+        let pulled = store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+        assert_eq!(pulled.len(), 3);
+        assert_eq!(pulled[0].seal_index, leased[2].seal_index + 2);
+        assert!(store.pull_seal_chunk(usize::MAX).unwrap().is_none());
     }
 
-    fn get_layer_size(&self, layer: usize) -> Result<Option<usize>> {
-        match self.kvdb.get(COL_FLOW_MPT_NODES, &layer_size_key(layer))? {
-            Some(v) => Ok(Some(try_decode_usize(&v)?)),
-            None => Ok(None),
-        }
+    /// Fails the writes while `fail_writes` is set.
+    struct FailingDB {
+        db: kvdb_memorydb::InMemory,
+        fail_writes: AtomicBool,
+        /// Panic in the writes of the unnamed threads spawned by `write_txs_concurrently`.
+        panic_thread_writes: AtomicBool,
     }
 
-    fn start_transaction(&self) -> Box<dyn NodeTransaction<DataRoot>> {
-        Box::new(NodeDBTransaction(self.kvdb.transaction()))
+    impl kvdb::KeyValueDB for FailingDB {
+        fn get(&self, col: u32, key: &[u8]) -> std::io::Result<Option<kvdb::DBValue>> {
+            self.db.get(col, key)
+        }
+
+        fn get_by_prefix(&self, col: u32, prefix: &[u8]) -> std::io::Result<Option<kvdb::DBValue>> {
+            self.db.get_by_prefix(col, prefix)
+        }
+
+        fn write(&self, transaction: DBTransaction) -> std::io::Result<()> {
+            if self.panic_thread_writes.load(Ordering::SeqCst)
+                && std::thread::current().name().is_none()
+            {
+                panic!("write panicked");
+            }
+            if self.fail_writes.load(Ordering::SeqCst) {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::Other,
+                    "write failed",
+                ));
+            }
+            self.db.write(transaction)
+        }
+
+        fn iter<'a>(
+            &'a self,
+            col: u32,
+        ) -> Box<dyn Iterator<Item = std::io::Result<kvdb::DBKeyValue>> + 'a> {
+            self.db.iter(col)
+        }
+
+        fn iter_with_prefix<'a>(
+            &'a self,
+            col: u32,
+            prefix: &'a [u8],
+        ) -> Box<dyn Iterator<Item = std::io::Result<kvdb::DBKeyValue>> + 'a> {
+            self.db.iter_with_prefix(col, prefix)
+        }
     }
 
-    fn commit(&self, tx: Box<dyn NodeTransaction<DataRoot>>) -> Result<()> {
-        let db_tx: Box<NodeDBTransaction> = tx
-            .into_any()
-            .downcast()
-            .map_err(|e| anyhow!("downcast failed, e={:?}", e))?;
-        self.kvdb.write(db_tx.0).map_err(Into::into)
+    impl ZgsKeyValueDB for FailingDB {
+        fn num_keys(&self, col: u32) -> std::io::Result<u64> {
+            self.db.num_keys(col)
+        }
     }
-}
 
-impl NodeTransaction<DataRoot> for NodeDBTransaction {
-    fn save_node(&mut self, layer: usize, pos: usize, node: &DataRoot) {
-        self.0.put(
-            COL_FLOW_MPT_NODES,
-            &encode_mpt_node_key(layer, pos),
-            node.as_bytes(),
+    #[test]
+    fn test_failed_write_window_flush() {
+        let kvdb = Arc::new(FailingDB {
+            db: kvdb_memorydb::create(COL_NUM),
+            fail_writes: AtomicBool::new(false),
+            panic_thread_writes: AtomicBool::new(false),
+        });
+        let db = Arc::new(FlowDBStore::new(kvdb.clone()));
+        let config = FlowConfig {
+            append_write_window_bytes: Some(usize::MAX),
+            append_write_window_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let store = FlowStore::new(db.clone(), db.clone(), config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        // The scans see the pending batch.
+        assert_eq!(
+            store
+                .iter_batch_indices_with_pending()
+                .collect::<Result<Vec<_>>>()
+                .unwrap(),
+            vec![1]
         );
+
+        kvdb.fail_writes.store(true, Ordering::SeqCst);
+        assert!(store.flush_append_buffer().is_err());
+        let start_index = SECTORS_PER_LOAD as u64;
+        assert!(store
+            .get_entries(start_index, start_index + 1)
+            .unwrap()
+            .is_some());
+        assert!(db.get_entry_batch(1).unwrap().is_none());
+
+        // The pending batch is written by the next flush.
+        kvdb.fail_writes.store(false, Ordering::SeqCst);
+        store.flush_append_buffer().unwrap();
+        assert!(store.write_window.lock().batches.is_empty());
+        assert!(db.get_entry_batch(1).unwrap().is_some());
     }
 
-    fn save_node_list(&mut self, nodes: &[(usize, usize, &DataRoot)]) {
-        for (layer_index, position, data) in nodes {
-            self.0.put(
-                COL_FLOW_MPT_NODES,
-                &encode_mpt_node_key(*layer_index, *position),
-                data.as_bytes(),
-            );
-        }
+    #[test]
+    fn test_queries_read_append_buffer() {
+        let config = FlowConfig {
+            coalesce_small_appends: true,
+            append_buffer_flush_interval: Duration::from_secs(3600),
+            ..Default::default()
+        };
+        let (store, db) = memory_store_with_db(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 4 * BYTES_PER_SECTOR],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+
+        // The queries see the buffered batch without writing it to db.
+        assert_eq!(store.stored_entries_in_shard().unwrap(), 4);
+        assert_eq!(store.longest_present_run(0).unwrap(), (1, 2));
+        let mut exported = Vec::new();
+        assert_eq!(
+            store
+                .export_shard(ShardConfig::default(), &mut exported)
+                .unwrap(),
+            1
+        );
+        assert!(db.get_entry_batch(1).unwrap().is_none());
+        assert_eq!(store.append_buffer.lock().len(), 1);
     }
 
-    fn remove_node_list(&mut self, nodes: &[(usize, usize)]) {
-        for (layer_index, position) in nodes {
-            self.0.delete(
-                COL_FLOW_MPT_NODES,
-                &encode_mpt_node_key(*layer_index, *position),
-            );
-        }
+    #[test]
+    fn test_duplicate_append_returns_root() {
+        let store = memory_store(Default::default());
+        let data = ChunkArray {
+            data: (0..BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+            start_index: SECTORS_PER_LOAD as u64,
+        };
+        let first = store.append_entries(data.clone()).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].0, 1);
+        // The duplicate is not rewritten but its root is returned again.
+        assert_eq!(store.append_entries(data).unwrap(), first);
     }
 
-    fn save_layer_size(&mut self, layer: usize, size: usize) {
-        self.0.put(
-            COL_FLOW_MPT_NODES,
-            &layer_size_key(layer),
-            &size.to_be_bytes(),
-        );
+    #[test]
+    fn test_quarantine_entry_kept_until_restored() {
+        let config = FlowConfig {
+            seal_quarantine_window: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        let quarantined = || {
+            store
+                .data_db
+                .kvdb
+                .get(store.data_db.columns.seal_quarantine, &1u64.to_be_bytes())
+                .unwrap()
+                .is_some()
+        };
+        let batch = ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: SECTORS_PER_LOAD as u64,
+        };
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store.append_entries(batch.clone()).unwrap();
+        let answers = store
+            .pull_seal_chunk(usize::MAX)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: Default::default(),
+                seal_context: Default::default(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        assert!(store.submit_seal_result(answers).unwrap().is_empty());
+        store.truncate_soft(SECTORS_PER_LOAD as u64).unwrap();
+        assert!(quarantined());
+
+        // A batch with another root does not consume the quarantine entry.
+        store
+            .append_entries(ChunkArray {
+                data: vec![2u8; BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert!(quarantined());
+
+        // The entry is deleted with the write of the restored batch.
+        store.truncate_soft(SECTORS_PER_LOAD as u64).unwrap();
+        store.pull_seal_chunk(usize::MAX).unwrap();
+        store.append_entries(batch).unwrap();
+        assert!(!quarantined());
+        assert!(store.pull_seal_chunk(usize::MAX).unwrap().is_none());
     }
 
-    fn remove_layer_size(&mut self, layer: usize) {
-        self.0.delete(COL_FLOW_MPT_NODES, &layer_size_key(layer));
+    #[test]
+    fn test_truncate_write_thread_panic() {
+        let kvdb = Arc::new(FailingDB {
+            db: kvdb_memorydb::create(COL_NUM),
+            fail_writes: AtomicBool::new(false),
+            panic_thread_writes: AtomicBool::new(false),
+        });
+        let db = Arc::new(FlowDBStore::new(kvdb.clone()));
+        let config = FlowConfig {
+            delete_batch_chunk_size: 1,
+            delete_concurrency: 3,
+            ..Default::default()
+        };
+        let store = FlowStore::new(db.clone(), db.clone(), config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; 6 * BYTES_PER_LOAD],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+
+        // The panic is returned as an error and the progress key is kept.
+        kvdb.panic_thread_writes.store(true, Ordering::SeqCst);
+        let start_index = 2 * SECTORS_PER_LOAD as u64;
+        assert!(store.truncate(start_index).is_err());
+        assert_eq!(db.pending_truncate().unwrap(), Some(start_index));
+
+        kvdb.panic_thread_writes.store(false, Ordering::SeqCst);
+        assert_eq!(store.resume_truncate().unwrap(), Some(start_index));
+        assert_eq!(db.pending_truncate().unwrap(), None);
+        assert!(db.get_entry_batch(1).unwrap().is_some());
+        for batch_index in 2..7 {
+            assert!(db.get_entry_batch(batch_index).unwrap().is_none());
+        }
     }
 
-    fn into_any(self: Box<Self>) -> Box<dyn Any> {
-        self
+    #[test]
+    fn test_health_does_not_track_access() {
+        let config = FlowConfig {
+            batch_access_tracking_capacity: 8,
+            ..Default::default()
+        };
+        let store = memory_store(config);
+        store
+            .append_entries(ChunkArray {
+                data: vec![1u8; BYTES_PER_LOAD + BYTES_PER_LOAD / 2],
+                start_index: SECTORS_PER_LOAD as u64,
+            })
+            .unwrap();
+        assert_eq!(store.health().unwrap().incomplete_tail_batches, Some(1));
+        assert!(store.batch_access.as_ref().unwrap().lock().is_empty());
     }
 }
@@ -5,7 +5,7 @@ use ssz::{Decode, DecodeError, Encode};
 use bitmaps::{Bitmap, Bits, BitsImpl};
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
 
-#[derive(Default, Debug)]
+#[derive(Default, Debug, Clone)]
 pub struct WrappedBitmap<const N: usize>(pub Bitmap<N>)
 where
     BitsImpl<{ N }>: Bits;
@@ -3,18 +3,18 @@ use serde::{Deserialize, Serialize};
 use shared_types::{bytes_to_chunks, DataRoot};
 use ssz_derive::{Decode, Encode};
 use std::fmt::{Debug, Formatter};
-use std::mem;
+use std::{cmp, mem};
 use tracing::error;
 use zgs_spec::{BYTES_PER_LOAD, BYTES_PER_SECTOR, SECTORS_PER_LOAD, SECTORS_PER_SEAL};
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize)]
 pub enum EntryBatchData {
     Complete(Vec<u8>),
     /// All `PartialBatch`s are ordered based on `start_index`.
     Incomplete(IncompleteData),
 }
 
-#[derive(Default, Debug, Encode, Decode, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, Encode, Decode, Deserialize, Serialize)]
 pub struct IncompleteData {
     pub subtrees: Vec<Subtree>,
     pub known_data: Vec<PartialBatch>,
@@ -44,6 +44,37 @@ impl IncompleteData {
         self.subtrees.truncate(subtree_truncate);
     }
 
+    /// Remove the data in `[start_sector, end_sector)` and return the number of removed sectors.
+    /// The subtree roots are kept because they are still valid for the missing data.
+    fn clear_range(&mut self, start_sector: usize, end_sector: usize) -> usize {
+        let mut cleared = 0;
+        let mut known_data = Vec::with_capacity(self.known_data.len() + 1);
+        for p in mem::take(&mut self.known_data) {
+            let p_end_sector = p.end_sector();
+            if p_end_sector <= start_sector || p.start_sector >= end_sector {
+                known_data.push(p);
+                continue;
+            }
+            let overlap_start = cmp::max(p.start_sector, start_sector);
+            let overlap_end = cmp::min(p_end_sector, end_sector);
+            cleared += overlap_end - overlap_start;
+            if p.start_sector < overlap_start {
+                known_data.push(PartialBatch {
+                    start_sector: p.start_sector,
+                    data: p.data[..(overlap_start - p.start_sector) * BYTES_PER_SECTOR].to_vec(),
+                });
+            }
+            if overlap_end < p_end_sector {
+                known_data.push(PartialBatch {
+                    start_sector: overlap_end,
+                    data: p.data[(overlap_end - p.start_sector) * BYTES_PER_SECTOR..].to_vec(),
+                });
+            }
+        }
+        self.known_data = known_data;
+        cleared
+    }
+
     /// This is only called once when the batch is removed from the memory and fully stored in db.
     pub fn set_subtree_list(&mut self, subtree_list: Vec<Subtree>) {
         self.subtrees = subtree_list;
@@ -58,14 +89,14 @@ impl IncompleteData {
     }
 }
 
-#[derive(Default, Debug, Encode, Decode, Deserialize, Serialize)]
+#[derive(Default, Debug, Clone, Encode, Decode, Deserialize, Serialize)]
 pub struct Subtree {
     pub start_sector: usize,
     pub subtree_height: usize,
     pub root: DataRoot,
 }
 
-#[derive(PartialEq, Eq, Deserialize, Serialize)]
+#[derive(PartialEq, Eq, Clone, Deserialize, Serialize)]
 pub struct PartialBatch {
     /// Offset in this batch.
     pub(super) start_sector: usize,
@@ -194,6 +225,28 @@ impl EntryBatchData {
         };
     }
 
+    /// Remove the data in `[start_sector, end_sector)` and return the number of removed sectors.
+    pub fn clear_range(&mut self, start_sector: usize, end_sector: usize) -> usize {
+        assert!(end_sector <= SECTORS_PER_LOAD);
+        if start_sector >= end_sector {
+            return 0;
+        }
+        if let EntryBatchData::Complete(data) = self {
+            let known_data = vec![PartialBatch {
+                start_sector: 0,
+                data: mem::take(data),
+            }];
+            *self = EntryBatchData::Incomplete(IncompleteData {
+                subtrees: vec![],
+                known_data,
+            });
+        }
+        match self {
+            EntryBatchData::Incomplete(data) => data.clear_range(start_sector, end_sector),
+            EntryBatchData::Complete(_) => unreachable!("converted above"),
+        }
+    }
+
     pub fn insert_data(&mut self, start_byte: usize, mut data: Vec<u8>) -> Result<Vec<u16>> {
         assert_eq!(start_byte % BYTES_PER_SECTOR, 0);
         assert_eq!(data.len() % BYTES_PER_SECTOR, 0);
@@ -445,6 +498,42 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_data_chunk_clear_range() {
+        let data = test_data();
+        let mut chunk_batch = EntryBatchData::new();
+        chunk_batch.insert_data(0, data.clone()).unwrap();
+        assert!(matches!(chunk_batch, EntryBatchData::Complete(_)));
+
+        const S: usize = SECTORS_PER_LOAD / 16;
+        assert_eq!(chunk_batch.clear_range(S, 2 * S), S);
+        assert_eq!(chunk_batch.clear_range(S / 2, 3 * S), 2 * S - S / 2);
+        assert_eq!(chunk_batch.clear_range(S, 2 * S), 0);
+        assert_eq!(
+            chunk_batch.available_range_entries(),
+            vec![(0, S / 2), (3 * S, SECTORS_PER_LOAD - 3 * S)]
+        );
+        assert_eq!(
+            chunk_batch.get(0, S / 2 * BYTES_PER_SECTOR).unwrap(),
+            &data[..S / 2 * BYTES_PER_SECTOR]
+        );
+        assert_eq!(
+            chunk_batch
+                .get(3 * S * BYTES_PER_SECTOR, S * BYTES_PER_SECTOR)
+                .unwrap(),
+            &data[3 * S * BYTES_PER_SECTOR..4 * S * BYTES_PER_SECTOR]
+        );
+        assert!(chunk_batch
+            .get(S * BYTES_PER_SECTOR, BYTES_PER_SECTOR)
+            .is_none());
+
+        assert_eq!(
+            chunk_batch.clear_range(0, SECTORS_PER_LOAD),
+            SECTORS_PER_LOAD - 5 * S / 2
+        );
+        assert!(chunk_batch.is_empty());
+    }
+
     #[test]
     fn test_data_chunk_get_slice() {
         let data = test_data();
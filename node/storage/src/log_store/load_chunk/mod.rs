@@ -23,7 +23,7 @@ use super::SealAnswer;
 pub use chunk_data::EntryBatchData;
 use seal::SealInfo;
 
-#[derive(Debug, Encode, Decode, Deserialize, Serialize)]
+#[derive(Debug, Clone, Encode, Decode, Deserialize, Serialize)]
 pub struct EntryBatch {
     seal: SealInfo,
     // the inner data
@@ -41,6 +41,23 @@ impl EntryBatch {
     pub fn is_empty(&self) -> bool {
         self.data.is_empty()
     }
+
+    pub fn is_sealed(&self, seal_index: u16) -> bool {
+        self.seal.is_sealed(seal_index)
+    }
+
+    pub fn has_sealed_data(&self) -> bool {
+        (0..SEALS_PER_LOAD as u16).any(|seal_index| self.seal.is_sealed(seal_index))
+    }
+
+    /// Return the number of sectors with data in this batch.
+    pub fn num_stored_sectors(&self) -> usize {
+        self.data
+            .available_range_entries()
+            .iter()
+            .map(|(_, len)| len)
+            .sum()
+    }
 }
 
 impl EntryBatch {
@@ -123,15 +140,19 @@ impl EntryBatch {
         Some(loaded_data)
     }
 
+    /// Return `true` if `data` are already stored at `offset`, so inserting them is a no-op.
+    pub fn contains_data(&self, offset: usize, data: &[u8]) -> bool {
+        data.is_empty()
+            || self
+                .get_unsealed_data(offset, data.len() / BYTES_PER_SECTOR)
+                .as_deref()
+                == Some(data)
+    }
+
     /// Return `Error` if the new data overlaps with old data.
     /// Convert `Incomplete` to `Completed` if the chunk is completed after the insertion.
     pub fn insert_data(&mut self, offset: usize, data: Vec<u8>) -> Result<Vec<u16>> {
-        if data.is_empty()
-            || self
-                .get_unsealed_data(offset, data.len() / BYTES_PER_SECTOR)
-                .as_ref()
-                == Some(&data)
-        {
+        if self.contains_data(offset, &data) {
             return Ok(vec![]);
         }
         self.data.insert_data(offset * BYTES_PER_SECTOR, data)
@@ -144,6 +165,32 @@ impl EntryBatch {
         self.truncate_seal(truncated_sector)
     }
 
+    /// Remove the data in `[start_sector, end_sector)`. The sealed seals overlapping the
+    /// range are unsealed first, so the remaining data can still be read.
+    ///
+    /// Return the number of removed sectors and the seal indices overlapping the range,
+    /// which are no longer ready for sealing.
+    pub fn clear_range(&mut self, start_sector: usize, end_sector: usize) -> (usize, Vec<u16>) {
+        assert!(start_sector < end_sector && end_sector <= SECTORS_PER_LOAD);
+
+        let affected_seals = (start_sector / SECTORS_PER_SEAL) as u16
+            ..((end_sector + SECTORS_PER_SEAL - 1) / SECTORS_PER_SEAL) as u16;
+        for seal_index in affected_seals.clone() {
+            if !self.seal.is_sealed(seal_index) {
+                continue;
+            }
+            let to_unseal = self
+                .data
+                .get_mut(seal_index as usize * BYTES_PER_SEAL, BYTES_PER_SEAL)
+                .expect("Sealed chunk should be complete");
+            self.seal.unseal(to_unseal, seal_index);
+            self.seal.mark_unsealed(seal_index);
+        }
+
+        let cleared = self.data.clear_range(start_sector, end_sector);
+        (cleared, affected_seals.collect())
+    }
+
     pub fn into_data_list(self, global_start_entry: u64) -> Vec<ChunkArray> {
         self.data
             .available_range_entries()
@@ -209,6 +256,30 @@ impl EntryBatch {
         ))
     }
 
+    /// Whether every sector is known as data or covered by a subtree, i.e. whether `build_root`
+    /// returns a root. Unlike `build_root`, no merkle tree is built.
+    pub fn is_complete(&self, is_first_chunk: bool) -> bool {
+        let known = |start_sector: usize, end_sector: usize| {
+            start_sector >= end_sector
+                || self
+                    .data
+                    .get(
+                        start_sector * BYTES_PER_SECTOR,
+                        (end_sector - start_sector) * BYTES_PER_SECTOR,
+                    )
+                    .is_some()
+        };
+        // The first sector of the flow is regarded as known, like in `to_merkle_tree`.
+        let mut next_sector = if is_first_chunk { 1 } else { 0 };
+        for subtree in self.data.get_subtree_list() {
+            if !known(next_sector, subtree.start_sector) {
+                return false;
+            }
+            next_sector = subtree.start_sector + (1 << (subtree.subtree_height - 1));
+        }
+        known(next_sector, SECTORS_PER_LOAD)
+    }
+
     pub fn submit_seal_result(&mut self, answer: SealAnswer) -> Result<()> {
         let local_seal_index = answer.seal_index as usize % SEALS_PER_LOAD;
         assert!(
@@ -236,6 +307,15 @@ impl EntryBatch {
         Ok(())
     }
 
+    /// Return the subtrees of the unknown data as `(start_sector, subtree_height, root)`.
+    pub fn subtree_list(&self) -> Vec<(usize, usize, DataRoot)> {
+        self.data
+            .get_subtree_list()
+            .iter()
+            .map(|subtree| (subtree.start_sector, subtree.subtree_height, subtree.root))
+            .collect()
+    }
+
     /// This is only called once when the batch is removed from the memory and fully stored in db.
     pub fn set_subtree_list(&mut self, subtree_list: Vec<(usize, usize, DataRoot)>) {
         self.data.set_subtree_list(subtree_list)
@@ -274,6 +354,25 @@ impl EntryBatch {
         }
         Ok(Some(merkle))
     }
+
+    /// Build a merkle tree over the data currently in the batch. Missing sectors are padded
+    /// with zero-filled sectors, so the root is only meaningful for this partial state.
+    pub fn to_partial_merkle_tree(&self, is_first_chunk: bool) -> Result<Merkle> {
+        let pad_leaf = Sha3Algorithm::leaf(&[0u8; BYTES_PER_SECTOR]);
+        let mut leaves = vec![pad_leaf; SECTORS_PER_LOAD];
+        for (start_sector, length_sector) in self.data.available_range_entries() {
+            let leaf_data = self
+                .get_unsealed_data(start_sector, length_sector)
+                .expect("available range");
+            for (index, leaf) in data_to_merkle_leaves(&leaf_data)?.into_iter().enumerate() {
+                leaves[start_sector + index] = leaf;
+            }
+        }
+        if is_first_chunk {
+            leaves[0] = H256::zero();
+        }
+        Ok(Merkle::new(leaves, 0, None))
+    }
 }
 
 #[cfg(test)]
@@ -311,6 +410,30 @@ mod tests {
             .unwrap();
     }
 
+    #[test]
+    fn test_is_complete() {
+        let mut batch = EntryBatch::new(LOAD_INDEX);
+        assert!(!batch.is_complete(false));
+        batch
+            .insert_data(0, vec![11; (SECTORS_PER_LOAD - 1) * BYTES_PER_SECTOR])
+            .unwrap();
+        assert!(!batch.is_complete(false));
+        assert!(batch.build_root(false).unwrap().is_none());
+        batch
+            .insert_data(SECTORS_PER_LOAD - 1, vec![11; BYTES_PER_SECTOR])
+            .unwrap();
+        assert!(batch.is_complete(false));
+        assert!(batch.build_root(false).unwrap().is_some());
+
+        // The first sector of the first batch is not stored.
+        let mut batch = EntryBatch::new(0);
+        batch
+            .insert_data(1, vec![11; (SECTORS_PER_LOAD - 1) * BYTES_PER_SECTOR])
+            .unwrap();
+        assert!(batch.is_complete(true));
+        assert!(batch.build_root(true).unwrap().is_some());
+    }
+
     #[test]
     fn test_seal_single() {
         let mut batch = EntryBatch::new(LOAD_INDEX);
@@ -419,6 +542,35 @@ mod tests {
         check_two_seals(&batch);
     }
 
+    #[test]
+    fn test_clear_range_sealed() {
+        let mut batch = EntryBatch::new(LOAD_INDEX);
+        batch.insert_data(0, vec![11; BYTES_PER_SEAL * 3]).unwrap();
+
+        const DIGEST: H256 = H256([22u8; 32]);
+        seal(&mut batch, 0, DIGEST, 3);
+        seal(&mut batch, 1, DIGEST, 3);
+        seal(&mut batch, 2, DIGEST, 3);
+
+        let (cleared, seals) = batch.clear_range(SECTORS_PER_SEAL + 1, SECTORS_PER_SEAL + 2);
+        assert_eq!(cleared, 1);
+        assert_eq!(seals, vec![1]);
+        assert!(batch.get_sealed_data(0).is_some());
+        assert!(batch.get_sealed_data(1).is_none());
+        assert!(batch.get_sealed_data(2).is_some());
+        assert!(batch.get_unsealed_data(SECTORS_PER_SEAL + 1, 1).is_none());
+        assert_eq!(
+            batch.get_unsealed_data(0, SECTORS_PER_SEAL + 1).unwrap(),
+            vec![11; (SECTORS_PER_SEAL + 1) * BYTES_PER_SECTOR]
+        );
+        assert_eq!(
+            batch
+                .get_unsealed_data(SECTORS_PER_SEAL + 2, 2 * SECTORS_PER_SEAL - 2)
+                .unwrap(),
+            vec![11; (2 * SECTORS_PER_SEAL - 2) * BYTES_PER_SECTOR]
+        );
+    }
+
     #[test]
     fn test_seal_hete_context_partial() {
         let mut batch = EntryBatch::new(LOAD_INDEX);
@@ -9,7 +9,7 @@ use zgs_spec::{SEALS_PER_LOAD, SECTORS_PER_LOAD, SECTORS_PER_SEAL};
 
 use super::bitmap::WrappedBitmap;
 
-#[derive(Debug, DeriveEncode, DeriveDecode, Deserialize, Serialize)]
+#[derive(Debug, Clone, DeriveEncode, DeriveDecode, Deserialize, Serialize)]
 pub struct SealContextInfo {
     /// The context digest for this seal group
     context_digest: H256,
@@ -20,7 +20,7 @@ pub struct SealContextInfo {
 type ChunkSealBitmap = WrappedBitmap<SEALS_PER_LOAD>;
 const_assert!(SEALS_PER_LOAD <= u128::BITS as usize);
 
-#[derive(Debug, Default, DeriveEncode, DeriveDecode, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, DeriveEncode, DeriveDecode, Deserialize, Serialize)]
 pub struct SealInfo {
     // a bitmap specify which sealing chunks have been sealed
     #[serde(skip)]
@@ -50,6 +50,10 @@ impl SealInfo {
         self.bitmap.set(seal_index as usize, true);
     }
 
+    pub fn mark_unsealed(&mut self, seal_index: u16) {
+        self.bitmap.set(seal_index as usize, false);
+    }
+
     pub fn load_index(&self) -> u64 {
         self.load_index
     }
@@ -1,11 +1,12 @@
 use crate::config::ShardConfig;
+use crate::error::Error;
 use crate::log_store::flow_store::{
     batch_iter_sharded, FlowConfig, FlowDBStore, FlowStore, PadPair,
 };
 use crate::log_store::tx_store::{BlockHashAndSubmissionIndex, TransactionStore, TxStatus};
 use crate::log_store::{
-    FlowRead, FlowSeal, FlowWrite, LogStoreChunkRead, LogStoreChunkWrite, LogStoreRead,
-    LogStoreWrite, MineLoadChunk, SealAnswer, SealTask,
+    FlowHealth, FlowRead, FlowSeal, FlowWrite, LogStoreChunkRead, LogStoreChunkWrite, LogStoreRead,
+    LogStoreWrite, MineLoadChunk, SealAnswer, SealBacklog, SealTask,
 };
 use crate::{try_option, ZgsKeyValueDB};
 use anyhow::{anyhow, bail, Result};
@@ -30,7 +31,7 @@ use std::time::{Duration, Instant};
 
 use tracing::{debug, error, info, instrument, trace, warn};
 
-use crate::log_store::metrics;
+use crate::log_store::{metrics, metrics_export};
 
 /// 256 Bytes
 pub const ENTRY_SIZE: usize = 256;
@@ -46,7 +47,10 @@ pub const COL_FLOW_MPT_NODES: u32 = 5; // flow db
 pub const COL_BLOCK_PROGRESS: u32 = 6; // flow db
 pub const COL_PAD_DATA_LIST: u32 = 7; // flow db
 pub const COL_PAD_DATA_SYNC_HEIGH: u32 = 8; // data db
-pub const COL_NUM: u32 = 9;
+pub const COL_ROOT_INDEX: u32 = 9; // data db
+pub const COL_SEAL_QUARANTINE: u32 = 10; // data db
+pub const COL_BATCH_MERKLE: u32 = 11; // data db
+pub const COL_NUM: u32 = 12;
 
 pub const DATA_DB_KEY: &str = "data_db";
 pub const FLOW_DB_KEY: &str = "flow_db";
@@ -317,6 +321,8 @@ impl LogStoreWrite for LogManager {
             if same_root_seq_list.first() == Some(&tx_seq) {
                 self.copy_tx_and_finalize(tx_seq, same_root_seq_list[1..].to_vec())?;
             }
+            // The buffered data must be written before the tx is marked as finalized.
+            self.flow_store.flush_append_buffer()?;
             self.tx_store.finalize_tx(tx_seq)?;
             Ok(())
         } else {
@@ -346,6 +352,8 @@ impl LogStoreWrite for LogManager {
         // TODO: Should we double check the tx merkle root?
         let tx_end_index = tx.start_entry_index + bytes_to_entries(tx.size);
         if self.check_data_completed(tx.start_entry_index, tx_end_index)? {
+            // The buffered data must be written before the tx is marked as finalized.
+            self.flow_store.flush_append_buffer()?;
             self.tx_store.finalize_tx(tx_seq)?;
             let same_root_seq_list = self
                 .tx_store
@@ -388,7 +396,7 @@ impl LogStoreWrite for LogManager {
         );
         let start_index = merkle.last_chunk_start_index() * PORA_CHUNK_SIZE as u64
             + merkle.last_chunk_merkle.leaves() as u64;
-        self.flow_store.truncate(start_index)?;
+        self.flow_store.truncate_soft(start_index)?;
         let start = if tx_seq != u64::MAX { tx_seq + 1 } else { 0 };
         self.tx_store.remove_tx_after(start)
     }
@@ -417,10 +425,22 @@ impl LogStoreWrite for LogManager {
         self.flow_store.update_shard_config(shard_config)
     }
 
-    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<()> {
+    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<Vec<SealAnswer>> {
         self.flow_store.submit_seal_result(answers)
     }
 
+    fn resync_seal_set(&self) -> Result<()> {
+        self.flow_store.resync_seal_set()
+    }
+
+    fn rebuild_seal_set(&self, start_batch: u64, end_batch: u64) -> Result<usize> {
+        self.flow_store.rebuild_seal_set(start_batch, end_batch)
+    }
+
+    fn flush(&self) -> Result<()> {
+        self.flow_store.flush()
+    }
+
     fn start_padding(&self, executor: &task_executor::TaskExecutor) {
         let store = self.flow_store.clone();
         executor.spawn(
@@ -529,6 +549,25 @@ impl LogStoreChunkRead for LogManager {
         self.flow_store
             .get_entries(start_flow_index, end_flow_index)
     }
+
+    fn get_entries_at_version(
+        &self,
+        start: u64,
+        end: u64,
+        min_flow_length: u64,
+    ) -> crate::error::Result<Option<ChunkArray>> {
+        // Hold the lock during the read, because `revert_to` truncates the flow with it held.
+        let merkle = self.merkle.read_recursive();
+        let flow_length =
+            merkle.last_chunk_start_index() + merkle.last_chunk_merkle.leaves() as u64;
+        if flow_length < min_flow_length {
+            bail!(Error::Stale {
+                flow_length,
+                min_flow_length,
+            });
+        }
+        self.flow_store.get_entries(start, end)
+    }
 }
 
 impl LogStoreRead for LogManager {
@@ -647,6 +686,35 @@ impl LogStoreRead for LogManager {
         })
     }
 
+    fn gen_tx_root_proof(&self, tx_seq: u64) -> crate::error::Result<(DataRoot, FlowProof)> {
+        let tx = self
+            .tx_store
+            .get_tx_by_seq_number(tx_seq)?
+            .ok_or_else(|| anyhow!("tx missing: tx_seq={}", tx_seq))?;
+        let (depth, root) = match tx.merkle_nodes.as_slice() {
+            [(depth, root)] if *root == tx.data_merkle_root => (*depth, *root),
+            _ => bail!(
+                "tx data root is not a node in the flow tree: tx_seq={} subtrees={}",
+                tx_seq,
+                tx.merkle_nodes.len()
+            ),
+        };
+        let tx_end_index = tx.start_entry_index + bytes_to_entries(tx.size);
+        if !self.check_data_completed(tx.start_entry_index, tx_end_index)? {
+            bail!("tx data missing: tx_seq={}", tx_seq);
+        }
+
+        // The tx subtree root is the ancestor of its first sector at `depth - 1`, so its proof
+        // is the upper part of the sector proof.
+        let sector_proof = self.gen_proof(tx.start_entry_index, None)?;
+        let height = depth - 1;
+        let mut lemma = vec![root];
+        lemma.extend_from_slice(&sector_proof.lemma()[height + 1..]);
+        let proof = FlowProof::new(lemma, sector_proof.path()[height..].to_vec())?;
+        proof.validate::<Sha3Algorithm>(&root, (tx.start_entry_index >> height) as usize)?;
+        Ok((root, proof))
+    }
+
     fn get_context(&self) -> crate::error::Result<(DataRoot, u64)> {
         let merkle = self.merkle.read_recursive();
         Ok((
@@ -674,6 +742,14 @@ impl LogStoreRead for LogManager {
     fn get_shard_config(&self) -> ShardConfig {
         self.flow_store.get_shard_config()
     }
+
+    fn get_seal_backlog(&self) -> SealBacklog {
+        self.flow_store.seal_backlog()
+    }
+
+    fn get_flow_health(&self) -> Result<FlowHealth> {
+        self.flow_store.health()
+    }
 }
 
 impl LogManager {
@@ -689,6 +765,18 @@ impl LogManager {
         Self::new(flow_db_source, data_db_source, config)
     }
 
+    /// Open a rocksdb db as a secondary instance, which reads the db at `primary_path` while
+    /// another process writes it. Its own info logs are kept at `secondary_path`.
+    /// Call `try_catch_up_with_primary` to see the writes after it is opened.
+    pub fn open_secondary_db(
+        primary_path: impl AsRef<Path>,
+        secondary_path: impl AsRef<Path>,
+    ) -> Result<Arc<Database>> {
+        let mut db_config = DatabaseConfig::with_columns(COL_NUM);
+        db_config.secondary = Some(secondary_path.as_ref().to_path_buf());
+        Ok(Arc::new(Database::open(&db_config, primary_path)?))
+    }
+
     pub fn memorydb(config: LogConfig) -> Result<Self> {
         let flow_db = Arc::new(kvdb_memorydb::create(COL_NUM));
         let data_db = Arc::new(kvdb_memorydb::create(COL_NUM));
@@ -700,14 +788,30 @@ impl LogManager {
         data_db_source: Arc<dyn ZgsKeyValueDB>,
         config: LogConfig,
     ) -> Result<Self> {
+        config.flow.validate()?;
         let tx_store = TransactionStore::new(flow_db_source.clone(), data_db_source.clone())?;
-        let flow_db = Arc::new(FlowDBStore::new(flow_db_source.clone()));
-        let data_db = Arc::new(FlowDBStore::new(data_db_source.clone()));
+        let flow_db = Arc::new(FlowDBStore::new_with_columns(
+            flow_db_source.clone(),
+            config.flow.columns,
+        ));
+        let data_db = Arc::new(FlowDBStore::new_with_columns(
+            data_db_source.clone(),
+            config.flow.columns,
+        ));
         let flow_store = Arc::new(FlowStore::new(
             flow_db.clone(),
             data_db.clone(),
             config.flow.clone(),
         ));
+        if config.flow.seal_set_compaction_interval.is_some() {
+            // A broken persisted set is not fatal, since `resync_seal_set` can rebuild it.
+            match flow_store.restore_seal_set() {
+                Ok(restored) => info!("Restore seal set: seals={}", restored),
+                Err(e) => error!("Restore seal set failed: {:?}", e),
+            }
+        }
+        flow_store.resume_truncate()?;
+        flow_store.initialize_genesis()?;
         // If the last tx `put_tx` does not complete, we will revert it in `pora_chunks_merkle`
         // first and call `put_tx` later.
         let next_tx_seq = tx_store.next_tx_seq();
@@ -718,11 +822,13 @@ impl LogManager {
         };
         let mut last_tx_to_insert = None;
 
-        let mut pora_chunks_merkle = Merkle::new_with_subtrees(
-            flow_db,
-            config.flow.merkle_node_cache_capacity,
-            log2_pow2(PORA_CHUNK_SIZE),
-        )?;
+        let node_cache_capacity = config.flow.merkle_node_cache_capacity.node_count();
+        info!(
+            "Merkle node cache: capacity={:?} nodes={}",
+            config.flow.merkle_node_cache_capacity, node_cache_capacity
+        );
+        let mut pora_chunks_merkle =
+            Merkle::new_with_subtrees(flow_db, node_cache_capacity, log2_pow2(PORA_CHUNK_SIZE))?;
         if let Some(last_tx_seq) = start_tx_seq {
             if !tx_store.check_tx_completed(last_tx_seq)? {
                 // Last tx not finalized, we need to check if its `put_tx` is completed.
@@ -823,6 +929,23 @@ impl LogManager {
             .merkle
             .write()
             .try_initialize(&log_manager.flow_store)?;
+        let flow_length = {
+            let merkle = log_manager.merkle.read();
+            merkle.last_chunk_start_index() + merkle.last_chunk_merkle.leaves() as u64
+        };
+        log_manager.flow_store.set_flow_length(flow_length);
+        if config.flow.self_check {
+            match log_manager.flow_store.rebuild_root_index() {
+                Ok(count) => info!("Rebuild root index: batches={}", count),
+                Err(e) => error!("Rebuild root index failed: {:?}", e),
+            }
+        }
+        if config.flow.warmup_batches > 0 {
+            // A failed warmup only leaves the caches cold.
+            if let Err(e) = log_manager.warmup(config.flow.warmup_batches) {
+                warn!("Warmup failed: {:?}", e);
+            }
+        }
         info!(
             "Log manager initialized, state={:?}",
             log_manager.get_context()?
@@ -830,6 +953,23 @@ impl LogManager {
         Ok(log_manager)
     }
 
+    /// Warm up the flow store and load the MPT nodes on the proof paths of the last `count`
+    /// chunks in our shard range into the node cache.
+    fn warmup(&self, count: usize) -> Result<()> {
+        self.flow_store.warmup()?;
+        let shard_config = self.flow_store.get_shard_config();
+        let merkle = self.merkle.read();
+        let leaves = merkle.pora_chunks_merkle.leaves();
+        for seg_index in (0..leaves)
+            .rev()
+            .filter(|seg_index| shard_config.in_range(*seg_index as u64))
+            .take(count)
+        {
+            merkle.pora_chunks_merkle.gen_proof(seg_index)?;
+        }
+        Ok(())
+    }
+
     fn gen_proof(&self, flow_index: u64, maybe_root: Option<DataRoot>) -> Result<FlowProof> {
         match maybe_root {
             None => self.gen_proof_at_version(flow_index, None),
@@ -1028,6 +1168,8 @@ impl LogManager {
         flow_entry_array: ChunkArray,
         merkle: &mut MerkleManager,
     ) -> Result<()> {
+        // Checked before the merkle tree is updated, so a throttled append leaves it unchanged.
+        self.flow_store.check_seal_backlog()?;
         let last_chunk_start_index = merkle.last_chunk_start_index();
         if flow_entry_array.start_index + bytes_to_chunks(flow_entry_array.data.len()) as u64
             > last_chunk_start_index
@@ -1095,11 +1237,55 @@ impl LogManager {
         vec![0; len * ENTRY_SIZE]
     }
 
-    #[cfg(test)]
     pub fn flow_store(&self) -> &FlowStore {
         &self.flow_store
     }
 
+    /// Spawn the task that persists the seal set at `FlowConfig::seal_set_compaction_interval`.
+    /// Each round rewrites the whole set as one range-encoded record, so the completed seals
+    /// do not leave many small records behind. Do nothing if the interval is not set.
+    pub fn start_seal_set_compaction(&self, executor: &task_executor::TaskExecutor) {
+        let interval = match self.flow_store.config().seal_set_compaction_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        let store = self.flow_store.clone();
+        executor.spawn(
+            async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    match store.persist_seal_set() {
+                        Ok(runs) => trace!("Persist seal set: runs={}", runs),
+                        Err(e) => warn!("Persist seal set failed: {:?}", e),
+                    }
+                }
+            },
+            "seal_set_compaction",
+        );
+    }
+
+    /// Spawn the task that pushes the log store metrics to the exporter of
+    /// `FlowConfig::metrics_export` at `metrics_export_interval`. Do nothing if it is disabled.
+    pub fn start_metrics_export(&self, executor: &task_executor::TaskExecutor) {
+        let config = self.flow_store.config();
+        let exporter = match config.metrics_export.exporter() {
+            Some(exporter) => exporter,
+            None => return,
+        };
+        let interval = config.metrics_export_interval;
+        executor.spawn(
+            async move {
+                loop {
+                    tokio::time::sleep(interval).await;
+                    if let Err(e) = exporter.export(&metrics_export::log_store_samples()) {
+                        warn!("Export log store metrics failed: {:?}", e);
+                    }
+                }
+            },
+            "log_store_metrics_export",
+        );
+    }
+
     fn padding_rear_data(&self, tx: &Transaction) -> Result<()> {
         let (chunks, _) = compute_padded_chunk_size(tx.size as usize);
         let (segments_for_proof, last_segment_size_for_proof) =
@@ -1195,6 +1381,7 @@ impl LogManager {
         }
         // num_entries() includes the rear padding data, so no need for more padding.
 
+        self.flow_store.flush_append_buffer()?;
         for (seq, _) in to_tx_offset_list {
             self.tx_store.finalize_tx(seq)?;
         }
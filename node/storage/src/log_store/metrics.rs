@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use metrics::{register_timer, Gauge, GaugeUsize, Timer};
+use metrics::{register_meter, register_timer, Gauge, GaugeUsize, Histogram, Meter, Sample, Timer};
+use parking_lot::Mutex;
 
 lazy_static::lazy_static! {
     pub static ref PUT_TX: Arc<dyn Timer> = register_timer("log_store_put_tx");
@@ -39,5 +41,51 @@ lazy_static::lazy_static! {
 
     pub static ref DATA_TO_MERKLE_LEAVES_SIZE: Arc<dyn Gauge<usize>> = GaugeUsize::register("log_store_data_to_merkle_leaves_size");
 
+    pub static ref FIRST_CHUNK_OFFSET_FIX: Arc<dyn Meter> = register_meter("log_store_flow_store_get_entries_first_chunk_offset_fix");
+
+    pub static ref APPEND_ENTRIES_DUPLICATE_BATCH: Arc<dyn Meter> = register_meter("log_store_flow_store_append_entries_duplicate_batch");
+
+    pub static ref APPEND_ENTRIES_TRUNCATED_BATCH: Arc<dyn Meter> = register_meter("log_store_flow_store_append_entries_truncated_batch");
+
+    pub static ref APPEND_BUFFER_FLUSH: Arc<dyn Meter> = register_meter("log_store_flow_store_append_buffer_flush");
+
+    pub static ref APPEND_WRITE_WINDOW_FLUSH: Arc<dyn Meter> = register_meter("log_store_flow_store_append_write_window_flush");
+
+    pub static ref SEAL_QUARANTINE_RESTORE: Arc<dyn Meter> = register_meter("log_store_flow_store_seal_quarantine_restore");
+
+    pub static ref SEALS_SUBMITTED: Arc<dyn Meter> = register_meter("log_store_flow_store_seals_submitted");
+
+    pub static ref SEAL_THROUGHPUT: Arc<dyn Gauge<usize>> = GaugeUsize::register("log_store_flow_store_seal_throughput");
+
+    pub static ref SEAL_LEASES_OUTSTANDING: Arc<dyn Gauge<usize>> = GaugeUsize::register("log_store_flow_store_seal_leases_outstanding");
+
+    pub static ref SEAL_VERIFICATION_FAILED: Arc<dyn Meter> = register_meter("log_store_flow_store_seal_verification_failed");
+
+    pub static ref READS_IN_FLIGHT: Arc<dyn Gauge<usize>> = GaugeUsize::register("log_store_flow_store_reads_in_flight");
+
+    pub static ref PROOF_CACHE_HIT: Arc<dyn Meter> = register_meter("log_store_flow_store_proof_cache_hit");
+
+    pub static ref BATCH_MERKLE_HIT: Arc<dyn Meter> = register_meter("log_store_flow_store_batch_merkle_hit");
+
     pub static ref TX_BY_SEQ_NUMBER: Arc<dyn Timer> = register_timer("log_store_tx_store_get_tx_by_seq_number");
 }
+
+lazy_static::lazy_static! {
+    /// The op count and byte size histograms of the db transactions of each write path,
+    /// registered on first use.
+    static ref DB_TX_SIZE: Mutex<HashMap<&'static str, (Arc<dyn Histogram>, Arc<dyn Histogram>)>> =
+        Default::default();
+}
+
+/// Record a db transaction written by `FlowDBStore` for the write `path`.
+pub fn record_db_tx(path: &'static str, ops: usize, bytes: usize) {
+    let mut db_tx_size = DB_TX_SIZE.lock();
+    let (ops_histogram, bytes_histogram) = db_tx_size.entry(path).or_insert_with(|| {
+        (
+            Sample::ExpDecay(0.015).register_with_group("log_store_db_tx_ops", path, 1024),
+            Sample::ExpDecay(0.015).register_with_group("log_store_db_tx_bytes", path, 1024),
+        )
+    });
+    ops_histogram.update(ops as u64);
+    bytes_histogram.update(bytes as u64);
+}
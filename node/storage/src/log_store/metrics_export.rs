@@ -0,0 +1,176 @@
+use anyhow::Result;
+use metrics::{DEFAULT_GROUPING_REGISTRY, DEFAULT_REGISTRY};
+use std::fmt::{Debug, Formatter, Write as _};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// The prefix of the names of the metrics recorded by the log store.
+const LOG_STORE_METRIC_PREFIX: &str = "log_store";
+
+/// One metric read from the metrics registries. `value` is the value formatted by the
+/// `metrics` crate, either a number or `{field: number, ...}` for the composite metrics like
+/// the timers and histograms.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricSample {
+    pub name: String,
+    pub kind: String,
+    pub value: String,
+}
+
+/// A sink that the log store metrics are pushed to by `LogManager::start_metrics_export`.
+/// The metrics are still recorded through the `metrics` crate, so the exporter only decides
+/// where the collected values go.
+pub trait MetricsExporter: Send + Sync {
+    fn export(&self, samples: &[MetricSample]) -> Result<()>;
+}
+
+/// The exporter selected by `FlowConfig::metrics_export`.
+#[derive(Clone, Default)]
+pub enum MetricsExport {
+    #[default]
+    Disabled,
+    /// Write the metrics in the Prometheus text format to a file, e.g. for the textfile
+    /// collector of the node exporter.
+    Prometheus(PathBuf),
+    Custom(Arc<dyn MetricsExporter>),
+}
+
+impl MetricsExport {
+    pub fn exporter(&self) -> Option<Arc<dyn MetricsExporter>> {
+        match self {
+            MetricsExport::Disabled => None,
+            MetricsExport::Prometheus(path) => {
+                Some(Arc::new(PrometheusExporter::new(path.clone())))
+            }
+            MetricsExport::Custom(exporter) => Some(exporter.clone()),
+        }
+    }
+}
+
+impl Debug for MetricsExport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MetricsExport::Disabled => write!(f, "Disabled"),
+            MetricsExport::Prometheus(path) => write!(f, "Prometheus({:?})", path),
+            MetricsExport::Custom(_) => write!(f, "Custom"),
+        }
+    }
+}
+
+/// Read the log store metrics from the default registries, ordered by name. The grouped
+/// metrics are named `group.name`.
+pub fn log_store_samples() -> Vec<MetricSample> {
+    let mut samples = Vec::new();
+    for (name, metric) in DEFAULT_REGISTRY.read().get_all() {
+        if name.starts_with(LOG_STORE_METRIC_PREFIX) {
+            samples.push(MetricSample {
+                name: name.clone(),
+                kind: metric.get_type().to_string(),
+                value: metric.get_value().to_string(),
+            });
+        }
+    }
+    for (group_name, metrics) in DEFAULT_GROUPING_REGISTRY.read().get_all() {
+        if !group_name.starts_with(LOG_STORE_METRIC_PREFIX) {
+            continue;
+        }
+        for (metric_name, metric) in metrics.iter() {
+            samples.push(MetricSample {
+                name: format!("{}.{}", group_name, metric_name),
+                kind: metric.get_type().to_string(),
+                value: metric.get_value().to_string(),
+            });
+        }
+    }
+    samples.sort_by(|a, b| a.name.cmp(&b.name));
+    samples
+}
+
+/// Write the samples to `path` in the Prometheus text format. The file is replaced through a
+/// temporary file, so a scraper never reads a partial one.
+pub struct PrometheusExporter {
+    path: PathBuf,
+}
+
+impl PrometheusExporter {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl MetricsExporter for PrometheusExporter {
+    fn export(&self, samples: &[MetricSample]) -> Result<()> {
+        let tmp_path = self.path.with_extension("tmp");
+        fs::write(&tmp_path, encode_prometheus(samples))?;
+        fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+}
+
+/// Encode each numeric field of a sample as a gauge named `<name>_<field>`. The values that
+/// are not numbers are skipped.
+fn encode_prometheus(samples: &[MetricSample]) -> String {
+    let mut encoded = String::new();
+    for sample in samples {
+        let name = prometheus_name(&sample.name);
+        let value = sample.value.trim();
+        let fields: Vec<(String, &str)> = match value.strip_prefix('{') {
+            Some(inner) => inner
+                .trim_end_matches('}')
+                .split(',')
+                .filter_map(|field| {
+                    let (key, value) = field.split_once(':')?;
+                    Some((
+                        format!("{}_{}", name, prometheus_name(key.trim())),
+                        value.trim(),
+                    ))
+                })
+                .collect(),
+            None => vec![(name, value)],
+        };
+        for (name, value) in fields {
+            if value.parse::<f64>().is_err() {
+                continue;
+            }
+            let _ = writeln!(encoded, "# TYPE {} gauge", name);
+            let _ = writeln!(encoded, "{} {}", name, value);
+        }
+    }
+    encoded
+}
+
+/// Replace the characters that are not allowed in a Prometheus metric name.
+fn prometheus_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_prometheus, MetricSample};
+
+    #[test]
+    fn test_encode_prometheus() {
+        let sample = |name: &str, value: &str| MetricSample {
+            name: name.to_string(),
+            kind: "gauge".to_string(),
+            value: value.to_string(),
+        };
+        let encoded = encode_prometheus(&[
+            sample("log_store_reads_in_flight", "3"),
+            sample("log_store_db_tx_ops.put_pad_data", "{count: 2, mean: 1.5}"),
+            sample("log_store_broken", "n/a"),
+        ]);
+        assert_eq!(
+            encoded,
+            "# TYPE log_store_reads_in_flight gauge\n\
+             log_store_reads_in_flight 3\n\
+             # TYPE log_store_db_tx_ops_put_pad_data_count gauge\n\
+             log_store_db_tx_ops_put_pad_data_count 2\n\
+             # TYPE log_store_db_tx_ops_put_pad_data_mean gauge\n\
+             log_store_db_tx_ops_put_pad_data_mean 1.5\n"
+        );
+    }
+}
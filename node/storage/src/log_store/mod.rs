@@ -2,10 +2,12 @@ use crate::config::ShardConfig;
 
 use ethereum_types::H256;
 use flow_store::PadPair;
+use futures::Stream;
 use shared_types::{
     Chunk, ChunkArray, ChunkArrayWithProof, ChunkWithProof, DataRoot, FlowProof, FlowRangeProof,
     Transaction,
 };
+use std::time::Duration;
 use zgs_spec::{BYTES_PER_SEAL, SEALS_PER_LOAD};
 
 use crate::error::Result;
@@ -13,11 +15,14 @@ use crate::error::Result;
 use self::tx_store::{BlockHashAndSubmissionIndex, TxStatus};
 
 pub mod config;
-mod flow_store;
+pub mod flow_store;
 pub mod load_chunk;
 pub mod log_manager;
 mod metrics;
+pub mod metrics_export;
+pub mod op_log;
 mod seal_task_manager;
+pub mod sharded_flow_store;
 #[cfg(test)]
 mod tests;
 pub mod tx_store;
@@ -83,6 +88,11 @@ pub trait LogStoreRead: LogStoreChunkRead {
         length: u64,
     ) -> Result<FlowRangeProof>;
 
+    /// Return the data root of a tx and its proof in the flow tree against the current flow
+    /// root. Only the tx stored as a single flow subtree has its data root as a tree node, so
+    /// an error is returned for the other txs or if the tx data are not fully stored.
+    fn gen_tx_root_proof(&self, tx_seq: u64) -> Result<(DataRoot, FlowProof)>;
+
     /// Return flow root and length.
     fn get_context(&self) -> Result<(DataRoot, u64)>;
 
@@ -93,6 +103,13 @@ pub trait LogStoreRead: LogStoreChunkRead {
     fn load_sealed_data(&self, chunk_index: u64) -> Result<Option<MineLoadChunk>>;
 
     fn get_shard_config(&self) -> ShardConfig;
+
+    /// Return the current seal backlog, so the caller can throttle ingest before
+    /// `Error::SealBacklogFull` is hit.
+    fn get_seal_backlog(&self) -> SealBacklog;
+
+    /// Return a summary of the flow store state for health checks.
+    fn get_flow_health(&self) -> Result<FlowHealth>;
 }
 
 pub trait LogStoreChunkRead {
@@ -125,6 +142,16 @@ pub trait LogStoreChunkRead {
 
     /// Accessing chunks by absolute flow index
     fn get_chunk_by_flow_index(&self, index: u64, length: u64) -> Result<Option<ChunkArray>>;
+
+    /// Get the entries in `[start, end)` of the flow, only if the flow length is at least
+    /// `min_flow_length`. Otherwise, `Error::Stale` is returned.
+    /// No revert can happen during the read, so the data are consistent with the checked length.
+    fn get_entries_at_version(
+        &self,
+        start: u64,
+        end: u64,
+        min_flow_length: u64,
+    ) -> Result<Option<ChunkArray>>;
 }
 
 pub trait LogStoreWrite: LogStoreChunkWrite {
@@ -165,7 +192,14 @@ pub trait LogStoreWrite: LogStoreChunkWrite {
 
     fn update_shard_config(&self, shard_config: ShardConfig);
 
-    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<()>;
+    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<Vec<SealAnswer>>;
+
+    fn resync_seal_set(&self) -> Result<()>;
+
+    fn rebuild_seal_set(&self, start_batch: u64, end_batch: u64) -> Result<usize>;
+
+    /// Persist the buffered writes. See `FlowStore::flush`.
+    fn flush(&self) -> Result<()>;
 
     fn start_padding(&self, executor: &task_executor::TaskExecutor);
 }
@@ -196,6 +230,7 @@ pub trait Store:
 }
 impl<T: LogStoreRead + LogStoreWrite + config::Configurable + Send + Sync + 'static> Store for T {}
 
+#[derive(Clone)]
 pub struct MineLoadChunk {
     // Use `Vec` instead of array to avoid thread stack overflow.
     pub loaded_chunk: Vec<[u8; BYTES_PER_SEAL]>,
@@ -213,6 +248,8 @@ impl Default for MineLoadChunk {
 
 pub trait FlowRead {
     /// Return the entries in the given range. If some data are missing, `Ok(None)` is returned.
+    /// The reserved genesis sector at index 0 is skipped, so a range from 0 returns the data
+    /// from index 1, though the returned `start_index` is 0.
     fn get_entries(&self, index_start: u64, index_end: u64) -> Result<Option<ChunkArray>>;
 
     /// Return the available entries in the given range.
@@ -260,6 +297,97 @@ pub struct SealTask {
     pub non_sealed_data: [u8; BYTES_PER_SEAL],
 }
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SealBacklog {
+    /// The number of seals waiting to be sealed.
+    pub backlog: usize,
+    /// The backlog size at which appending is rejected. `None` means unbounded.
+    pub high_water_mark: Option<usize>,
+}
+
+/// The state of one seal returned by `FlowSeal::inspect_seal`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SealInspect {
+    /// The version of the seal task if the seal is waiting to be sealed.
+    pub queued_version: Option<u64>,
+    /// The version of the new seal tasks. A queued version below it was queued before a revert.
+    pub current_version: u64,
+    /// Whether the task is yielded by `seal_task_stream` and its lease has not expired.
+    pub leased: bool,
+    /// Whether the batch of the seal is stored.
+    pub batch_exists: bool,
+    /// Whether the data to seal can be loaded, which a queued task needs to be pulled.
+    pub non_sealed_data_available: bool,
+}
+
+/// Returned by `FlowSeal::seal_set_summary`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SealSetSummary {
+    /// The number of seals waiting to be sealed.
+    pub count: usize,
+    /// The lowest and the highest waiting seal index. `None` if no seal is waiting.
+    pub min_index: Option<usize>,
+    pub max_index: Option<usize>,
+    /// The number of load chunks with waiting seals.
+    pub load_chunks: usize,
+}
+
+/// A summary of the flow store state. A field is `None` if its query failed or the state is
+/// unknown yet, and the failed queries are recorded in `errors`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FlowHealth {
+    /// The flow length estimated by `get_num_entries`.
+    pub num_entries: Option<u64>,
+    /// The number of seals waiting to be sealed.
+    pub seal_backlog: usize,
+    /// The number of incomplete batches near the end of the last append. `None` if nothing
+    /// is appended since the store is opened.
+    pub incomplete_tail_batches: Option<usize>,
+    pub pad_data_sync_height: Option<u64>,
+    /// `None` if nothing is appended since the store is opened.
+    pub last_append_succeeded: Option<bool>,
+    pub init_state: Option<FlowInitState>,
+    pub errors: Vec<String>,
+}
+
+/// Returned by `FlowStore::init_state`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlowInitState {
+    /// `FlowStore::initialize_genesis` is not called, so a missing first batch does not tell
+    /// whether the flow is empty or the store is not set up.
+    Uninitialized,
+    /// Initialized and nothing is appended.
+    Empty,
+    /// Initialized and the flow has entries.
+    NonEmpty,
+}
+
+/// The result of `FlowStore::verify_commitment`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CommitmentCheck {
+    /// The committed flow root is the expected one.
+    Match,
+    /// The committed flow root is `local_root`, which is `None` if the flow is empty.
+    /// `first_divergent_batch` is the lowest stored complete batch whose root recomputed from
+    /// its data differs from its MPT leaf, so the flow should be re-synced from there. It is
+    /// `None` if all the stored batches agree with the tree, e.g. when the divergence is in the
+    /// batches of the other shards or in the upper MPT layers.
+    Mismatch {
+        local_root: Option<DataRoot>,
+        first_divergent_batch: Option<u64>,
+    },
+}
+
+/// Sent by `FlowStore::subscribe_truncations` after a successful truncate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TruncationEvent {
+    /// The entries from this index are removed.
+    pub start_index: u64,
+    /// The number of seals in the kept part of the partially truncated batch that are queued
+    /// to be sealed again.
+    pub resealed_seals: usize,
+}
+
 #[derive(Debug)]
 pub struct SealAnswer {
     /// The index (in seal) of chunks
@@ -280,9 +408,40 @@ pub trait FlowSeal {
     /// Return the global index (in sector) and the data
     fn pull_seal_chunk(&self, seal_index_max: usize) -> Result<Option<Vec<SealTask>>>;
 
-    /// Submit sealing result
+    /// Return a stream of the seal tasks below `seal_index_max`, which waits for new tasks
+    /// instead of polling. A yielded task is leased and not yielded again by any stream until
+    /// it is submitted, reverted, or the lease expires.
+    /// The stream ends if the tasks cannot be loaded from db.
+    fn seal_task_stream(&self, seal_index_max: usize) -> impl Stream<Item = SealTask> + '_;
+
+    /// Submit sealing result. Return the stale answers, whose tasks have been reverted or
+    /// re-queued with a newer version since they were pulled. They are not applied.
+    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<Vec<SealAnswer>>;
+
+    /// Rebuild the seal tasks from the seal state of the stored batches. Call it after
+    /// `Error::SealStateDesync` is returned.
+    fn resync_seal_set(&self) -> Result<()>;
+
+    /// Add the non-sealed seals of the stored batches in `[start_batch, end_batch)` that are
+    /// missing in the seal tasks, and return the number of added seals. Unlike
+    /// `resync_seal_set`, the existing tasks are kept with their versions.
+    fn rebuild_seal_set(&self, start_batch: u64, end_batch: u64) -> Result<usize>;
+
+    /// Return the state of a seal to debug why it is not sealed, or `None` if the seal is not
+    /// queued and its batch is not stored. Nothing is changed.
+    fn inspect_seal(&self, seal_index: usize) -> Result<Option<SealInspect>>;
+
+    /// Summarize the seals waiting to be sealed in one pass under the read lock, e.g. to decide
+    /// whether the seal workers can be spun down.
+    fn seal_set_summary(&self) -> SealSetSummary;
+
+    /// Estimate how long the seals waiting to be sealed take at the recent seal rate. Return
+    /// `None` if nothing has been sealed recently, so the rate is unknown.
+    fn estimated_seal_eta(&self) -> Option<Duration>;
 
-    fn submit_seal_result(&self, answers: Vec<SealAnswer>) -> Result<()>;
+    /// Return the seals waiting to be sealed in the batches `[start_batch, end_batch)` in
+    /// increasing order, e.g. to cancel them before the batches are dropped. Nothing is changed.
+    fn pending_seals_in_range(&self, start_batch: u64, end_batch: u64) -> Vec<usize>;
 }
 
 pub trait Flow: FlowRead + FlowWrite + FlowSeal {}
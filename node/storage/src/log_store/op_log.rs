@@ -0,0 +1,158 @@
+use crate::error::Error;
+use crate::log_store::flow_store::{FlowStore, PadPair};
+use crate::log_store::{FlowSeal, FlowWrite, SealAnswer};
+use anyhow::{bail, Result};
+use ethereum_types::H256;
+use parking_lot::Mutex;
+use shared_types::ChunkArray;
+use ssz::{Decode, Encode};
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use tracing::warn;
+use zgs_spec::BYTES_PER_SEAL;
+
+const OP_APPEND_ENTRIES: u8 = 0;
+const OP_TRUNCATE: u8 = 1;
+const OP_SUBMIT_SEAL_RESULT: u8 = 2;
+const OP_PUT_PAD_DATA: u8 = 3;
+
+const SEAL_ANSWER_SIZE: usize = 8 + 8 + BYTES_PER_SEAL + 32 + 32 + 8;
+
+/// A log of the writes to a flow store, which can be replayed against a fresh store with
+/// `replay` to reproduce its state. It is enabled by `FlowConfig::operation_log_path`.
+///
+/// Each record is `op: u8 | length: u32 | payload`. The calls are recorded before they are
+/// executed, so the failed calls are also recorded.
+pub struct OperationLog {
+    file: Mutex<File>,
+}
+
+impl OperationLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn log_append_entries(&self, data: &ChunkArray) {
+        self.write(OP_APPEND_ENTRIES, &data.as_ssz_bytes());
+    }
+
+    pub fn log_truncate(&self, start_index: u64) {
+        self.write(OP_TRUNCATE, &start_index.to_be_bytes());
+    }
+
+    pub fn log_submit_seal_result(&self, answers: &[SealAnswer]) {
+        let mut payload = Vec::with_capacity(answers.len() * SEAL_ANSWER_SIZE);
+        for answer in answers {
+            payload.extend_from_slice(&answer.seal_index.to_be_bytes());
+            payload.extend_from_slice(&answer.version.to_be_bytes());
+            payload.extend_from_slice(&answer.sealed_data);
+            payload.extend_from_slice(answer.miner_id.as_bytes());
+            payload.extend_from_slice(answer.seal_context.as_bytes());
+            payload.extend_from_slice(&answer.context_end_seal.to_be_bytes());
+        }
+        self.write(OP_SUBMIT_SEAL_RESULT, &payload);
+    }
+
+    pub fn log_put_pad_data(&self, data_sizes: &[PadPair], tx_seq: u64) {
+        let mut payload = tx_seq.to_be_bytes().to_vec();
+        for item in data_sizes {
+            item.ssz_append(&mut payload);
+        }
+        self.write(OP_PUT_PAD_DATA, &payload);
+    }
+
+    pub fn sync(&self) -> Result<()> {
+        self.file.lock().sync_data()?;
+        Ok(())
+    }
+
+    /// A record is written in one `write_all`, and a failed write does not fail the store call.
+    fn write(&self, op: u8, payload: &[u8]) {
+        let mut record = Vec::with_capacity(5 + payload.len());
+        record.push(op);
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(payload);
+        if let Err(e) = self.file.lock().write_all(&record) {
+            warn!("Write operation log failed: op={} e={:?}", op, e);
+        }
+    }
+}
+
+/// Re-execute the writes recorded at `log_path` against `store`, which should be a fresh store
+/// with the same config and without an operation log. A replayed call that fails is logged
+/// and skipped, because the recorded call may have failed in the same way. A partial record
+/// at the end, e.g. written before a crash, is ignored.
+/// Return the number of replayed records.
+pub fn replay(log_path: impl AsRef<Path>, store: &FlowStore) -> Result<usize> {
+    let mut reader = BufReader::new(File::open(log_path)?);
+    let mut count = 0;
+    loop {
+        let mut header = [0u8; 5];
+        let mut payload = Vec::new();
+        let read = reader.read_exact(&mut header).and_then(|()| {
+            let length = u32::from_be_bytes(header[1..].try_into().unwrap()) as usize;
+            payload.resize(length, 0);
+            reader.read_exact(&mut payload)
+        });
+        match read {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+            Err(e) => return Err(e.into()),
+        }
+        let op = header[0];
+        let result = match op {
+            OP_APPEND_ENTRIES => {
+                let data = ChunkArray::from_ssz_bytes(&payload).map_err(Error::from)?;
+                store.append_entries(data).map(|_| ())
+            }
+            OP_TRUNCATE => store.truncate(u64::from_be_bytes(payload.as_slice().try_into()?)),
+            OP_SUBMIT_SEAL_RESULT => store
+                .submit_seal_result(decode_seal_answers(&payload)?)
+                .map(|_| ()),
+            OP_PUT_PAD_DATA => {
+                if payload.len() < 8 {
+                    bail!("invalid pad data record: length={}", payload.len());
+                }
+                let tx_seq = u64::from_be_bytes(payload[..8].try_into().unwrap());
+                let data_sizes =
+                    Vec::<PadPair>::from_ssz_bytes(&payload[8..]).map_err(Error::from)?;
+                store.put_pad_data(&data_sizes, tx_seq)
+            }
+            op => bail!("invalid operation log record: op={}", op),
+        };
+        if let Err(e) = result {
+            warn!("Replayed operation failed: op={} e={:?}", op, e);
+        }
+        count += 1;
+    }
+    Ok(count)
+}
+
+fn decode_seal_answers(payload: &[u8]) -> Result<Vec<SealAnswer>> {
+    if payload.len() % SEAL_ANSWER_SIZE != 0 {
+        bail!("invalid seal answer record: length={}", payload.len());
+    }
+    let read_u64 = |data: &[u8]| u64::from_be_bytes(data.try_into().unwrap());
+    Ok(payload
+        .chunks_exact(SEAL_ANSWER_SIZE)
+        .map(|data| {
+            let (seal_index, data) = data.split_at(8);
+            let (version, data) = data.split_at(8);
+            let (sealed_data, data) = data.split_at(BYTES_PER_SEAL);
+            let (miner_id, data) = data.split_at(32);
+            let (seal_context, context_end_seal) = data.split_at(32);
+            SealAnswer {
+                seal_index: read_u64(seal_index),
+                version: read_u64(version),
+                sealed_data: sealed_data.try_into().unwrap(),
+                miner_id: H256::from_slice(miner_id),
+                seal_context: H256::from_slice(seal_context),
+                context_end_seal: read_u64(context_end_seal),
+            }
+        })
+        .collect())
+}
@@ -1,12 +1,16 @@
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, VecDeque},
     sync::atomic::{AtomicU64, Ordering},
-    time::{SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use parking_lot::RwLock;
+use anyhow::{bail, Result};
+use parking_lot::{Mutex, RwLock};
+use tokio::sync::Notify;
 use zgs_spec::SEALS_PER_LOAD;
 
+use crate::log_store::metrics;
+
 pub struct SealTaskManager {
     // TODO(kevin): This is an in-memory cache for recording which chunks are ready for sealing. It should be persisted on disk.
     pub to_seal_set: RwLock<BTreeMap<usize, u64>>,
@@ -14,6 +18,14 @@ pub struct SealTaskManager {
     // The sealing service uses the version number to distinguish if revert happens during sealing.
     to_seal_version: AtomicU64,
     last_pull_time: AtomicU64,
+    /// The seal tasks yielded by `seal_task_stream` and not submitted yet, with their versions
+    /// and lease time. A leased task is not yielded again until its lease expires.
+    pub in_flight: Mutex<BTreeMap<usize, (u64, Instant)>>,
+    /// Notified when new seal tasks are added to `to_seal_set`.
+    new_task_notify: Notify,
+    /// The number of seals accepted in each second of the last `SEAL_RATE_WINDOW_SECONDS`.
+    sealed_per_second: Mutex<VecDeque<(u64, usize)>>,
+    total_sealed: AtomicU64,
 }
 
 impl Default for SealTaskManager {
@@ -22,11 +34,15 @@ impl Default for SealTaskManager {
             to_seal_set: Default::default(),
             to_seal_version: Default::default(),
             last_pull_time: AtomicU64::new(current_timestamp()),
+            in_flight: Default::default(),
+            new_task_notify: Notify::new(),
+            sealed_per_second: Default::default(),
+            total_sealed: Default::default(),
         }
     }
 }
 
-fn current_timestamp() -> u64 {
+pub fn current_timestamp() -> u64 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .expect("unexpected negative timestamp")
@@ -34,6 +50,69 @@ fn current_timestamp() -> u64 {
 }
 
 const SEAL_TASK_PULL_TIMEOUT_SECONDS: u64 = 300;
+pub const SEAL_TASK_LEASE_TIMEOUT: Duration = Duration::from_secs(SEAL_TASK_PULL_TIMEOUT_SECONDS);
+const SEAL_RATE_WINDOW_SECONDS: u64 = 60;
+/// The number of seal versions in an epoch of `start_seal_version_epoch`.
+pub const SEAL_VERSION_EPOCH_SIZE: u64 = 1 << 32;
+/// `start: u64 | length: u64 | version: u64`.
+pub const SEAL_SET_RUN_SIZE: usize = 24;
+
+/// Encode a seal set as the runs of consecutive seal indices with the same version, so a
+/// backlog of whole batches takes one run instead of one entry per seal.
+pub fn encode_seal_set(to_seal_set: &BTreeMap<usize, u64>) -> Vec<u8> {
+    let mut runs: Vec<(u64, u64, u64)> = Vec::new();
+    for (&seal_index, &version) in to_seal_set {
+        let seal_index = seal_index as u64;
+        match runs.last_mut() {
+            Some((start, length, run_version))
+                if *start + *length == seal_index && *run_version == version =>
+            {
+                *length += 1
+            }
+            _ => runs.push((seal_index, 1, version)),
+        }
+    }
+    let mut encoded = Vec::with_capacity(runs.len() * SEAL_SET_RUN_SIZE);
+    for (start, length, version) in runs {
+        encoded.extend_from_slice(&start.to_be_bytes());
+        encoded.extend_from_slice(&length.to_be_bytes());
+        encoded.extend_from_slice(&version.to_be_bytes());
+    }
+    encoded
+}
+
+/// Decode the runs written by `encode_seal_set`. The runs may hold at most `max_seals` seals
+/// in total, so a corrupt run length is rejected instead of expanded.
+pub fn decode_seal_set(encoded: &[u8], max_seals: usize) -> Result<BTreeMap<usize, u64>> {
+    if encoded.len() % SEAL_SET_RUN_SIZE != 0 {
+        bail!("invalid seal set encoding: length={}", encoded.len());
+    }
+    let read_u64 = |data: &[u8]| u64::from_be_bytes(data.try_into().unwrap());
+    let mut to_seal_set = BTreeMap::new();
+    let mut total_seals = 0u64;
+    for run in encoded.chunks_exact(SEAL_SET_RUN_SIZE) {
+        let start = read_u64(&run[..8]);
+        let length = read_u64(&run[8..16]);
+        let version = read_u64(&run[16..]);
+        total_seals = match total_seals.checked_add(length) {
+            Some(total) if total <= max_seals as u64 => total,
+            _ => bail!(
+                "invalid seal set run: start={} length={} max_seals={}",
+                start,
+                length,
+                max_seals
+            ),
+        };
+        let end = match start.checked_add(length) {
+            Some(end) => end,
+            None => bail!("invalid seal set run: start={} length={}", start, length),
+        };
+        for seal_index in start..end {
+            to_seal_set.insert(seal_index as usize, version);
+        }
+    }
+    Ok(to_seal_set)
+}
 
 impl SealTaskManager {
     pub fn delete_batch_list(&self, batch_list: &[u64]) {
@@ -51,7 +130,46 @@ impl SealTaskManager {
     pub fn update_pull_time(&self) {
         // Here we only need an approximate timestamp and can tolerate a few seconds of error, so we used Ordering::Relaxed
         self.last_pull_time
-            .store(current_timestamp(), Ordering::Relaxed)
+            .store(current_timestamp(), Ordering::Relaxed);
+        // Refresh the throughput gauge, so it drops to zero when no seal is submitted.
+        metrics::SEAL_THROUGHPUT.update(self.seal_rate() as usize);
+    }
+
+    /// Record the seals accepted by `submit_seal_result`.
+    pub fn record_sealed(&self, count: usize) {
+        if count == 0 {
+            return;
+        }
+        let now = current_timestamp();
+        {
+            let mut sealed_per_second = self.sealed_per_second.lock();
+            match sealed_per_second.back_mut() {
+                Some((second, sealed)) if *second == now => *sealed += count,
+                _ => sealed_per_second.push_back((now, count)),
+            }
+        }
+        self.total_sealed.fetch_add(count as u64, Ordering::Relaxed);
+        metrics::SEALS_SUBMITTED.mark(count);
+        metrics::SEAL_THROUGHPUT.update(self.seal_rate() as usize);
+    }
+
+    /// Return the average number of seals accepted per second in the recent window.
+    pub fn seal_rate(&self) -> f64 {
+        let now = current_timestamp();
+        let mut sealed_per_second = self.sealed_per_second.lock();
+        while let Some((second, _)) = sealed_per_second.front() {
+            if now.saturating_sub(*second) < SEAL_RATE_WINDOW_SECONDS {
+                break;
+            }
+            sealed_per_second.pop_front();
+        }
+        let sealed: usize = sealed_per_second.iter().map(|(_, sealed)| sealed).sum();
+        sealed as f64 / SEAL_RATE_WINDOW_SECONDS as f64
+    }
+
+    /// Return the number of seals accepted since the store is opened.
+    pub fn total_sealed(&self) -> u64 {
+        self.total_sealed.load(Ordering::Relaxed)
     }
 
     pub fn seal_worker_available(&self) -> bool {
@@ -63,7 +181,102 @@ impl SealTaskManager {
         self.to_seal_version.load(Ordering::Acquire)
     }
 
+    /// The version wraps to `0` after `u64::MAX`. The versions are only compared for equality,
+    /// so a wrapped version is only confused with one 2^64 truncates older.
     pub fn inc_seal_version(&self) -> u64 {
-        self.to_seal_version.fetch_add(1, Ordering::AcqRel) + 1
+        self.to_seal_version
+            .fetch_add(1, Ordering::AcqRel)
+            .wrapping_add(1)
+    }
+
+    /// Move the version to the start of the next epoch of `SEAL_VERSION_EPOCH_SIZE` versions,
+    /// so none of the recently used versions is reused by the new tasks. It wraps to `0` after
+    /// the last epoch. Return the new version.
+    pub fn start_seal_version_epoch(&self) -> u64 {
+        let next_epoch = |version: u64| {
+            (version / SEAL_VERSION_EPOCH_SIZE + 1).wrapping_mul(SEAL_VERSION_EPOCH_SIZE)
+        };
+        let previous = self
+            .to_seal_version
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |version| {
+                Some(next_epoch(version))
+            })
+            .expect("the update always succeeds");
+        next_epoch(previous)
+    }
+
+    /// Wake up the seal task streams waiting for new tasks.
+    pub fn notify_new_tasks(&self) {
+        self.new_task_notify.notify_waiters();
+    }
+
+    pub fn new_task_notify(&self) -> &Notify {
+        &self.new_task_notify
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_seal_set, encode_seal_set, SealTaskManager, SEAL_SET_RUN_SIZE,
+        SEAL_VERSION_EPOCH_SIZE,
+    };
+    use std::collections::BTreeMap;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn test_seal_set_encoding() {
+        let mut to_seal_set = BTreeMap::new();
+        // One run of a whole batch, a run split by a version change, and single seals.
+        for seal_index in 0..1024 {
+            to_seal_set.insert(seal_index, 1);
+        }
+        for seal_index in 2048..2058 {
+            to_seal_set.insert(seal_index, if seal_index < 2053 { 1 } else { 2 });
+        }
+        to_seal_set.insert(3000, 2);
+        to_seal_set.insert(3002, 2);
+
+        let encoded = encode_seal_set(&to_seal_set);
+        assert_eq!(encoded.len(), 5 * SEAL_SET_RUN_SIZE);
+        assert_eq!(decode_seal_set(&encoded, usize::MAX).unwrap(), to_seal_set);
+        assert_eq!(
+            decode_seal_set(&encoded, to_seal_set.len()).unwrap(),
+            to_seal_set
+        );
+        assert!(decode_seal_set(&encoded, to_seal_set.len() - 1).is_err());
+
+        assert!(encode_seal_set(&BTreeMap::new()).is_empty());
+        assert!(decode_seal_set(&[], 0).unwrap().is_empty());
+        assert!(decode_seal_set(&encoded[1..], usize::MAX).is_err());
+
+        // A corrupt run length is rejected before it is expanded.
+        let mut corrupt = encoded.clone();
+        corrupt[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(decode_seal_set(&corrupt, usize::MAX).is_err());
+    }
+
+    #[test]
+    fn test_seal_version_overflow() {
+        let manager = SealTaskManager::default();
+        manager
+            .to_seal_version
+            .store(u64::MAX - 1, Ordering::Release);
+        assert_eq!(manager.inc_seal_version(), u64::MAX);
+        assert_eq!(manager.inc_seal_version(), 0);
+        assert_eq!(manager.to_seal_version(), 0);
+
+        assert_eq!(manager.start_seal_version_epoch(), SEAL_VERSION_EPOCH_SIZE);
+        manager
+            .to_seal_version
+            .store(3 * SEAL_VERSION_EPOCH_SIZE + 5, Ordering::Release);
+        assert_eq!(
+            manager.start_seal_version_epoch(),
+            4 * SEAL_VERSION_EPOCH_SIZE
+        );
+        // The last epoch wraps to the first one.
+        manager.to_seal_version.store(u64::MAX, Ordering::Release);
+        assert_eq!(manager.start_seal_version_epoch(), 0);
+        assert_eq!(manager.inc_seal_version(), 1);
     }
 }
@@ -0,0 +1,193 @@
+use crate::log_store::flow_store::{append_entries_by_shard, batch_iter_lazy, FlowStore};
+use crate::log_store::{FlowRead, FlowWrite};
+use crate::try_option;
+use anyhow::{bail, Result};
+use shared_types::{ChunkArray, DataRoot};
+use std::collections::BTreeMap;
+
+/// The shards owned by one node, each stored by its own `FlowStore` with its own `data_db`, so
+/// the shards can be put on separate disks and pruned or migrated independently. The stores
+/// can share one `flow_db`. The appends, reads and truncates are routed to the store whose
+/// shard owns each batch.
+pub struct ShardedFlowStore {
+    /// Keyed by shard id.
+    stores: BTreeMap<usize, FlowStore>,
+    batch_size: usize,
+    /// The maximum number of stores written in parallel by `append_entries`.
+    max_concurrency: usize,
+}
+
+impl ShardedFlowStore {
+    /// The stores must have distinct shard ids of the same number of shards and the same batch
+    /// size.
+    pub fn new(stores: Vec<FlowStore>, max_concurrency: usize) -> Result<Self> {
+        let (batch_size, num_shard) = match stores.first() {
+            Some(store) => (
+                store.config().batch_size,
+                store.config().shard_config.read().num_shard,
+            ),
+            None => bail!("no shard store"),
+        };
+        let mut sharded = BTreeMap::new();
+        for store in stores {
+            let shard_config = *store.config().shard_config.read();
+            if shard_config.num_shard != num_shard || store.config().batch_size != batch_size {
+                bail!(
+                    "inconsistent shard store: shard_id={} num_shard={} batch_size={}",
+                    shard_config.shard_id,
+                    shard_config.num_shard,
+                    store.config().batch_size
+                );
+            }
+            if sharded.insert(shard_config.shard_id, store).is_some() {
+                bail!("duplicated shard store: shard_id={}", shard_config.shard_id);
+            }
+        }
+        Ok(Self {
+            stores: sharded,
+            batch_size,
+            max_concurrency,
+        })
+    }
+
+    /// Return the store of a shard, e.g. to prune or export it alone.
+    pub fn store(&self, shard_id: usize) -> Option<&FlowStore> {
+        self.stores.get(&shard_id)
+    }
+
+    /// Return the store whose shard owns the batch, or `None` if no owned shard has it.
+    pub fn owner(&self, batch_index: u64) -> Option<&FlowStore> {
+        self.stores
+            .values()
+            .find(|store| store.config().shard_config.read().in_range(batch_index))
+    }
+
+    /// Append the batches to their owners with `append_entries_by_shard`. The batches owned
+    /// by no store are skipped.
+    pub fn append_entries(&self, data: ChunkArray) -> Result<Vec<(u64, DataRoot)>> {
+        let stores: Vec<&FlowStore> = self.stores.values().collect();
+        append_entries_by_shard(&stores, data, self.max_concurrency)
+    }
+
+    /// Read each batch of the range from its owner. Return `None` if any batch is not owned
+    /// or its data are missing. As in `FlowStore::get_entries`, the reserved first sector is
+    /// skipped.
+    pub fn get_entries(&self, index_start: u64, index_end: u64) -> Result<Option<ChunkArray>> {
+        if index_end <= index_start {
+            bail!(
+                "invalid entry index: start={} end={}",
+                index_start,
+                index_end
+            );
+        }
+        let mut data = Vec::new();
+        for (start_entry_index, end_entry_index) in
+            batch_iter_lazy(index_start, index_end, self.batch_size)
+        {
+            let store = try_option!(self.owner(start_entry_index / self.batch_size as u64));
+            let entries = try_option!(store.get_entries(start_entry_index, end_entry_index)?);
+            data.extend_from_slice(&entries.data);
+        }
+        Ok(Some(ChunkArray {
+            data,
+            start_index: index_start,
+        }))
+    }
+
+    /// Truncate every store, since the first batches at or after `start_index` may be owned by
+    /// any of them. Stop at the first failed store, which can be truncated again later.
+    pub fn truncate(&self, start_index: u64) -> Result<()> {
+        for store in self.stores.values() {
+            store.truncate(start_index)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShardedFlowStore;
+    use crate::config::ShardConfig;
+    use crate::log_store::flow_store::{FlowConfig, FlowDBStore, FlowStore};
+    use crate::log_store::log_manager::COL_NUM;
+    use parking_lot::RwLock;
+    use shared_types::ChunkArray;
+    use std::sync::Arc;
+    use zgs_spec::{BYTES_PER_LOAD, BYTES_PER_SECTOR, SECTORS_PER_LOAD};
+
+    #[test]
+    fn test_sharded_flow_store() {
+        let flow_db = Arc::new(FlowDBStore::new(Arc::new(kvdb_memorydb::create(COL_NUM))));
+        let create_shard_store = |shard_id| {
+            let data_db = Arc::new(FlowDBStore::new(Arc::new(kvdb_memorydb::create(COL_NUM))));
+            let config = FlowConfig {
+                shard_config: Arc::new(RwLock::new(ShardConfig::new(shard_id, 2).unwrap())),
+                ..Default::default()
+            };
+            (
+                FlowStore::new(flow_db.clone(), data_db.clone(), config),
+                data_db,
+            )
+        };
+        let (store_0, data_db_0) = create_shard_store(0);
+        let (store_1, data_db_1) = create_shard_store(1);
+        let sharded = ShardedFlowStore::new(vec![store_0, store_1], 2).unwrap();
+
+        let data: Vec<u8> = (0..4 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+        let start_index = SECTORS_PER_LOAD as u64;
+        let completed = sharded
+            .append_entries(ChunkArray {
+                data: data.clone(),
+                start_index,
+            })
+            .unwrap();
+        assert_eq!(completed.len(), 4);
+        for batch_index in 1..5 {
+            let owned_by_0 = batch_index % 2 == 0;
+            assert_eq!(
+                data_db_0
+                    .get_entry_batch_raw(batch_index)
+                    .unwrap()
+                    .is_some(),
+                owned_by_0
+            );
+            assert_eq!(
+                data_db_1
+                    .get_entry_batch_raw(batch_index)
+                    .unwrap()
+                    .is_some(),
+                !owned_by_0
+            );
+        }
+
+        // A read across the shards is served by both stores.
+        let read_start = start_index + 10;
+        let read_end = start_index + 3 * SECTORS_PER_LOAD as u64 + 5;
+        let entries = sharded.get_entries(read_start, read_end).unwrap().unwrap();
+        assert_eq!(entries.start_index, read_start);
+        assert_eq!(
+            entries.data,
+            data[10 * BYTES_PER_SECTOR..(3 * SECTORS_PER_LOAD + 5) * BYTES_PER_SECTOR]
+        );
+
+        // Batch 2 is truncated in the store of shard 0, and the later ones in both stores.
+        sharded.truncate(2 * SECTORS_PER_LOAD as u64 + 10).unwrap();
+        assert!(data_db_0.get_entry_batch_raw(2).unwrap().is_some());
+        assert!(data_db_0.get_entry_batch_raw(4).unwrap().is_none());
+        assert!(data_db_1.get_entry_batch_raw(1).unwrap().is_some());
+        assert!(data_db_1.get_entry_batch_raw(3).unwrap().is_none());
+        assert!(sharded
+            .get_entries(start_index, 2 * SECTORS_PER_LOAD as u64 + 10)
+            .unwrap()
+            .is_some());
+        assert!(sharded
+            .get_entries(start_index, 2 * SECTORS_PER_LOAD as u64 + 11)
+            .unwrap()
+            .is_none());
+
+        assert!(
+            ShardedFlowStore::new(vec![create_shard_store(0).0, create_shard_store(0).0], 1)
+                .is_err()
+        );
+    }
+}
@@ -1,13 +1,23 @@
+use crate::config::ShardConfig;
+use crate::error::Error;
+use crate::log_store::flow_store::{FlowConfig, MerkleNodeCacheCapacity, PadPair};
 use crate::log_store::log_manager::{
     data_to_merkle_leaves, sub_merkle_tree, tx_subtree_root_list_padded, LogConfig, LogManager,
     PORA_CHUNK_SIZE,
 };
-use crate::log_store::{LogStoreChunkRead, LogStoreChunkWrite, LogStoreRead, LogStoreWrite};
+use crate::log_store::op_log::replay;
+use crate::log_store::{
+    FlowRead, FlowSeal, FlowWrite, LogStoreChunkRead, LogStoreChunkWrite, LogStoreRead,
+    LogStoreWrite, SealAnswer, SealTask,
+};
 use append_merkle::{Algorithm, AppendMerkleTree, MerkleTreeRead, Sha3Algorithm};
 use ethereum_types::H256;
+use futures::StreamExt;
 use rand::random;
 use shared_types::{compute_padded_chunk_size, ChunkArray, Transaction, CHUNK_SIZE};
 use std::cmp;
+use std::time::Duration;
+use zgs_spec::{BYTES_PER_LOAD, SEALS_PER_LOAD, SECTORS_PER_SEAL};
 
 #[test]
 fn test_put_get() {
@@ -167,6 +177,1547 @@ fn test_put_tx() {
     }
 }
 
+#[test]
+fn test_zero_merkle_node_cache() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            merkle_node_cache_capacity: MerkleNodeCacheCapacity::Nodes(0),
+            ..Default::default()
+        },
+    };
+    let mut store = LogManager::memorydb(config).unwrap();
+    let chunk_count = PORA_CHUNK_SIZE * 3;
+    put_tx(&mut store, chunk_count, 0);
+    let tx = store.get_tx_by_seq_number(0).unwrap().unwrap();
+    let (flow_root, _) = store.get_context().unwrap();
+
+    for i in (0..chunk_count).step_by(PORA_CHUNK_SIZE / 4) {
+        let chunk_with_proof = store
+            .get_chunk_with_proof_by_tx_and_index(tx.seq, i)
+            .unwrap()
+            .unwrap();
+        let r = chunk_with_proof.proof.validate::<Sha3Algorithm>(
+            &Sha3Algorithm::leaf(&chunk_with_proof.chunk.0),
+            tx.start_entry_index as usize + i,
+        );
+        assert!(r.is_ok(), "proof={:?} \n r={:?}", chunk_with_proof.proof, r);
+        assert_eq!(chunk_with_proof.proof.root(), flow_root);
+    }
+
+    // The batches of the tx are complete, so their proofs are built from the flow data.
+    let first_batch = tx.start_entry_index as usize / PORA_CHUNK_SIZE;
+    for batch_index in first_batch..first_batch + chunk_count / PORA_CHUNK_SIZE {
+        let proof = store
+            .flow_store()
+            .gen_proof_in_batch(batch_index, PORA_CHUNK_SIZE / 2)
+            .unwrap();
+        assert_eq!(proof.position(), PORA_CHUNK_SIZE / 2);
+    }
+}
+
+#[test]
+fn test_seal_backlog_full() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            seal_backlog_high_water_mark: Some(SEALS_PER_LOAD),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let backlog = store.get_seal_backlog();
+    assert_eq!(backlog.backlog, SEALS_PER_LOAD);
+    assert_eq!(backlog.high_water_mark, Some(SEALS_PER_LOAD));
+
+    let err = flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: 2 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::SealBacklogFull { .. })
+    ));
+}
+
+#[test]
+fn test_seal_backlog_full_before_merkle_update() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            seal_backlog_high_water_mark: Some(SEALS_PER_LOAD),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    store
+        .flow_store()
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: 16 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+
+    let data = vec![1u8; CHUNK_SIZE];
+    let merkle_nodes = tx_subtree_root_list_padded(&data);
+    let tx = Transaction {
+        stream_ids: vec![],
+        size: data.len() as u64,
+        data_merkle_root: sub_merkle_tree(&data).unwrap().root().into(),
+        seq: 0,
+        data: vec![],
+        start_entry_index: store.get_context().unwrap().1,
+        merkle_nodes,
+    };
+    store.put_tx(tx).unwrap();
+    let context = store.get_context().unwrap();
+
+    // The throttled chunks do not reach the merkle tree.
+    let err = store
+        .put_chunks(
+            0,
+            ChunkArray {
+                data,
+                start_index: 0,
+            },
+        )
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::SealBacklogFull { .. })
+    ));
+    assert_eq!(store.get_context().unwrap(), context);
+}
+
+#[test]
+fn test_partial_proof_in_batch() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let batch_index = 1;
+    let start_sector = PORA_CHUNK_SIZE / 4;
+    let num_sectors = PORA_CHUNK_SIZE / 4;
+    let mut data = vec![0u8; num_sectors * CHUNK_SIZE];
+    for i in 0..num_sectors {
+        data[i * CHUNK_SIZE] = random();
+    }
+    flow_store
+        .append_entries(ChunkArray {
+            data: data.clone(),
+            start_index: (batch_index * PORA_CHUNK_SIZE + start_sector) as u64,
+        })
+        .unwrap();
+
+    assert!(flow_store
+        .gen_proof_in_batch(batch_index, start_sector)
+        .is_err());
+    for i in [0, num_sectors / 2, num_sectors - 1] {
+        let partial = flow_store
+            .gen_partial_proof_in_batch(batch_index, start_sector + i)
+            .unwrap();
+        let leaf = Sha3Algorithm::leaf(&data[i * CHUNK_SIZE..(i + 1) * CHUNK_SIZE]);
+        assert!(partial
+            .proof
+            .validate::<Sha3Algorithm>(&leaf, start_sector + i)
+            .is_ok());
+        assert_eq!(partial.proof.root(), partial.partial_root);
+    }
+    // Sectors that are not present cannot be proved.
+    assert!(flow_store
+        .gen_partial_proof_in_batch(batch_index, start_sector + num_sectors)
+        .is_err());
+}
+
+#[test]
+fn test_small_batch_size() {
+    let batch_size = PORA_CHUNK_SIZE / 4;
+    let config = LogConfig {
+        flow: FlowConfig {
+            batch_size,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let num_batches = 3;
+    let mut data = vec![0u8; num_batches * batch_size * CHUNK_SIZE];
+    for i in 0..num_batches * batch_size {
+        data[i * CHUNK_SIZE] = random();
+    }
+    let chunks = ChunkArray {
+        data,
+        start_index: batch_size as u64,
+    };
+    flow_store.append_entries(chunks.clone()).unwrap();
+
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        (num_batches * batch_size) as u64
+    );
+    let end_index = ((num_batches + 1) * batch_size) as u64;
+    assert_eq!(
+        flow_store
+            .get_entries(batch_size as u64, end_index)
+            .unwrap()
+            .unwrap(),
+        chunks
+    );
+    let start_index = batch_size as u64 + 3;
+    assert_eq!(
+        flow_store
+            .get_entries(start_index, end_index - 5)
+            .unwrap()
+            .unwrap(),
+        chunks.sub_array(start_index, end_index - 5).unwrap()
+    );
+}
+
+#[test]
+fn test_delete_sector_ranges() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let start_index = PORA_CHUNK_SIZE as u64;
+    let data = (0..3 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    let chunks = ChunkArray { data, start_index };
+    flow_store.append_entries(chunks.clone()).unwrap();
+
+    let batch_size = PORA_CHUNK_SIZE as u64;
+    let cleared = flow_store
+        .delete_sector_ranges(&[
+            (start_index + 10, start_index + 20),
+            (start_index + batch_size, start_index + 2 * batch_size),
+            (start_index + 15, start_index + 30),
+        ])
+        .unwrap();
+    assert_eq!(cleared, 20 + PORA_CHUNK_SIZE);
+
+    assert!(flow_store
+        .get_entries(start_index + 10, start_index + 11)
+        .unwrap()
+        .is_none());
+    assert!(flow_store
+        .get_entries(start_index + batch_size, start_index + batch_size + 1)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        flow_store
+            .get_entries(start_index, start_index + 10)
+            .unwrap()
+            .unwrap(),
+        chunks.sub_array(start_index, start_index + 10).unwrap()
+    );
+    let third_batch = start_index + 2 * batch_size;
+    assert_eq!(
+        flow_store
+            .get_entries(third_batch, third_batch + batch_size)
+            .unwrap()
+            .unwrap(),
+        chunks
+            .sub_array(third_batch, third_batch + batch_size)
+            .unwrap()
+    );
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        2 * PORA_CHUNK_SIZE as u64
+    );
+
+    // The cleared seals must not be returned as seal tasks.
+    while let Some(tasks) = flow_store.pull_seal_chunk(usize::MAX).unwrap() {
+        let answers = tasks
+            .into_iter()
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: H256::zero(),
+                seal_context: H256::zero(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        flow_store.submit_seal_result(answers).unwrap();
+    }
+}
+
+#[test]
+fn test_delete_batch_list_in_chunks() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            delete_batch_chunk_size: 2,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let num_batches = 5;
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; num_batches * BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        (num_batches * PORA_CHUNK_SIZE) as u64
+    );
+
+    let batch_list: Vec<u64> = (1..=num_batches as u64).collect();
+    flow_store.delete_batch_list(&batch_list).unwrap();
+    assert_eq!(flow_store.get_num_entries().unwrap(), 0);
+    // Deleting again is a no-op.
+    flow_store.delete_batch_list(&batch_list).unwrap();
+}
+
+#[test]
+fn test_sealed_data_cache() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            sealed_data_cache_capacity: 2,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+    let batch_index = 1;
+    let start_index = batch_index * PORA_CHUNK_SIZE as u64;
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![7u8; BYTES_PER_LOAD],
+            start_index,
+        })
+        .unwrap();
+
+    let mine_chunk = flow_store.load_sealed_data(batch_index).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| !x));
+    // Served from the cache.
+    let mine_chunk = flow_store.load_sealed_data(batch_index).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| !x));
+
+    // Sealing invalidates the cached batch.
+    while let Some(tasks) = flow_store.pull_seal_chunk(usize::MAX).unwrap() {
+        let answers = tasks
+            .into_iter()
+            .map(|task| SealAnswer {
+                seal_index: task.seal_index,
+                version: task.version,
+                sealed_data: task.non_sealed_data,
+                miner_id: H256::zero(),
+                seal_context: H256::zero(),
+                context_end_seal: task.seal_index + 1,
+            })
+            .collect();
+        flow_store.submit_seal_result(answers).unwrap();
+    }
+    let mine_chunk = flow_store.load_sealed_data(batch_index).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| *x));
+    assert!(mine_chunk
+        .loaded_chunk
+        .iter()
+        .all(|seal| seal.iter().all(|x| *x == 7)));
+
+    // Truncation drops the whole cache.
+    flow_store.truncate(start_index).unwrap();
+    assert!(flow_store.load_sealed_data(batch_index).unwrap().is_none());
+}
+
+#[test]
+fn test_longest_present_run() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    assert_eq!(flow_store.longest_present_run(0).unwrap(), (0, 0));
+    // Store the batches 1..3 and 4..8.
+    for (start_batch, end_batch) in [(1, 3), (4, 8)] {
+        flow_store
+            .append_entries(ChunkArray {
+                data: vec![1u8; (end_batch - start_batch) as usize * BYTES_PER_LOAD],
+                start_index: start_batch * PORA_CHUNK_SIZE as u64,
+            })
+            .unwrap();
+    }
+    assert_eq!(flow_store.longest_present_run(0).unwrap(), (4, 8));
+    assert_eq!(flow_store.longest_present_run(6).unwrap(), (6, 8));
+    assert_eq!(flow_store.longest_present_run(8).unwrap(), (8, 8));
+
+    flow_store.delete_batch_list(&[5]).unwrap();
+    assert_eq!(flow_store.longest_present_run(0).unwrap(), (1, 3));
+    assert_eq!(flow_store.longest_present_run(2).unwrap(), (6, 8));
+}
+
+#[test]
+fn test_get_entries_at_version() {
+    let mut store = create_store();
+    put_tx(&mut store, PORA_CHUNK_SIZE, 0);
+    let flow_length = store.get_context().unwrap().1;
+    let tx = store.get_tx_by_seq_number(0).unwrap().unwrap();
+    let (start, end) = (tx.start_entry_index, tx.start_entry_index + 4);
+    assert_eq!(
+        store
+            .get_entries_at_version(start, end, flow_length)
+            .unwrap(),
+        store.get_chunk_by_flow_index(start, end - start).unwrap()
+    );
+    assert!(store
+        .get_entries_at_version(start, end, 0)
+        .unwrap()
+        .is_some());
+
+    let err = store
+        .get_entries_at_version(start, end, flow_length + 1)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::Stale { flow_length: len, min_flow_length })
+            if *len == flow_length && *min_flow_length == flow_length + 1
+    ));
+}
+
+#[test]
+fn test_append_duplicate_batch() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let chunks = ChunkArray {
+        data: (0..2 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+        start_index: PORA_CHUNK_SIZE as u64,
+    };
+    let roots = flow_store.append_entries(chunks.clone()).unwrap();
+    assert_eq!(roots.len(), 2);
+
+    // The duplicate batches are not written again, but their roots are returned.
+    assert_eq!(flow_store.append_entries(chunks.clone()).unwrap(), roots);
+    let mut overlapped = chunks
+        .sub_array(2 * PORA_CHUNK_SIZE as u64, 3 * PORA_CHUNK_SIZE as u64)
+        .unwrap();
+    overlapped.data.extend(vec![1u8; BYTES_PER_LOAD]);
+    let roots = flow_store.append_entries(overlapped).unwrap();
+    assert_eq!(roots.len(), 2);
+    assert_eq!(roots[0].0, 2);
+    assert_eq!(roots[1].0, 3);
+
+    // Conflicting data are still rejected.
+    let mut conflict = chunks
+        .sub_array(PORA_CHUNK_SIZE as u64, PORA_CHUNK_SIZE as u64 + 1)
+        .unwrap();
+    conflict.data[0] ^= 1;
+    assert!(flow_store.append_entries(conflict).is_err());
+    assert_eq!(
+        flow_store
+            .get_entries(PORA_CHUNK_SIZE as u64, 3 * PORA_CHUNK_SIZE as u64)
+            .unwrap()
+            .unwrap(),
+        chunks
+    );
+}
+
+#[tokio::test]
+async fn test_seal_task_stream() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+    let append_batch = |batch_index: u64| {
+        flow_store
+            .append_entries(ChunkArray {
+                data: vec![5u8; BYTES_PER_LOAD],
+                start_index: batch_index * PORA_CHUNK_SIZE as u64,
+            })
+            .unwrap();
+    };
+    append_batch(1);
+
+    let stream = flow_store.seal_task_stream(usize::MAX);
+    futures::pin_mut!(stream);
+    let mut seal_indices = Vec::new();
+    for _ in 0..SEALS_PER_LOAD {
+        seal_indices.push(stream.next().await.unwrap().seal_index);
+    }
+    assert_eq!(
+        seal_indices,
+        (SEALS_PER_LOAD as u64..2 * SEALS_PER_LOAD as u64).collect::<Vec<_>>()
+    );
+
+    // The leased tasks are not yielded again, even by another stream.
+    let other = flow_store.seal_task_stream(usize::MAX);
+    futures::pin_mut!(other);
+    assert!(
+        tokio::time::timeout(Duration::from_millis(100), other.next())
+            .await
+            .is_err()
+    );
+
+    // The stream wakes up when new tasks are added.
+    let (task, _) = tokio::join!(stream.next(), async {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        append_batch(2);
+    });
+    assert_eq!(task.unwrap().seal_index, 2 * SEALS_PER_LOAD as u64);
+}
+
+#[test]
+fn test_append_in_multiple_transactions() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            max_batches_per_append: Some(2),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let num_batches = 5;
+    let chunks = ChunkArray {
+        data: (0..num_batches * BYTES_PER_LOAD)
+            .map(|i| (i % 251) as u8)
+            .collect(),
+        start_index: PORA_CHUNK_SIZE as u64,
+    };
+    let roots = flow_store.append_entries(chunks.clone()).unwrap();
+    assert_eq!(
+        roots.iter().map(|(index, _)| *index).collect::<Vec<_>>(),
+        (1..=num_batches as u64).collect::<Vec<_>>()
+    );
+    assert_eq!(
+        flow_store
+            .get_entries(
+                PORA_CHUNK_SIZE as u64,
+                ((num_batches + 1) * PORA_CHUNK_SIZE) as u64
+            )
+            .unwrap()
+            .unwrap(),
+        chunks
+    );
+}
+
+#[test]
+fn test_batch_index_of_root() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    // The batches 1 and 3 have the same data, so they share a root.
+    let mut data = vec![1u8; 3 * BYTES_PER_LOAD];
+    data[BYTES_PER_LOAD..2 * BYTES_PER_LOAD].fill(2);
+    let roots = flow_store
+        .append_entries(ChunkArray {
+            data,
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert_eq!(roots.len(), 3);
+    assert_eq!(roots[0].1, roots[2].1);
+    assert_eq!(
+        flow_store.batch_index_of_root(&roots[0].1).unwrap(),
+        Some(1)
+    );
+    assert_eq!(
+        flow_store.batch_index_of_root(&roots[1].1).unwrap(),
+        Some(2)
+    );
+    assert_eq!(
+        flow_store
+            .batch_index_of_root(&H256::repeat_byte(0xff))
+            .unwrap(),
+        None
+    );
+
+    // The index does not return the removed batches.
+    flow_store.delete_batch_list(&[1]).unwrap();
+    assert_eq!(
+        flow_store.batch_index_of_root(&roots[0].1).unwrap(),
+        Some(3)
+    );
+    flow_store.truncate(2 * PORA_CHUNK_SIZE as u64).unwrap();
+    assert_eq!(flow_store.batch_index_of_root(&roots[0].1).unwrap(), None);
+    assert_eq!(flow_store.batch_index_of_root(&roots[1].1).unwrap(), None);
+}
+
+#[test]
+fn test_coalesce_small_appends() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            coalesce_small_appends: true,
+            append_buffer_flush_interval: Duration::from_secs(3600),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let half = PORA_CHUNK_SIZE as u64 / 2;
+    let chunks = ChunkArray {
+        data: (0..BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+        start_index: PORA_CHUNK_SIZE as u64,
+    };
+
+    // The incomplete batch is buffered but readable.
+    let first_half = chunks
+        .sub_array(PORA_CHUNK_SIZE as u64, PORA_CHUNK_SIZE as u64 + half)
+        .unwrap();
+    assert!(flow_store
+        .append_entries(first_half.clone())
+        .unwrap()
+        .is_empty());
+    assert_eq!(flow_store.get_num_entries().unwrap(), 0);
+    assert_eq!(
+        flow_store
+            .get_entries(PORA_CHUNK_SIZE as u64, PORA_CHUNK_SIZE as u64 + half)
+            .unwrap()
+            .unwrap(),
+        first_half
+    );
+
+    // The batch is written once it is complete.
+    let second_half = chunks
+        .sub_array(PORA_CHUNK_SIZE as u64 + half, 2 * PORA_CHUNK_SIZE as u64)
+        .unwrap();
+    let roots = flow_store.append_entries(second_half).unwrap();
+    assert_eq!(roots.len(), 1);
+    assert_eq!(roots[0].0, 1);
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        PORA_CHUNK_SIZE as u64
+    );
+    assert_eq!(
+        flow_store
+            .get_entries(PORA_CHUNK_SIZE as u64, 2 * PORA_CHUNK_SIZE as u64)
+            .unwrap()
+            .unwrap(),
+        chunks
+    );
+
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD / 4],
+            start_index: 2 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        PORA_CHUNK_SIZE as u64
+    );
+    flow_store.flush_append_buffer().unwrap();
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        2 * PORA_CHUNK_SIZE as u64
+    );
+}
+
+#[test]
+fn test_gen_tx_root_proof() {
+    let mut store = create_store();
+    put_tx(&mut store, PORA_CHUNK_SIZE, 0);
+    put_tx(&mut store, PORA_CHUNK_SIZE / 4 * 3, 1);
+    put_tx(&mut store, PORA_CHUNK_SIZE / 4, 2);
+    let flow_root = store.get_context().unwrap().0;
+
+    for tx_seq in [0, 2] {
+        let tx = store.get_tx_by_seq_number(tx_seq).unwrap().unwrap();
+        let (root, proof) = store.gen_tx_root_proof(tx_seq).unwrap();
+        assert_eq!(root, tx.data_merkle_root);
+        assert_eq!(proof.item(), root);
+        assert_eq!(proof.root(), flow_root);
+        let height = tx.merkle_nodes[0].0 - 1;
+        proof
+            .validate::<Sha3Algorithm>(&root, (tx.start_entry_index >> height) as usize)
+            .unwrap();
+    }
+
+    // The tx is stored as two subtrees.
+    assert!(store.gen_tx_root_proof(1).is_err());
+    assert!(store.gen_tx_root_proof(3).is_err());
+}
+
+#[test]
+fn test_stored_entries_in_shard() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    assert_eq!(flow_store.stored_entries_in_shard().unwrap(), 0);
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD + BYTES_PER_LOAD / 2],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert_eq!(
+        flow_store.stored_entries_in_shard().unwrap(),
+        (PORA_CHUNK_SIZE + PORA_CHUNK_SIZE / 2) as u64
+    );
+    assert_eq!(
+        flow_store.get_num_entries().unwrap(),
+        2 * PORA_CHUNK_SIZE as u64
+    );
+}
+
+#[test]
+fn test_truncate_soft_restores_seals() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            seal_quarantine_window: Some(Duration::from_secs(3600)),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let seal_all = || {
+        while let Some(tasks) = flow_store.pull_seal_chunk(usize::MAX).unwrap() {
+            let answers = tasks
+                .into_iter()
+                .map(|task| SealAnswer {
+                    seal_index: task.seal_index,
+                    version: task.version,
+                    sealed_data: task.non_sealed_data,
+                    miner_id: H256::zero(),
+                    seal_context: H256::zero(),
+                    context_end_seal: task.seal_index + 1,
+                })
+                .collect();
+            flow_store.submit_seal_result(answers).unwrap();
+        }
+    };
+    let batch_start = 2 * PORA_CHUNK_SIZE as u64;
+    let batch = ChunkArray {
+        data: vec![3u8; BYTES_PER_LOAD],
+        start_index: batch_start,
+    };
+    // Mark the seal worker as available so the appended seals are queued.
+    seal_all();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![2u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    flow_store.append_entries(batch.clone()).unwrap();
+    seal_all();
+
+    // The same data appended again reuse the seals.
+    flow_store.truncate_soft(batch_start).unwrap();
+    assert!(flow_store
+        .get_entries(batch_start, batch_start + 1)
+        .unwrap()
+        .is_none());
+    flow_store.append_entries(batch.clone()).unwrap();
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+    let mine_chunk = flow_store.load_sealed_data(2).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| *x));
+    assert_eq!(
+        flow_store
+            .get_entries(batch_start, batch_start + PORA_CHUNK_SIZE as u64)
+            .unwrap()
+            .unwrap(),
+        batch
+    );
+
+    // Different data are sealed again.
+    flow_store.truncate_soft(batch_start).unwrap();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![4u8; BYTES_PER_LOAD],
+            start_index: batch_start,
+        })
+        .unwrap();
+    let mine_chunk = flow_store.load_sealed_data(2).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| !x));
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_some());
+}
+
+#[test]
+fn test_completed_roots() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let mut roots = flow_store
+        .append_entries(ChunkArray {
+            data: (0..2 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    // The incomplete batch 3 is skipped.
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD / 2],
+            start_index: 3 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    roots.extend(
+        flow_store
+            .append_entries(ChunkArray {
+                data: vec![2u8; BYTES_PER_LOAD],
+                start_index: 5 * PORA_CHUNK_SIZE as u64,
+            })
+            .unwrap(),
+    );
+    assert_eq!(roots.len(), 3);
+
+    assert_eq!(flow_store.completed_roots(0, 8).unwrap(), roots);
+    assert_eq!(flow_store.completed_roots(2, 5).unwrap(), roots[1..2]);
+    assert!(flow_store.completed_roots(3, 5).unwrap().is_empty());
+}
+
+#[test]
+fn test_flow_health() {
+    let store = create_store();
+    let health = store.get_flow_health().unwrap();
+    assert_eq!(health.num_entries, Some(0));
+    assert_eq!(health.incomplete_tail_batches, None);
+    assert_eq!(health.last_append_succeeded, None);
+    assert!(health.errors.is_empty());
+
+    let flow_store = store.flow_store();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD + BYTES_PER_LOAD / 2],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    flow_store.put_pad_data_sync_height(3).unwrap();
+    let health = store.get_flow_health().unwrap();
+    assert_eq!(health.num_entries, Some(2 * PORA_CHUNK_SIZE as u64));
+    assert_eq!(health.incomplete_tail_batches, Some(1));
+    assert_eq!(health.pad_data_sync_height, Some(3));
+    assert_eq!(health.last_append_succeeded, Some(true));
+
+    assert!(flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; 1],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .is_err());
+    assert_eq!(
+        store.get_flow_health().unwrap().last_append_succeeded,
+        Some(false)
+    );
+}
+
+#[test]
+fn test_export_import_shard() {
+    let source = create_store();
+    let source_flow = source.flow_store();
+    let data: Vec<u8> = (0..4 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    let roots = source_flow
+        .append_entries(ChunkArray {
+            data,
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let shard = ShardConfig::new(1, 2).unwrap();
+    let mut exported = Vec::new();
+    assert_eq!(source_flow.export_shard(shard, &mut exported).unwrap(), 2);
+
+    let target = create_store();
+    let target_flow = target.flow_store();
+    target_flow.update_shard_config(shard);
+    assert_eq!(target_flow.import_shard(exported.as_slice()).unwrap(), 2);
+    assert_eq!(
+        target_flow.completed_roots(0, 8).unwrap(),
+        vec![roots[0], roots[2]]
+    );
+    for batch_index in [1, 3] {
+        let start = batch_index * PORA_CHUNK_SIZE as u64;
+        assert_eq!(
+            target_flow
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap(),
+            source_flow
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap()
+        );
+    }
+
+    // The batches out of our shard range are rejected.
+    let other = create_store();
+    other
+        .flow_store()
+        .update_shard_config(ShardConfig::new(0, 2).unwrap());
+    assert!(other
+        .flow_store()
+        .import_shard(exported.as_slice())
+        .is_err());
+    // A truncated or corrupted export is detected.
+    let target = create_store();
+    assert!(target
+        .flow_store()
+        .import_shard(&exported[..exported.len() - 1])
+        .is_err());
+    let mut corrupted = exported.clone();
+    corrupted[20] ^= 1;
+    assert!(target
+        .flow_store()
+        .import_shard(corrupted.as_slice())
+        .is_err());
+}
+
+#[test]
+fn test_export_complete_shard() {
+    let source = create_store();
+    let source_flow = source.flow_store();
+    // Batches 1-4 are complete and batch 5 is not.
+    let data: Vec<u8> = (0..4 * BYTES_PER_LOAD + BYTES_PER_LOAD / 2)
+        .map(|i| (i % 251) as u8)
+        .collect();
+    let roots = source_flow
+        .append_entries(ChunkArray {
+            data,
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let shard = ShardConfig::new(1, 2).unwrap();
+    assert_eq!(source_flow.export_shard(shard, &mut Vec::new()).unwrap(), 3);
+    let mut exported = Vec::new();
+    assert_eq!(
+        source_flow
+            .export_complete_shard(shard, &mut exported)
+            .unwrap(),
+        2
+    );
+
+    let target = create_store();
+    let target_flow = target.flow_store();
+    target_flow.update_shard_config(shard);
+    assert_eq!(target_flow.import_shard(exported.as_slice()).unwrap(), 2);
+    assert_eq!(
+        target_flow.completed_roots(0, 8).unwrap(),
+        vec![roots[0], roots[2]]
+    );
+
+    // A batch that does not match its root is rejected before it is appended.
+    let mut corrupted = exported.clone();
+    corrupted[9] ^= 1;
+    let target = create_store();
+    target.flow_store().update_shard_config(shard);
+    let err = target
+        .flow_store()
+        .import_shard(corrupted.as_slice())
+        .unwrap_err();
+    assert!(err.to_string().contains("root mismatch"));
+    assert!(target
+        .flow_store()
+        .completed_roots(0, 8)
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn test_validate_archive() {
+    let source = create_store();
+    let data: Vec<u8> = (0..4 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    source
+        .flow_store()
+        .append_entries(ChunkArray {
+            data,
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let shard = ShardConfig::new(1, 2).unwrap();
+    let mut exported = Vec::new();
+    source
+        .flow_store()
+        .export_complete_shard(shard, &mut exported)
+        .unwrap();
+
+    let target = create_store();
+    let target_flow = target.flow_store();
+    target_flow.update_shard_config(shard);
+    let report = target_flow.validate_archive(exported.as_slice()).unwrap();
+    assert!(report.is_valid());
+    assert_eq!(report.batches, 2);
+    assert_eq!(report.checked_roots, 2);
+
+    // The root of batch 1 is corrupted, which also breaks the checksum.
+    let mut corrupted = exported.clone();
+    corrupted[9] ^= 1;
+    let report = target_flow.validate_archive(corrupted.as_slice()).unwrap();
+    assert!(!report.is_valid());
+    assert_eq!(report.batches, 2);
+    assert_eq!(report.root_mismatches, vec![1]);
+    assert!(report.decode_failures.is_empty());
+    assert!(!report.trailer_valid);
+    assert!(report.framing_error.is_none());
+
+    let report = target_flow
+        .validate_archive(&exported[..exported.len() - 1])
+        .unwrap();
+    assert!(report.framing_error.is_some());
+    assert!(!report.trailer_valid);
+
+    let other = create_store();
+    other
+        .flow_store()
+        .update_shard_config(ShardConfig::new(0, 2).unwrap());
+    let report = other
+        .flow_store()
+        .validate_archive(exported.as_slice())
+        .unwrap();
+    assert_eq!(report.out_of_shard, vec![1, 3]);
+
+    // Nothing is written by the validation.
+    assert!(target_flow.completed_roots(0, 8).unwrap().is_empty());
+}
+
+#[test]
+fn test_get_entries_strided() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let data: Vec<u8> = (0..2 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    let batch_start = PORA_CHUNK_SIZE as u64;
+    flow_store
+        .append_entries(ChunkArray {
+            data: data.clone(),
+            start_index: batch_start,
+        })
+        .unwrap();
+
+    let entries = flow_store
+        .get_entries_strided(
+            batch_start + 3,
+            batch_start + 2 * PORA_CHUNK_SIZE as u64,
+            100,
+        )
+        .unwrap()
+        .unwrap();
+    assert_eq!(entries.len(), 21);
+    for (sector_index, sector) in entries {
+        let offset = (sector_index - batch_start) as usize * CHUNK_SIZE;
+        assert_eq!((sector_index - batch_start - 3) % 100, 0);
+        assert_eq!(sector, data[offset..offset + CHUNK_SIZE]);
+    }
+    // A missing sector fails the whole read.
+    assert!(flow_store
+        .get_entries_strided(batch_start, batch_start + 3 * PORA_CHUNK_SIZE as u64, 512)
+        .unwrap()
+        .is_none());
+    assert!(flow_store
+        .get_entries_strided(batch_start, batch_start + 1, 0)
+        .is_err());
+}
+
+#[test]
+fn test_pull_seal_chunk_across_load_chunks() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            max_seal_load_chunks_per_pull: 2,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; 3 * BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+
+    let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+    assert_eq!(tasks.len(), 2 * SEALS_PER_LOAD);
+    assert_eq!(tasks[0].seal_index, SEALS_PER_LOAD as u64);
+    assert_eq!(
+        tasks.last().unwrap().seal_index,
+        3 * SEALS_PER_LOAD as u64 - 1
+    );
+    // The cutoff still applies.
+    let seal_index_max = 2 * SEALS_PER_LOAD + 3;
+    let tasks = flow_store.pull_seal_chunk(seal_index_max).unwrap().unwrap();
+    assert_eq!(tasks.len(), SEALS_PER_LOAD + 3);
+}
+
+#[test]
+fn test_replay_operation_log() {
+    let log_path = std::env::temp_dir().join(format!("zgs_operation_log_{}", random::<u64>()));
+    let config = LogConfig {
+        flow: FlowConfig {
+            operation_log_path: Some(log_path.clone()),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+    flow_store
+        .append_entries(ChunkArray {
+            data: (0..3 * BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+    flow_store
+        .submit_seal_result(
+            tasks
+                .into_iter()
+                .map(|task| SealAnswer {
+                    seal_index: task.seal_index,
+                    version: task.version,
+                    sealed_data: task.non_sealed_data,
+                    miner_id: H256::repeat_byte(1),
+                    seal_context: H256::repeat_byte(2),
+                    context_end_seal: task.seal_index + 1,
+                })
+                .collect(),
+        )
+        .unwrap();
+    flow_store
+        .put_pad_data(
+            &[PadPair {
+                start_index: 1,
+                data_size: 2,
+            }],
+            5,
+        )
+        .unwrap();
+    flow_store.truncate(3 * PORA_CHUNK_SIZE as u64).unwrap();
+
+    let replayed = create_store();
+    assert_eq!(replay(&log_path, replayed.flow_store()).unwrap(), 4);
+    std::fs::remove_file(&log_path).unwrap();
+    let replayed_flow = replayed.flow_store();
+    for batch_index in 1..4 {
+        let start = batch_index * PORA_CHUNK_SIZE as u64;
+        assert_eq!(
+            replayed_flow
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap(),
+            flow_store
+                .get_entries(start, start + PORA_CHUNK_SIZE as u64)
+                .unwrap()
+        );
+        let replayed_chunk = replayed_flow.load_sealed_data(batch_index).unwrap();
+        let chunk = flow_store.load_sealed_data(batch_index).unwrap();
+        assert_eq!(replayed_chunk.is_some(), chunk.is_some());
+        if let (Some(replayed_chunk), Some(chunk)) = (replayed_chunk, chunk) {
+            assert_eq!(replayed_chunk.availabilities, chunk.availabilities);
+            assert!(replayed_chunk.loaded_chunk == chunk.loaded_chunk);
+        }
+    }
+    assert_eq!(
+        replayed_flow.get_pad_data(5).unwrap(),
+        flow_store.get_pad_data(5).unwrap()
+    );
+}
+
+#[test]
+fn test_get_sealed_seal() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    flow_store.pull_seal_chunk(usize::MAX).unwrap();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert!(flow_store.get_sealed_seal(1, 0).unwrap().is_none());
+    assert!(flow_store.get_sealed_seal(2, 0).unwrap().is_none());
+    assert!(flow_store
+        .get_sealed_seal(1, SEALS_PER_LOAD as u16)
+        .is_err());
+
+    let task = flow_store
+        .pull_seal_chunk(usize::MAX)
+        .unwrap()
+        .unwrap()
+        .remove(0);
+    let seal_index = task.seal_index;
+    let sealed_data = task.non_sealed_data.map(|x| x ^ 0xff);
+    flow_store
+        .submit_seal_result(vec![SealAnswer {
+            seal_index,
+            version: task.version,
+            sealed_data,
+            miner_id: H256::zero(),
+            seal_context: H256::zero(),
+            context_end_seal: seal_index + 1,
+        }])
+        .unwrap();
+    let local_index = (seal_index as usize % SEALS_PER_LOAD) as u16;
+    assert_eq!(
+        flow_store.get_sealed_seal(1, local_index).unwrap().unwrap(),
+        sealed_data.to_vec()
+    );
+}
+
+#[test]
+fn test_sample_batch() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let data: Vec<u8> = (0..BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    let batch_start = PORA_CHUNK_SIZE as u64;
+    let roots = flow_store
+        .append_entries(ChunkArray {
+            data: data.clone(),
+            start_index: batch_start,
+        })
+        .unwrap();
+    let root = roots[0].1;
+
+    let seed = [7u8; 32];
+    let samples = flow_store.sample_batch(1, seed, 16).unwrap();
+    assert_eq!(samples.len(), 16);
+    assert_eq!(samples, flow_store.sample_batch(1, seed, 16).unwrap());
+    let mut indices: Vec<usize> = samples.iter().map(|(index, _, _)| *index).collect();
+    indices.sort_unstable();
+    indices.dedup();
+    assert_eq!(indices.len(), 16);
+    for (sector_index, chunk, proof) in &samples {
+        let offset = sector_index * CHUNK_SIZE;
+        assert_eq!(chunk.data, data[offset..offset + CHUNK_SIZE]);
+        assert_eq!(chunk.start_index, batch_start + *sector_index as u64);
+        assert_eq!(proof.root(), root);
+        proof
+            .validate::<Sha3Algorithm>(&Sha3Algorithm::leaf(&chunk.data), *sector_index)
+            .unwrap();
+    }
+    assert_ne!(samples, flow_store.sample_batch(1, [8u8; 32], 16).unwrap());
+
+    // Incomplete batches cannot be sampled.
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD / 2],
+            start_index: 2 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert!(flow_store.sample_batch(2, seed, 1).is_err());
+    assert!(flow_store.sample_batch(3, seed, 1).is_err());
+}
+
+#[test]
+fn test_get_sector_with_proof() {
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let data: Vec<u8> = (0..BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect();
+    let roots = flow_store
+        .append_entries(ChunkArray {
+            data: data.clone(),
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+
+    let sector_index = 5;
+    let (sector, proof, root) = flow_store
+        .get_sector_with_proof(1, sector_index)
+        .unwrap()
+        .unwrap();
+    let offset = sector_index * CHUNK_SIZE;
+    assert_eq!(sector, data[offset..offset + CHUNK_SIZE]);
+    assert_eq!(root, roots[0].1);
+    assert_eq!(proof.root(), root);
+    proof
+        .validate::<Sha3Algorithm>(&Sha3Algorithm::leaf(&sector), sector_index)
+        .unwrap();
+    assert_eq!(
+        proof,
+        flow_store.gen_proof_in_batch(1, sector_index).unwrap()
+    );
+
+    // Incomplete and absent batches have no proof.
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD / 2],
+            start_index: 2 * PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert!(flow_store.get_sector_with_proof(2, 0).unwrap().is_none());
+    assert!(flow_store.get_sector_with_proof(3, 0).unwrap().is_none());
+}
+
+#[test]
+fn test_submit_seal_result_in_rounds() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            max_seal_answers_per_lock: Some(1),
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let seal_all = || {
+        while let Some(tasks) = flow_store.pull_seal_chunk(usize::MAX).unwrap() {
+            let answers = tasks
+                .into_iter()
+                .map(|task| SealAnswer {
+                    seal_index: task.seal_index,
+                    version: task.version,
+                    sealed_data: task.non_sealed_data,
+                    miner_id: H256::zero(),
+                    seal_context: H256::zero(),
+                    context_end_seal: task.seal_index + 1,
+                })
+                .collect();
+            flow_store.submit_seal_result(answers).unwrap();
+        }
+    };
+    // Mark the seal worker as available so the appended seals are queued.
+    seal_all();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; 3 * BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    assert_eq!(flow_store.seal_throughput(), (0.0, 0));
+    seal_all();
+
+    for load_index in 1..4 {
+        let mine_chunk = flow_store.load_sealed_data(load_index).unwrap().unwrap();
+        assert!(mine_chunk.availabilities.iter().all(|x| *x));
+    }
+    let (rate, total) = flow_store.seal_throughput();
+    assert_eq!(total, 3 * SEALS_PER_LOAD as u64);
+    assert!(rate > 0.0);
+    assert!(flow_store.pull_seal_chunk(usize::MAX).unwrap().is_none());
+}
+
+#[test]
+fn test_seal_verify_sample() {
+    check_seal_verify_sample(0);
+}
+
+#[test]
+fn test_seal_verify_sample_in_pool() {
+    check_seal_verify_sample(4);
+}
+
+fn check_seal_verify_sample(seal_verify_threads: usize) {
+    let config = LogConfig {
+        flow: FlowConfig {
+            seal_verify_sample_rate: 1.0,
+            seal_verify_seed: Some(0),
+            seal_verify_threads,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    flow_store.pull_seal_chunk(usize::MAX).unwrap();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+    let answer = |task: &SealTask, sealed: bool| {
+        let mut sealed_data = task.non_sealed_data;
+        if sealed {
+            zgs_seal::seal(
+                &mut sealed_data,
+                &H256::zero(),
+                &H256::zero(),
+                task.seal_index * SECTORS_PER_SEAL as u64,
+            );
+        }
+        SealAnswer {
+            seal_index: task.seal_index,
+            version: task.version,
+            sealed_data,
+            miner_id: H256::zero(),
+            seal_context: H256::zero(),
+            context_end_seal: task.seal_index + 1,
+        }
+    };
+
+    let mut wrong_answers: Vec<SealAnswer> = tasks.iter().map(|task| answer(task, true)).collect();
+    wrong_answers.last_mut().unwrap().sealed_data = tasks.last().unwrap().non_sealed_data;
+    let err = flow_store.submit_seal_result(wrong_answers).unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::SealVerificationFailed { seal_index, .. })
+            if *seal_index == tasks.last().unwrap().seal_index
+    ));
+    let mine_chunk = flow_store.load_sealed_data(1).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| !*x));
+
+    let answers = tasks.iter().map(|task| answer(task, true)).collect();
+    flow_store.submit_seal_result(answers).unwrap();
+    let mine_chunk = flow_store.load_sealed_data(1).unwrap().unwrap();
+    assert!(mine_chunk.availabilities.iter().all(|x| *x));
+}
+
+#[test]
+fn test_proof_cache() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            proof_cache_capacity: 16,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let sector_index = PORA_CHUNK_SIZE / 2;
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let proof = flow_store.gen_proof_in_batch(1, sector_index).unwrap();
+    assert_eq!(
+        flow_store.gen_proof_in_batch(1, sector_index).unwrap(),
+        proof
+    );
+
+    // The cached proof is dropped once the batch is truncated and rewritten with other data.
+    let truncate_index = (PORA_CHUNK_SIZE + sector_index) as u64;
+    flow_store.truncate(truncate_index).unwrap();
+    assert!(flow_store.gen_proof_in_batch(1, sector_index).is_err());
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![2u8; BYTES_PER_LOAD / 2],
+            start_index: truncate_index,
+        })
+        .unwrap();
+    let new_proof = flow_store.gen_proof_in_batch(1, sector_index).unwrap();
+    assert_eq!(new_proof.position(), sector_index);
+    assert_ne!(new_proof.root(), proof.root());
+}
+
+#[test]
+fn test_append_entries_with_roots() {
+    let data = ChunkArray {
+        data: (0..BYTES_PER_LOAD).map(|i| (i % 251) as u8).collect(),
+        start_index: PORA_CHUNK_SIZE as u64,
+    };
+    let roots = create_store()
+        .flow_store()
+        .append_entries(data.clone())
+        .unwrap();
+    assert_eq!(roots.len(), 1);
+
+    let store = create_store();
+    let flow_store = store.flow_store();
+    let wrong_roots = vec![(1, H256::repeat_byte(1))];
+    let err = flow_store
+        .append_entries_with_roots(data.clone(), &wrong_roots)
+        .unwrap_err();
+    assert!(matches!(
+        err.downcast_ref::<Error>(),
+        Some(Error::BatchRootMismatch { batch_index: 1, .. })
+    ));
+    assert!(flow_store
+        .get_entries(data.start_index, data.start_index + 1)
+        .unwrap()
+        .is_none());
+    assert_eq!(
+        flow_store
+            .append_entries_with_roots(data.clone(), &roots)
+            .unwrap(),
+        roots
+    );
+
+    // Without the verification, the supplied root of a complete batch is trusted.
+    let config = LogConfig {
+        flow: FlowConfig {
+            verify_supplied_batch_roots: false,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    assert_eq!(
+        store
+            .flow_store()
+            .append_entries_with_roots(data, &wrong_roots)
+            .unwrap(),
+        wrong_roots
+    );
+}
+
+#[test]
+fn test_require_seal_aligned_appends() {
+    let config = LogConfig {
+        flow: FlowConfig {
+            require_seal_aligned_appends: true,
+            ..Default::default()
+        },
+    };
+    let store = LogManager::memorydb(config).unwrap();
+    let flow_store = store.flow_store();
+    let start_index = (PORA_CHUNK_SIZE + SECTORS_PER_SEAL / 2) as u64;
+    let err = flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; CHUNK_SIZE],
+            start_index,
+        })
+        .unwrap_err();
+    assert!(err.to_string().contains("not aligned"));
+    assert!(flow_store
+        .get_entries(start_index, start_index + 1)
+        .unwrap()
+        .is_none());
+
+    let start_index = (PORA_CHUNK_SIZE + SECTORS_PER_SEAL) as u64;
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; CHUNK_SIZE],
+            start_index,
+        })
+        .unwrap();
+    assert!(flow_store
+        .get_entries(start_index, start_index + 1)
+        .unwrap()
+        .is_some());
+}
+
+#[test]
+fn test_flow_root() {
+    let mut store = create_store();
+    put_tx(&mut store, 3, 0);
+    assert_eq!(
+        store.flow_store().flow_root().unwrap(),
+        Some(store.get_context().unwrap().0)
+    );
+    put_tx(&mut store, 2 * PORA_CHUNK_SIZE + 5, 1);
+    assert_eq!(
+        store.flow_store().flow_root().unwrap(),
+        Some(store.get_context().unwrap().0)
+    );
+}
+
+#[test]
+fn test_submit_stale_seal_answers() {
+    let store = LogManager::memorydb(LogConfig::default()).unwrap();
+    let flow_store = store.flow_store();
+    // Mark the seal worker as available so the appended seals are queued.
+    flow_store.pull_seal_chunk(usize::MAX).unwrap();
+    flow_store
+        .append_entries(ChunkArray {
+            data: vec![1u8; BYTES_PER_LOAD],
+            start_index: PORA_CHUNK_SIZE as u64,
+        })
+        .unwrap();
+    let tasks = flow_store.pull_seal_chunk(usize::MAX).unwrap().unwrap();
+    assert_eq!(tasks.len(), SEALS_PER_LOAD);
+    // The odd answers carry another version, like the tasks pulled before a reorg. They are
+    // submitted in reverse order to check that the order does not matter.
+    let answers: Vec<SealAnswer> = tasks
+        .into_iter()
+        .rev()
+        .map(|task| SealAnswer {
+            seal_index: task.seal_index,
+            version: task.version + task.seal_index % 2,
+            sealed_data: task.non_sealed_data,
+            miner_id: H256::zero(),
+            seal_context: H256::zero(),
+            context_end_seal: task.seal_index + 1,
+        })
+        .collect();
+    let stale = flow_store.submit_seal_result(answers).unwrap();
+    assert_eq!(stale.len(), SEALS_PER_LOAD / 2);
+    assert!(stale
+        .windows(2)
+        .all(|pair| pair[0].seal_index < pair[1].seal_index));
+    assert!(stale.iter().all(|answer| answer.seal_index % 2 == 1));
+
+    let mine_chunk = flow_store.load_sealed_data(1).unwrap().unwrap();
+    for (seal_index, sealed) in mine_chunk.availabilities.iter().enumerate() {
+        assert_eq!(*sealed, seal_index % 2 == 0);
+    }
+}
+
 fn create_store() -> LogManager {
     let config = LogConfig::default();
     LogManager::memorydb(config).unwrap()
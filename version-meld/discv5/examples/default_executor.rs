@@ -67,6 +67,11 @@ async fn main() {
             }
             Some(Discv5Event::Discovered(enr)) => {
                 println!("A peer has been discovered: {}", enr.node_id());
+                let stats = discv5.discovery_stats();
+                println!(
+                    "Routing table: {} entries, {} ipv4, {} ipv6",
+                    stats.total_entries, stats.ipv4_reachable, stats.ipv6_reachable
+                );
             }
             _ => {}
         }
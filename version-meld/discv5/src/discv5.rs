@@ -25,6 +25,7 @@ use crate::{
 use enr::{CombinedKey, EnrError, EnrKey, NodeId};
 use parking_lot::RwLock;
 use std::{
+    collections::BTreeMap,
     future::Future,
     net::SocketAddr,
     sync::Arc,
@@ -67,6 +68,20 @@ pub enum Discv5Event {
     TalkRequest(TalkRequest),
 }
 
+/// A snapshot of the routing table, as returned by [`Discv5::discovery_stats`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscoveryStats {
+    /// The number of ENRs currently in the routing table.
+    pub total_entries: usize,
+    /// The number of entries per bucket, keyed by the log2 distance from the local node.
+    /// Empty buckets are omitted.
+    pub bucket_entries: BTreeMap<u64, usize>,
+    /// The number of entries that advertise an IPv4 UDP socket.
+    pub ipv4_reachable: usize,
+    /// The number of entries that advertise an IPv6 UDP socket.
+    pub ipv6_reachable: usize,
+}
+
 /// The main Discv5 Service struct. This provides the user-level API for performing queries and
 /// interacting with the underlying service.
 pub struct Discv5 {
@@ -268,6 +283,25 @@ impl Discv5 {
             .count()
     }
 
+    /// Returns statistics about the current state of the routing table.
+    pub fn discovery_stats(&self) -> DiscoveryStats {
+        let local_key = kbucket::Key::from(self.local_enr.read().node_id());
+        let mut stats = DiscoveryStats::default();
+        for entry in self.kbuckets.read().iter_ref() {
+            stats.total_entries += 1;
+            if let Some(distance) = entry.node.key.log2_distance(&local_key) {
+                *stats.bucket_entries.entry(distance).or_default() += 1;
+            }
+            if entry.node.value.udp4_socket().is_some() {
+                stats.ipv4_reachable += 1;
+            }
+            if entry.node.value.udp6_socket().is_some() {
+                stats.ipv6_reachable += 1;
+            }
+        }
+        stats
+    }
+
     /// Gets the metrics associated with the Server
     pub fn metrics(&self) -> Metrics {
         Metrics::from(&METRICS)
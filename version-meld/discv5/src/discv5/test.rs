@@ -480,3 +480,37 @@ async fn test_bucket_limits() {
     // Number of entries should be equal to `bucket_limit`.
     assert_eq!(discv5.kbuckets.read().iter_ref().count(), bucket_limit);
 }
+
+#[tokio::test]
+async fn test_discovery_stats() {
+    let enr_key = CombinedKey::generate_secp256k1();
+    let ip: IpAddr = "127.0.0.1".parse().unwrap();
+    let enr = EnrBuilder::new("v4")
+        .ip(ip)
+        .udp(9600)
+        .build(&enr_key)
+        .unwrap();
+    let local_key: kbucket::Key<NodeId> = enr.node_id().into();
+    let config = Discv5ConfigBuilder::new().build();
+    let discv5 = Discv5::new(enr, enr_key, config).unwrap();
+    assert_eq!(discv5.discovery_stats(), DiscoveryStats::default());
+
+    let mut expected_buckets = std::collections::BTreeMap::new();
+    for i in 1..=4u8 {
+        let key = CombinedKey::generate_secp256k1();
+        let enr = EnrBuilder::new("v4")
+            .ip(IpAddr::V4(Ipv4Addr::new(192, 168, i, 1)))
+            .udp(9600 + i as u16)
+            .build(&key)
+            .unwrap();
+        let distance = local_key.log2_distance(&enr.node_id().into()).unwrap();
+        *expected_buckets.entry(distance).or_insert(0) += 1;
+        discv5.add_enr(enr).unwrap();
+    }
+
+    let stats = discv5.discovery_stats();
+    assert_eq!(stats.total_entries, 4);
+    assert_eq!(stats.bucket_entries, expected_buckets);
+    assert_eq!(stats.ipv4_reachable, 4);
+    assert_eq!(stats.ipv6_reachable, 0);
+}